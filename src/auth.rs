@@ -23,9 +23,25 @@
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// AUTH response, the identity nsqd's auth server associated to the secret.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct Authentication {
     identity: String,
     identity_url: Option<String>,
     permission_count: i32,
 }
+
+impl Authentication {
+    pub fn identity(&self) -> &str {
+        &self.identity
+    }
+
+    pub fn identity_url(&self) -> Option<&str> {
+        self.identity_url.as_deref()
+    }
+
+    /// Number of topic/channel permissions granted.
+    pub fn permission_count(&self) -> i32 {
+        self.permission_count
+    }
+}
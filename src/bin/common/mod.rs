@@ -0,0 +1,498 @@
+// MIT License
+//
+// Copyright (c) 2019-2021 Alessandro Cresto Miseroglio <alex179ohm@gmail.com>
+// Copyright (c) 2019-2021 Tangram Technologies S.R.L. <https://tngrm.io>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+
+//! Command line parsing and connection setup shared by the nsq-* tools.
+//!
+//! Every tool compiles its own copy of this module, items not used by all of them
+//! allow `dead_code`.
+
+use futures::stream::{select_all, SelectAll};
+use nsq_rust::{rt, Channel, Client, Config, LookupdClient, MessageStream, NsqError, NsqResult, Topic};
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(unix)]
+use std::sync::Once;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Options to connect to nsqd, accepted by every tool.
+pub const CONNECTION_USAGE: &str = "\
+connection options:
+  --nsqd-tcp-address ADDR      nsqd TCP address (may be given multiple times)
+  --lookupd-http-address ADDR  nsqlookupd HTTP address (may be given multiple times)
+  --config FILE                read the client configuration from FILE (json, toml or yaml)
+  --no-tls                     don't upgrade the connection to TLS
+  --tls-root-ca-file FILE      PEM certificates trusted to verify nsqd
+  --tls-cert FILE              PEM client certificate
+  --tls-key FILE               PEM client private key
+  --auth-secret SECRET         secret sent with AUTH
+  -h, --help                   print this help";
+
+const CONNECTION_OPTIONS: &[&str] = &[
+    "nsqd-tcp-address",
+    "lookupd-http-address",
+    "config",
+    "tls-root-ca-file",
+    "tls-cert",
+    "tls-key",
+    "auth-secret",
+];
+
+const CONNECTION_SWITCHES: &[&str] = &["no-tls"];
+
+/// Command line flags: `--name value`, `--name=value` and switches without value.
+///
+/// The connection options are always accepted.
+pub struct Flags {
+    usage: &'static str,
+    options: Vec<&'static str>,
+    switches: Vec<&'static str>,
+    positional: bool,
+    values: HashMap<String, Vec<String>>,
+    args: Vec<String>,
+}
+
+impl Flags {
+    pub fn new(usage: &'static str) -> Self {
+        Flags {
+            usage,
+            options: CONNECTION_OPTIONS.to_vec(),
+            switches: CONNECTION_SWITCHES.to_vec(),
+            positional: false,
+            values: HashMap::new(),
+            args: Vec::new(),
+        }
+    }
+
+    /// Flags followed by a value.
+    pub fn options(mut self, options: &[&'static str]) -> Self {
+        self.options.extend_from_slice(options);
+        self
+    }
+
+    #[allow(dead_code)]
+    /// Flags without value.
+    pub fn switches(mut self, switches: &[&'static str]) -> Self {
+        self.switches.extend_from_slice(switches);
+        self
+    }
+
+    #[allow(dead_code)]
+    /// Accept arguments that are not flags, see [args](#method.args).
+    pub fn positional(mut self) -> Self {
+        self.positional = true;
+        self
+    }
+
+    /// Parse the command line, exit printing the usage on error or with `--help`.
+    pub fn parse(self) -> Self {
+        let args = env::args().skip(1).collect();
+        self.parse_from(args)
+    }
+
+    fn parse_from(mut self, args: Vec<String>) -> Self {
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg == "-h" || arg == "--help" {
+                let _ = writeln!(io::stdout(), "{}\n\n{}", self.usage, CONNECTION_USAGE);
+                process::exit(0);
+            }
+            let flag = match arg.strip_prefix("--").or_else(|| arg.strip_prefix('-')) {
+                Some(flag) if !flag.is_empty() => flag,
+                _ if self.positional => {
+                    self.args.push(arg);
+                    continue;
+                }
+                _ => self.usage_error(format!("unexpected argument {}", arg)),
+            };
+            let (name, value) = match flag.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_owned())),
+                None => (flag, None),
+            };
+            let value = if self.switches.contains(&name) {
+                value.unwrap_or_else(|| "true".to_owned())
+            } else if self.options.contains(&name) {
+                match value.or_else(|| args.next()) {
+                    Some(value) => value,
+                    None => self.usage_error(format!("missing value for --{}", name)),
+                }
+            } else {
+                self.usage_error(format!("unknown flag --{}", name))
+            };
+            self.values.entry(name.to_owned()).or_default().push(value);
+        }
+        self
+    }
+
+    fn usage_error(&self, msg: String) -> ! {
+        eprintln!("{}\n\n{}", self.usage, CONNECTION_USAGE);
+        fatal(msg)
+    }
+
+    #[allow(dead_code)]
+    /// Arguments that are not flags.
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    /// Last value of `name`.
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.values.get(name).and_then(|v| v.last()).map(String::as_str)
+    }
+
+    #[allow(dead_code)]
+    /// Every value of `name`, as given.
+    pub fn raw_values(&self, name: &str) -> &[String] {
+        self.values.get(name).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Every value of `name`, comma separated lists are split.
+    pub fn values(&self, name: &str) -> Vec<String> {
+        self.values
+            .get(name)
+            .into_iter()
+            .flatten()
+            .flat_map(|v| v.split(','))
+            .filter(|v| !v.is_empty())
+            .map(str::to_owned)
+            .collect()
+    }
+
+    pub fn is_set(&self, name: &str) -> bool {
+        self.value(name).is_some_and(|v| v != "false")
+    }
+
+    #[allow(dead_code)]
+    /// Value of `name`, exit if it is missing.
+    pub fn required(&self, name: &str) -> &str {
+        match self.value(name) {
+            Some(value) => value,
+            None => self.usage_error(format!("--{} is required", name)),
+        }
+    }
+
+    /// Value of `name` parsed, `default` if missing, exit if invalid.
+    pub fn parse_or<T: FromStr>(&self, name: &str, default: T) -> T
+    where
+        T::Err: Display,
+    {
+        match self.value(name) {
+            Some(value) => value
+                .parse()
+                .unwrap_or_else(|e| fatal(format!("invalid --{} {}: {}", name, value, e))),
+            None => default,
+        }
+    }
+
+    #[allow(dead_code)]
+    /// Duration of `name` (`1500ms`, `30s`, `5m`, `2h`), `default` if missing, exit if invalid.
+    pub fn duration_or(&self, name: &str, default: Duration) -> Duration {
+        match self.value(name) {
+            Some(value) => parse_duration(value)
+                .unwrap_or_else(|| fatal(format!("invalid --{} {}: expected a duration like 500ms, 30s, 5m, 2h", name, value))),
+            None => default,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn topic(&self) -> Topic {
+        let topic = self.required("topic");
+        Topic::new(topic).unwrap_or_else(|e| fatal(e))
+    }
+}
+
+#[allow(dead_code)]
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (n, unit) = s.split_at(split);
+    let n: u64 = n.parse().ok()?;
+    match unit {
+        "ms" => Some(Duration::from_millis(n)),
+        "s" | "" => Some(Duration::from_secs(n)),
+        "m" => Some(Duration::from_secs(n * 60)),
+        "h" => Some(Duration::from_secs(n * 3600)),
+        _ => None,
+    }
+}
+
+/// Print `msg` on stderr and exit with status 1.
+pub fn fatal<D: Display>(msg: D) -> ! {
+    let program = env::args().next().unwrap_or_default();
+    let program = Path::new(&program).file_name().map(|p| p.to_string_lossy().into_owned()).unwrap_or(program);
+    eprintln!("{}: {}", program, msg);
+    process::exit(1)
+}
+
+/// Client configuration from `--config` and the connection flags, `tool` is the user agent.
+pub fn config(flags: &Flags, tool: &str) -> Config {
+    let mut config = match flags.value("config") {
+        Some(path) => Config::from_file(path).unwrap_or_else(|e| fatal(e)),
+        None => Config::new(),
+    };
+    config = config.user_agent(format!("{}/{}", tool, env!("CARGO_PKG_VERSION")));
+    let mut nsqd = config.nsqd_tcp_addresses.clone();
+    nsqd.extend(flags.values("nsqd-tcp-address"));
+    let mut lookupd = config.lookupd_http_addresses.clone();
+    lookupd.extend(flags.values("lookupd-http-address"));
+    config = config.nsqd_tcp_addresses(nsqd).lookupd_http_addresses(lookupd);
+    if flags.is_set("no-tls") {
+        config = config.tls_v1(false);
+    }
+    if let Some(cafile) = flags.value("tls-root-ca-file") {
+        config = config.tls_root_ca_file(cafile);
+    }
+    match (flags.value("tls-cert"), flags.value("tls-key")) {
+        (Some(cert), Some(key)) => config = config.tls_client_cert(cert, key),
+        (None, None) => {}
+        _ => fatal("--tls-cert and --tls-key must be given together"),
+    }
+    if let Some(secret) = flags.value("auth-secret") {
+        config = config.auth_secret(secret);
+    }
+    config
+}
+
+#[allow(dead_code)]
+/// `--nsqd-tcp-address`es and the nsqd producing `topic` registered in the `--lookupd-http-address`es.
+pub async fn nsqd_addresses(config: &Config, topic: &Topic) -> NsqResult<Vec<String>> {
+    let mut addrs = config.nsqd_tcp_addresses.clone();
+    for lookupd in &config.lookupd_http_addresses {
+        for producer in LookupdClient::new(lookupd.as_str()).lookup(topic).await?.producers {
+            addrs.push(producer.tcp_address());
+        }
+    }
+    addrs.sort();
+    addrs.dedup();
+    if addrs.is_empty() {
+        return Err(NsqError::Unknown(format!("no nsqd found for topic {}", topic)));
+    }
+    Ok(addrs)
+}
+
+#[allow(dead_code)]
+/// Subscribe to `topic` on every nsqd, messages of all the connections are merged.
+pub async fn subscribe(config: &Config, topic: &Topic, channel: &Channel) -> NsqResult<SelectAll<MessageStream>> {
+    let mut streams = Vec::new();
+    for addr in nsqd_addresses(config, topic).await? {
+        let client = Client::new(addr, config.clone(), None, None);
+        streams.push(client.subscribe(topic.clone(), channel.clone()).await?);
+    }
+    Ok(select_all(streams))
+}
+
+#[allow(dead_code)]
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+#[allow(dead_code)]
+/// Resolves once SIGINT or SIGTERM is received.
+pub async fn shutdown() {
+    #[cfg(unix)]
+    {
+        static REGISTER: Once = Once::new();
+        REGISTER.call_once(register_signals);
+    }
+    while !SHUTDOWN.load(Ordering::SeqCst) {
+        rt::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+#[allow(dead_code)]
+#[cfg(unix)]
+fn register_signals() {
+    const SIGINT: i32 = 2;
+    const SIGTERM: i32 = 15;
+    for signal in [SIGINT, SIGTERM] {
+        // storing to an atomic is async signal safe
+        let registered = unsafe { signal_hook_registry::register(signal, || SHUTDOWN.store(true, Ordering::SeqCst)) };
+        if let Err(e) = registered {
+            fatal(format!("failed to register signal handler: {}", e));
+        }
+    }
+}
+
+#[allow(dead_code)]
+/// Broken down UTC time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Utc {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    pub nanos: u32,
+}
+
+#[allow(dead_code)]
+impl Utc {
+    pub fn now() -> Self {
+        let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        Utc::from_nanos(since_epoch.as_nanos() as i64)
+    }
+
+    /// Time from nanoseconds since the epoch, like the message timestamps.
+    pub fn from_nanos(nanos: i64) -> Self {
+        let secs = nanos.div_euclid(1_000_000_000);
+        let days = secs.div_euclid(86400);
+        let rem = secs.rem_euclid(86400);
+        // civil from days, http://howardhinnant.github.io/date_algorithms.html
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+        Utc {
+            year,
+            month,
+            day,
+            hour: (rem / 3600) as u32,
+            minute: (rem % 3600 / 60) as u32,
+            second: (rem % 60) as u32,
+            nanos: nanos.rem_euclid(1_000_000_000) as u32,
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl Utc {
+    /// Format with `%Y`, `%m`, `%d`, `%H`, `%M`, `%S` and `%%`.
+    pub fn format(&self, format: &str) -> String {
+        let mut out = String::new();
+        let mut chars = format.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                out.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('Y') => out.push_str(&format!("{:04}", self.year)),
+                Some('m') => out.push_str(&format!("{:02}", self.month)),
+                Some('d') => out.push_str(&format!("{:02}", self.day)),
+                Some('H') => out.push_str(&format!("{:02}", self.hour)),
+                Some('M') => out.push_str(&format!("{:02}", self.minute)),
+                Some('S') => out.push_str(&format!("{:02}", self.second)),
+                Some('%') => out.push('%'),
+                Some(c) => {
+                    out.push('%');
+                    out.push(c);
+                }
+                None => out.push('%'),
+            }
+        }
+        out
+    }
+}
+
+impl std::fmt::Display for Utc {
+    /// RFC 3339 with milliseconds.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+            self.year,
+            self.month,
+            self.day,
+            self.hour,
+            self.minute,
+            self.second,
+            self.nanos / 1_000_000
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Flags {
+        let args = args.iter().map(|a| a.to_string()).collect();
+        Flags::new("usage").options(&["topic", "header"]).switches(&["gzip"]).positional().parse_from(args)
+    }
+
+    #[test]
+    fn flags() {
+        let flags = parse(&[
+            "--topic",
+            "test",
+            "--nsqd-tcp-address=nsqd-1:4150,nsqd-2:4150",
+            "-nsqd-tcp-address",
+            "nsqd-3:4150",
+            "--header",
+            "X-A: 1,2",
+            "--gzip",
+            "--no-tls=false",
+            "input.txt",
+            "--topic=other",
+        ]);
+        assert_eq!(flags.value("topic"), Some("other"));
+        assert_eq!(flags.values("nsqd-tcp-address"), vec!["nsqd-1:4150", "nsqd-2:4150", "nsqd-3:4150"]);
+        assert_eq!(flags.raw_values("header"), ["X-A: 1,2"]);
+        assert!(flags.raw_values("auth-secret").is_empty());
+        assert!(flags.is_set("gzip"));
+        assert!(!flags.is_set("no-tls"));
+        assert!(!flags.is_set("config"));
+        assert_eq!(flags.args(), ["input.txt"]);
+        assert_eq!(flags.parse_or("missing", 7u32), 7);
+        assert_eq!(flags.duration_or("missing", Duration::from_secs(3)), Duration::from_secs(3));
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("1500ms"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("30s"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_duration("30"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_duration("5m"), Some(Duration::from_secs(300)));
+        assert_eq!(parse_duration("2h"), Some(Duration::from_secs(7200)));
+        for invalid in &["", "ms", "1.5s", "-1s", "10d", "1 s"] {
+            assert_eq!(parse_duration(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn utc_from_nanos() {
+        assert_eq!(Utc::from_nanos(0).to_string(), "1970-01-01T00:00:00.000Z");
+        assert_eq!(Utc::from_nanos(1_600_000_000_123_456_789).to_string(), "2020-09-13T12:26:40.123Z");
+        // leap day and the day after
+        assert_eq!(Utc::from_nanos(951_782_400 * 1_000_000_000).to_string(), "2000-02-29T00:00:00.000Z");
+        assert_eq!(Utc::from_nanos(951_868_800 * 1_000_000_000).to_string(), "2000-03-01T00:00:00.000Z");
+        assert_eq!(Utc::from_nanos(-1).to_string(), "1969-12-31T23:59:59.999Z");
+        assert_eq!(Utc::from_nanos(-1).nanos, 999_999_999);
+    }
+
+    #[test]
+    fn utc_format() {
+        let utc = Utc::from_nanos(1_600_000_000_123_456_789);
+        assert_eq!(utc.format("%Y%m%d-%H%M%S"), "20200913-122640");
+        assert_eq!(utc.format("100%% %x %"), "100% %x %");
+    }
+}
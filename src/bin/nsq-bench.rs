@@ -0,0 +1,296 @@
+// MIT License
+//
+// Copyright (c) 2019-2021 Alessandro Cresto Miseroglio <alex179ohm@gmail.com>
+// Copyright (c) 2019-2021 Tangram Technologies S.R.L. <https://tngrm.io>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Measure the throughput and latency of publishing and consuming.
+
+mod common;
+
+use common::{fatal, Flags};
+use futures::future::{self, join_all};
+use futures::stream::{self, select_all};
+use futures::{SinkExt, StreamExt};
+use nsq_rust::metrics::MetricsSink;
+use nsq_rust::{rt, Bytes, Channel, Client, Config, MessageStream, Mpub, NsqError, NsqResult, Producer, Topic};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const USAGE: &str = "\
+usage: nsq-bench --nsqd-tcp-address ADDR [options]
+
+Publish and consume messages as fast as possible, then print the throughput and
+the latency percentiles of every workload.
+
+Compression is not benchmarked: the client doesn't support deflate nor snappy yet.
+
+options:
+  --mode MODE             pub, sub or both, publishing and consuming at the same
+                          time (default: both)
+  --topic TOPIC           topic of the benchmark (default: nsq_bench)
+  --channel CHANNEL       channel to consume (default: nsq_bench)
+  --messages N            messages published and consumed (default: 100000)
+  --size BYTES            size of the messages published (default: 200)
+  --connections N         connections to every nsqd, for each workload (default: 1)
+  --batch N               messages published with every MPUB (default: 1, PUB)
+  --depth N               PUBs, or MPUBs with --batch, sent on every connection
+                          before waiting for nsqd's responses (default: 1)
+  --max-in-flight N       messages in flight on every consumer connection (default: 2500)
+  --mock                  run against an in-process nsqd stand-in instead of
+                          --nsqd-tcp-address (requires the testing feature)";
+
+fn main() {
+    let flags = Flags::new(USAGE)
+        .options(&["mode", "topic", "channel", "messages", "size", "connections", "batch", "depth", "max-in-flight"])
+        .switches(&["mock"])
+        .parse();
+    if let Err(e) = rt::block_on(bench(flags)) {
+        fatal(e);
+    }
+}
+
+async fn bench(flags: Flags) -> NsqResult<()> {
+    let (publish, consume) = match flags.value("mode").unwrap_or("both") {
+        "pub" => (true, false),
+        "sub" => (false, true),
+        "both" => (true, true),
+        m => fatal(format!("invalid --mode {}: expected pub, sub or both", m)),
+    };
+    let bench = Bench {
+        topic: Topic::new(flags.value("topic").unwrap_or("nsq_bench"))?,
+        channel: Channel::new(flags.value("channel").unwrap_or("nsq_bench"))?,
+        messages: flags.parse_or("messages", 100_000),
+        size: flags.parse_or::<usize>("size", 200).max(1),
+        connections: flags.parse_or::<usize>("connections", 1).max(1),
+        batch: flags.parse_or::<usize>("batch", 1).max(1),
+        depth: flags.parse_or::<usize>("depth", 1).max(1),
+    };
+    let config = common::config(&flags, "nsq-bench")
+        .max_in_flight(flags.parse_or("max-in-flight", 2500));
+
+    // dropped at the end of the benchmark
+    #[cfg(feature = "testing")]
+    let (config, _mock) = if flags.is_set("mock") {
+        let nsqd = nsq_rust::testing::MockNsqd::start().await?;
+        (config.nsqd_tcp_addresses(vec![nsqd.addr()]), Some(nsqd))
+    } else {
+        (config, None)
+    };
+    #[cfg(not(feature = "testing"))]
+    if flags.is_set("mock") {
+        fatal("--mock requires nsq-bench built with the testing feature");
+    }
+    let config = config.build()?;
+
+    println!(
+        "nsq-bench: {} messages of {} bytes, {} connection(s) per nsqd, batch {}, depth {}",
+        bench.messages, bench.size, bench.connections, bench.batch, bench.depth
+    );
+    let producers = if publish {
+        if config.nsqd_tcp_addresses.is_empty() {
+            fatal("--nsqd-tcp-address is required");
+        }
+        bench.producers(&config, &config.nsqd_tcp_addresses).await?
+    } else {
+        Vec::new()
+    };
+    let consumers = if consume {
+        let addrs = common::nsqd_addresses(&config, &bench.topic).await?;
+        bench.consumers(&config, &addrs).await?
+    } else {
+        Vec::new()
+    };
+
+    let publishing = async {
+        if publish {
+            bench.publish(producers).await.map(Some)
+        } else {
+            Ok(None)
+        }
+    };
+    let consuming = async {
+        if consume {
+            bench.consume(consumers).await.map(Some)
+        } else {
+            Ok(None)
+        }
+    };
+    let (published, consumed) = future::join(publishing, consuming).await;
+    if let Some(report) = published? {
+        report.print("pub", if bench.batch > 1 { "per MPUB" } else { "per PUB" });
+    }
+    if let Some(report) = consumed? {
+        report.print("sub", "end to end");
+    }
+    Ok(())
+}
+
+struct Bench {
+    topic: Topic,
+    channel: Channel,
+    messages: usize,
+    size: usize,
+    /// For every nsqd.
+    connections: usize,
+    batch: usize,
+    depth: usize,
+}
+
+impl Bench {
+    async fn producers(&self, config: &Config, addrs: &[String]) -> NsqResult<Vec<(Producer, Latencies)>> {
+        let mut producers = Vec::new();
+        for i in 0..self.connections * addrs.len() {
+            let latencies = Latencies::default();
+            let client = Client::new(addrs[i % addrs.len()].as_str(), config.clone(), None, None).metrics(latencies.clone());
+            producers.push((client.producer().await?.max_unacknowledged(self.depth), latencies));
+        }
+        Ok(producers)
+    }
+
+    async fn consumers(&self, config: &Config, addrs: &[String]) -> NsqResult<Vec<MessageStream>> {
+        let mut streams = Vec::new();
+        for i in 0..self.connections * addrs.len() {
+            let client = Client::new(addrs[i % addrs.len()].as_str(), config.clone(), None, None);
+            streams.push(client.subscribe(self.topic.clone(), self.channel.clone()).await?);
+        }
+        Ok(streams)
+    }
+
+    /// Publish the messages spread over the producers.
+    async fn publish(&self, producers: Vec<(Producer, Latencies)>) -> NsqResult<Report> {
+        let body = Bytes::from(vec![b'n'; self.size]);
+        let count = producers.len();
+        let start = Instant::now();
+        let workers = producers.into_iter().enumerate().map(|(i, (producer, latencies))| {
+            let share = self.messages / count + usize::from(i < self.messages % count);
+            let body = body.clone();
+            async move {
+                self.publish_share(producer, body, share).await?;
+                Ok::<_, NsqError>(latencies)
+            }
+        });
+        let mut latencies = Vec::new();
+        for res in join_all(workers).await {
+            latencies.extend(res?.take());
+        }
+        Ok(Report::new(start.elapsed(), self.messages, self.messages * self.size, latencies))
+    }
+
+    async fn publish_share(&self, mut producer: Producer, body: Bytes, count: usize) -> NsqResult<()> {
+        // pipelined up to --depth by the Sinks
+        if self.batch == 1 {
+            let mut msgs = stream::iter((0..count).map(|_| Ok((self.topic.clone(), body.clone()))));
+            return producer.send_all(&mut msgs).await;
+        }
+        let mut batches = stream::iter(
+            (0..count)
+                .step_by(self.batch)
+                .map(|sent| Mpub::new(self.topic.clone(), vec![body.to_vec(); self.batch.min(count - sent)])),
+        );
+        producer.batches().send_all(&mut batches).await
+    }
+
+    /// Receive and finish the messages, the latency is the time since nsqd received them.
+    async fn consume(&self, streams: Vec<MessageStream>) -> NsqResult<Report> {
+        let mut messages = select_all(streams);
+        let mut latencies = Vec::with_capacity(self.messages);
+        let mut bytes = 0;
+        let start = Instant::now();
+        while latencies.len() < self.messages {
+            let msg = match messages.next().await {
+                Some(msg) => msg?,
+                None => break,
+            };
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as i64;
+            latencies.push(Duration::from_nanos(now.saturating_sub(msg.timestamp()).max(0) as u64));
+            bytes += msg.body().len();
+            msg.finish();
+        }
+        let elapsed = start.elapsed();
+        for stream in messages {
+            stream.close().await;
+        }
+        Ok(Report::new(elapsed, latencies.len(), bytes, latencies))
+    }
+}
+
+/// Records the publish latencies of a producer.
+#[derive(Clone, Default)]
+struct Latencies(Arc<Mutex<Vec<Duration>>>);
+
+impl Latencies {
+    fn take(&self) -> Vec<Duration> {
+        std::mem::take(&mut *self.0.lock().expect("latencies poisoned"))
+    }
+}
+
+impl MetricsSink for Latencies {
+    fn publish_latency(&self, _addr: &str, latency: Duration) {
+        self.0.lock().expect("latencies poisoned").push(latency);
+    }
+}
+
+struct Report {
+    elapsed: Duration,
+    messages: usize,
+    bytes: usize,
+    /// Sorted.
+    latencies: Vec<Duration>,
+}
+
+impl Report {
+    fn new(elapsed: Duration, messages: usize, bytes: usize, mut latencies: Vec<Duration>) -> Self {
+        latencies.sort_unstable();
+        Report { elapsed, messages, bytes, latencies }
+    }
+
+    fn percentile(&self, p: f64) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::from_secs(0);
+        }
+        let i = ((self.latencies.len() - 1) as f64 * p / 100.0).round() as usize;
+        self.latencies[i]
+    }
+
+    fn print(&self, workload: &str, latency: &str) {
+        let secs = self.elapsed.as_secs_f64().max(f64::EPSILON);
+        println!(
+            "{}: {} msgs in {:.3}s, {:.1} msgs/sec, {:.2} MB/sec",
+            workload,
+            self.messages,
+            secs,
+            self.messages as f64 / secs,
+            self.bytes as f64 / secs / (1024.0 * 1024.0)
+        );
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        println!(
+            "{:width$}  latency {}: p50 {:.3}ms, p90 {:.3}ms, p99 {:.3}ms, p99.9 {:.3}ms, max {:.3}ms",
+            "",
+            latency,
+            ms(self.percentile(50.0)),
+            ms(self.percentile(90.0)),
+            ms(self.percentile(99.0)),
+            ms(self.percentile(99.9)),
+            ms(self.percentile(100.0)),
+            width = workload.len()
+        );
+    }
+}
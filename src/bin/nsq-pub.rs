@@ -0,0 +1,310 @@
+// MIT License
+//
+// Copyright (c) 2019-2021 Alessandro Cresto Miseroglio <alex179ohm@gmail.com>
+// Copyright (c) 2019-2021 Tangram Technologies S.R.L. <https://tngrm.io>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+
+//! Publish records read from stdin or files.
+
+mod common;
+
+use common::{fatal, Flags};
+use futures::channel::mpsc::{self, Sender};
+use futures::{executor, SinkExt, StreamExt};
+use nsq_rust::retry::ExponentialBackoff;
+use nsq_rust::{rt, Client, NsqError, NsqResult, Producer, Topic};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Reconnection attempts before a lost nsqd is reported and nsq-pub exits.
+const RECONNECT_ATTEMPTS: u32 = 3;
+
+const USAGE: &str = "\
+usage: nsq-pub --topic TOPIC --nsqd-tcp-address ADDR [options] [FILE...]
+
+Publish the records read from the FILEs (or stdin, also with -) to TOPIC.
+The exit status is 1 if any message failed to be published, nsq-pub stops
+if a connection to nsqd is lost and can't be established again.
+
+options:
+  --topic TOPIC           topic to publish to
+  --delimiter DELIM       how records are separated: line, nul or length, a 4 bytes
+                          big endian size before each record (default: line)
+  --batch N               publish N records with every MPUB (default: 1, PUB)
+  --delay DURATION        publish with DPUB, delivered after DURATION (like 500ms, 30s)
+  --rate N                publish at most N records per second (default: 0, unlimited)";
+
+fn main() {
+    let flags = Flags::new(USAGE)
+        .options(&["topic", "delimiter", "batch", "delay", "rate"])
+        .positional()
+        .parse();
+    match rt::block_on(publish(flags)) {
+        Ok(0) => {}
+        Ok(failed) => {
+            eprintln!("nsq-pub: {} message(s) failed", failed);
+            process::exit(1);
+        }
+        Err(e) => fatal(e),
+    }
+}
+
+/// Publish every record, returns the number of messages that failed.
+async fn publish(flags: Flags) -> NsqResult<usize> {
+    let topic = flags.topic();
+    let delimiter = match flags.value("delimiter").unwrap_or("line") {
+        "line" => Delimiter::Line,
+        "nul" => Delimiter::Nul,
+        "length" => Delimiter::Length,
+        d => fatal(format!("invalid --delimiter {}: expected line, nul or length", d)),
+    };
+    let batch: usize = flags.parse_or("batch", 1).max(1);
+    let delay = flags.value("delay").map(|_| flags.duration_or("delay", Duration::from_secs(0)));
+    if delay.is_some() && batch > 1 {
+        fatal("--delay can't be used with --batch");
+    }
+    let rate = Rate::new(flags.parse_or("rate", 0));
+    let config = common::config(&flags, "nsq-pub").build()?;
+    if config.nsqd_tcp_addresses.is_empty() {
+        fatal("--nsqd-tcp-address is required");
+    }
+
+    let mut producers = Vec::new();
+    for addr in &config.nsqd_tcp_addresses {
+        let backoff = ExponentialBackoff::new(
+            Duration::from_millis(config.backoff_multiplier),
+            Duration::from_millis(config.max_backoff_duration),
+        );
+        let client = Client::new(addr.as_str(), config.clone(), None, None)
+            .retry_policy(backoff.max_attempts(RECONNECT_ATTEMPTS));
+        producers.push(client.producer().await?);
+    }
+    let mut publisher = Publisher {
+        topic,
+        delay,
+        producers,
+        next: 0,
+        rate,
+        failed: 0,
+    };
+
+    let mut inputs: Vec<String> = flags.args().to_vec();
+    if inputs.is_empty() {
+        inputs.push("-".to_owned());
+    }
+    // blocking reads stay off the runtime threads
+    let (records_tx, mut records) = mpsc::channel(batch);
+    thread::spawn(move || read(inputs, delimiter, records_tx));
+    let mut pending = Vec::with_capacity(batch);
+    while let Some(record) = records.next().await {
+        pending.push(record);
+        if pending.len() == batch {
+            publisher.publish(pending.split_off(0)).await?;
+        }
+    }
+    if !pending.is_empty() {
+        publisher.publish(pending).await?;
+    }
+    Ok(publisher.failed)
+}
+
+/// Send the records of every input to `records`, until they are read or the receiver is dropped.
+fn read(inputs: Vec<String>, delimiter: Delimiter, mut records: Sender<Vec<u8>>) {
+    for input in inputs {
+        let reader: Box<dyn BufRead> = if input == "-" {
+            Box::new(BufReader::new(io::stdin()))
+        } else {
+            let file = File::open(&input).unwrap_or_else(|e| fatal(format!("{}: {}", input, e)));
+            Box::new(BufReader::new(file))
+        };
+        let mut reader = Records { reader, delimiter };
+        while let Some(record) = reader.next().unwrap_or_else(|e| fatal(format!("{}: {}", input, e))) {
+            // nsqd rejects empty messages
+            if record.is_empty() {
+                continue;
+            }
+            if executor::block_on(records.send(record)).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+struct Publisher {
+    topic: Topic,
+    delay: Option<Duration>,
+    /// Used round robin.
+    producers: Vec<Producer>,
+    next: usize,
+    rate: Rate,
+    failed: usize,
+}
+
+impl Publisher {
+    /// Publish `msgs`, errors returned by nsqd are counted and a lost connection is returned.
+    async fn publish(&mut self, mut msgs: Vec<Vec<u8>>) -> NsqResult<()> {
+        self.rate.wait(msgs.len()).await;
+        let count = msgs.len();
+        let next = self.next;
+        self.next = (next + 1) % self.producers.len();
+        let producer = &mut self.producers[next];
+        let res = match self.delay {
+            Some(delay) => producer.dpublish(&self.topic, delay, msgs.remove(0)).await,
+            None if count == 1 => producer.publish(&self.topic, msgs.remove(0)).await,
+            None => producer.mpublish(&self.topic, msgs).await,
+        };
+        match res {
+            Err(e @ NsqError::Io(_)) | Err(e @ NsqError::Disconnected) => Err(e),
+            Err(e) => {
+                eprintln!("nsq-pub: failed to publish {} message(s): {}", count, e);
+                self.failed += count;
+                Ok(())
+            }
+            Ok(_) => Ok(()),
+        }
+    }
+}
+
+/// Spaces the publishes out to send at most `per_second` records per second.
+struct Rate {
+    interval: Option<Duration>,
+    start: Instant,
+    sent: u32,
+}
+
+impl Rate {
+    fn new(per_second: u32) -> Self {
+        Rate {
+            interval: if per_second > 0 { Some(Duration::from_secs(1) / per_second) } else { None },
+            start: Instant::now(),
+            sent: 0,
+        }
+    }
+
+    async fn wait(&mut self, records: usize) {
+        if let Some(interval) = self.interval {
+            let due = self.start + interval * self.sent;
+            let now = Instant::now();
+            if due > now {
+                rt::sleep(due - now).await;
+            }
+            self.sent += records as u32;
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Delimiter {
+    Line,
+    Nul,
+    Length,
+}
+
+struct Records<R> {
+    reader: R,
+    delimiter: Delimiter,
+}
+
+impl<R: BufRead> Records<R> {
+    fn next(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut record = Vec::new();
+        match self.delimiter {
+            Delimiter::Line => {
+                if self.reader.read_until(b'\n', &mut record)? == 0 {
+                    return Ok(None);
+                }
+                if record.ends_with(b"\n") {
+                    record.pop();
+                    if record.ends_with(b"\r") {
+                        record.pop();
+                    }
+                }
+            }
+            Delimiter::Nul => {
+                if self.reader.read_until(0, &mut record)? == 0 {
+                    return Ok(None);
+                }
+                if record.ends_with(&[0]) {
+                    record.pop();
+                }
+            }
+            Delimiter::Length => {
+                // the input may only end between records
+                if self.reader.fill_buf()?.is_empty() {
+                    return Ok(None);
+                }
+                let mut size = [0u8; 4];
+                self.reader.read_exact(&mut size)?;
+                record.resize(u32::from_be_bytes(size) as usize, 0);
+                self.reader.read_exact(&mut record)?;
+            }
+        }
+        Ok(Some(record))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, ErrorKind};
+
+    fn records(delimiter: Delimiter, input: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+        let mut records = Records { reader: Cursor::new(input.to_vec()), delimiter };
+        let mut all = Vec::new();
+        while let Some(record) = records.next()? {
+            all.push(record);
+        }
+        Ok(all)
+    }
+
+    #[test]
+    fn lines() {
+        let all = records(Delimiter::Line, b"a\nb\r\n\nlast").unwrap();
+        assert_eq!(all, vec![b"a".to_vec(), b"b".to_vec(), b"".to_vec(), b"last".to_vec()]);
+        // a lone \r is part of the record
+        let all = records(Delimiter::Line, b"a\rb\n").unwrap();
+        assert_eq!(all, vec![b"a\rb".to_vec()]);
+        assert!(records(Delimiter::Line, b"").unwrap().is_empty());
+    }
+
+    #[test]
+    fn nul() {
+        let all = records(Delimiter::Nul, b"a\nb\0c\0d").unwrap();
+        assert_eq!(all, vec![b"a\nb".to_vec(), b"c".to_vec(), b"d".to_vec()]);
+    }
+
+    #[test]
+    fn length() {
+        let all = records(Delimiter::Length, b"\0\0\0\x02a\n\0\0\0\0\0\0\0\x01\0").unwrap();
+        assert_eq!(all, vec![b"a\n".to_vec(), b"".to_vec(), b"\0".to_vec()]);
+    }
+
+    #[test]
+    fn truncated_length() {
+        let err = records(Delimiter::Length, b"\0\0\0\x02a\n\0\0").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        let err = records(Delimiter::Length, b"\0\0\0\x05abc").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+}
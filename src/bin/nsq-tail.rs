@@ -0,0 +1,130 @@
+// MIT License
+//
+// Copyright (c) 2019-2021 Alessandro Cresto Miseroglio <alex179ohm@gmail.com>
+// Copyright (c) 2019-2021 Tangram Technologies S.R.L. <https://tngrm.io>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+
+//! Print the messages of a topic on stdout.
+
+mod common;
+
+use common::{fatal, Flags, Utc};
+use futures::StreamExt;
+use nsq_rust::{retry, rt, Channel, Message};
+use serde_json::json;
+use std::io::{self, Write};
+
+const USAGE: &str = "\
+usage: nsq-tail --topic TOPIC [options]
+
+Print the messages of TOPIC on stdout, one per line.
+
+options:
+  --topic TOPIC           topic to tail
+  --channel CHANNEL       channel to subscribe (default: random ephemeral channel)
+  -n, --n N               exit after N messages (default: 0, forever)
+  --max-in-flight N       messages in flight per nsqd (default: 200)
+  --metadata              print id, timestamp and attempts before the body
+  --json                  print every message as a JSON object";
+
+fn main() {
+    let flags = Flags::new(USAGE)
+        .options(&["topic", "channel", "n", "max-in-flight"])
+        .switches(&["metadata", "json"])
+        .parse();
+    if let Err(e) = rt::block_on(tail(flags)) {
+        fatal(e);
+    }
+}
+
+async fn tail(flags: Flags) -> nsq_rust::NsqResult<()> {
+    let topic = flags.topic();
+    let channel = match flags.value("channel") {
+        Some(channel) => Channel::new(channel)?,
+        None => Channel::new(format!("tail{:06}#ephemeral", retry::random() % 1_000_000))?,
+    };
+    let limit: usize = flags.parse_or("n", 0);
+    let max_in_flight = flags.parse_or("max-in-flight", 200);
+    let config = common::config(&flags, "nsq-tail").max_in_flight(max_in_flight).build()?;
+    let format = if flags.is_set("json") {
+        Format::Json
+    } else if flags.is_set("metadata") {
+        Format::Metadata
+    } else {
+        Format::Body
+    };
+
+    let mut messages = common::subscribe(&config, &topic, &channel).await?;
+    let stdout = io::stdout();
+    let mut received = 0;
+    while let Some(msg) = messages.next().await {
+        let msg = msg?;
+        let mut out = stdout.lock();
+        if let Err(e) = print(&mut out, &msg, format, topic.as_str()) {
+            // stdout closed, like piping to head
+            if e.kind() == io::ErrorKind::BrokenPipe {
+                return Ok(());
+            }
+            fatal(e);
+        }
+        msg.finish();
+        received += 1;
+        if received == limit {
+            break;
+        }
+    }
+    for stream in messages {
+        stream.close().await;
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy)]
+enum Format {
+    Body,
+    Metadata,
+    Json,
+}
+
+fn print<W: Write>(out: &mut W, msg: &Message, format: Format, topic: &str) -> io::Result<()> {
+    match format {
+        Format::Body => {
+            out.write_all(msg.body())?;
+            out.write_all(b"\n")?;
+        }
+        Format::Metadata => {
+            write!(out, "{} {} {} ", msg.id(), Utc::from_nanos(msg.timestamp()), msg.attempts())?;
+            out.write_all(msg.body())?;
+            out.write_all(b"\n")?;
+        }
+        Format::Json => {
+            let msg = json!({
+                "topic": topic,
+                "id": msg.id(),
+                "timestamp": msg.timestamp(),
+                "attempts": msg.attempts(),
+                "body": String::from_utf8_lossy(msg.body()),
+            });
+            writeln!(out, "{}", msg)?;
+        }
+    }
+    out.flush()
+}
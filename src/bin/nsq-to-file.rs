@@ -0,0 +1,499 @@
+// MIT License
+//
+// Copyright (c) 2019-2021 Alessandro Cresto Miseroglio <alex179ohm@gmail.com>
+// Copyright (c) 2019-2021 Tangram Technologies S.R.L. <https://tngrm.io>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+
+//! Archive topics to disk.
+//!
+//! Messages are written to time and size rotated files and finished only once the file
+//! has been synced to disk.
+
+mod common;
+
+use common::{fatal, Flags, Utc};
+use futures::stream::{select_all, StreamExt};
+use futures::{pin_mut, select, FutureExt};
+use nsq_rust::retry::{ExponentialBackoff, RetryPolicy};
+use nsq_rust::{rt, Channel, Message, NsqResult, Topic};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::process;
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "\
+usage: nsq-to-file --topic TOPIC [options]
+
+Write the messages of every TOPIC to files, one message per line. A message is
+finished only after its file has been synced to disk.
+
+options:
+  --topic TOPIC              topic to archive (may be given multiple times)
+  --channel CHANNEL          channel to subscribe (default: nsq_to_file)
+  --output-dir DIR           directory of the files (default: /tmp)
+  --filename-format FORMAT   file name template, with <TOPIC>, <HOST>, <PID>, <DATETIME>
+                             and <REV> (default: <TOPIC>.<HOST><REV>.<DATETIME>.log)
+  --datetime-format FORMAT   format of <DATETIME> in UTC, with %Y %m %d %H %M %S, a new
+                             file is started when it changes (default: %Y-%m-%d_%H)
+  --rotate-size BYTES        start a new file after BYTES uncompressed bytes (default: 0, never)
+  --rotate-interval DURATION start a new file after DURATION (default: 0, never)
+  --gzip                     compress the files, .gz is added to the name
+  --gzip-level N             compression level 1-9 (default: 6)
+  --sync-interval DURATION   sync the files and finish the messages at least every
+                             DURATION (default: 30s)
+  --max-in-flight N          messages in flight per nsqd, the files are also synced when
+                             N messages are waiting (default: 200)";
+
+fn main() {
+    let flags = Flags::new(USAGE)
+        .options(&[
+            "topic",
+            "channel",
+            "output-dir",
+            "filename-format",
+            "datetime-format",
+            "rotate-size",
+            "rotate-interval",
+            "gzip-level",
+            "sync-interval",
+            "max-in-flight",
+        ])
+        .switches(&["gzip"])
+        .parse();
+    if let Err(e) = rt::block_on(archive(flags)) {
+        fatal(e);
+    }
+}
+
+async fn archive(flags: Flags) -> NsqResult<()> {
+    let topics = flags
+        .values("topic")
+        .into_iter()
+        .map(Topic::new)
+        .collect::<NsqResult<Vec<_>>>()?;
+    if topics.is_empty() {
+        fatal("--topic is required");
+    }
+    let channel = Channel::new(flags.value("channel").unwrap_or("nsq_to_file"))?;
+    let max_in_flight: u32 = flags.parse_or("max-in-flight", 200);
+    let config = common::config(&flags, "nsq-to-file").max_in_flight(max_in_flight).build()?;
+    let gzip = if flags.is_set("gzip") {
+        match flags.parse_or("gzip-level", 6) {
+            level @ 1..=9 => Some(level),
+            level => fatal(format!("invalid --gzip-level {}: expected 1-9", level)),
+        }
+    } else {
+        None
+    };
+    if gzip.is_some() && !cfg!(feature = "gzip") {
+        fatal("--gzip requires building with the gzip feature");
+    }
+    let mut filename = flags
+        .value("filename-format")
+        .unwrap_or("<TOPIC>.<HOST><REV>.<DATETIME>.log")
+        .to_owned();
+    if gzip.is_some() {
+        filename.push_str(".gz");
+    }
+    let options = Options {
+        dir: PathBuf::from(flags.value("output-dir").unwrap_or("/tmp")),
+        filename,
+        datetime: flags.value("datetime-format").unwrap_or("%Y-%m-%d_%H").to_owned(),
+        host: hostname(),
+        rotate_size: flags.parse_or("rotate-size", 0),
+        rotate_interval: flags.duration_or("rotate-interval", Duration::from_secs(0)),
+        gzip,
+    };
+    fs::create_dir_all(&options.dir).unwrap_or_else(|e| fatal(format!("{}: {}", options.dir.display(), e)));
+
+    let mut subscriptions = Vec::new();
+    for (i, topic) in topics.iter().enumerate() {
+        let streams = common::subscribe(&config, topic, &channel).await?;
+        subscriptions.push(streams.map(move |msg| (i, msg)));
+    }
+    let mut messages = select_all(subscriptions);
+    let backoff = ExponentialBackoff::new(
+        Duration::from_millis(config.backoff_multiplier),
+        Duration::from_millis(config.max_backoff_duration),
+    );
+    let mut archiver = Archiver {
+        options,
+        outputs: topics.into_iter().map(|topic| Output { topic, file: None }).collect(),
+        pending: Vec::new(),
+        max_pending: max_in_flight as usize,
+        sync_interval: flags.duration_or("sync-interval", Duration::from_secs(30)),
+        last_sync: Instant::now(),
+        backoff,
+        failures: 0,
+    };
+
+    let shutdown = common::shutdown().fuse();
+    pin_mut!(shutdown);
+    let mut error = None;
+    loop {
+        let sync = rt::sleep(archiver.until_sync()).fuse();
+        pin_mut!(sync);
+        select! {
+            msg = messages.next() => match msg {
+                Some((i, Ok(msg))) => {
+                    if let Err(e) = archiver.write(i, msg) {
+                        archiver.failed(e).await;
+                    }
+                }
+                Some((_, Err(e))) => {
+                    error = Some(e);
+                    break;
+                }
+                None => break,
+            },
+            _ = sync => {
+                if let Err(e) = archiver.sync() {
+                    archiver.failed(e).await;
+                }
+            }
+            _ = shutdown => break,
+        }
+    }
+
+    // graceful shutdown: finish what is on disk, close the subscriptions and the files
+    let closed = archiver.close();
+    for subscription in messages {
+        for stream in subscription.into_inner() {
+            stream.close().await;
+        }
+    }
+    if let Err(e) = closed {
+        eprintln!("nsq-to-file: {}", e);
+        process::exit(1);
+    }
+    match error {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+struct Options {
+    dir: PathBuf,
+    filename: String,
+    datetime: String,
+    host: String,
+    rotate_size: u64,
+    rotate_interval: Duration,
+    gzip: Option<u32>,
+}
+
+impl Options {
+    /// File name of `topic` at `now`, `<REV>` is replaced when the file is created.
+    fn filename(&self, topic: &Topic, now: Utc) -> String {
+        self.filename
+            .replace("<TOPIC>", topic.as_str())
+            .replace("<HOST>", &self.host)
+            .replace("<PID>", &process::id().to_string())
+            .replace("<DATETIME>", &now.format(&self.datetime))
+    }
+}
+
+fn hostname() -> String {
+    hostname::get()
+        .ok()
+        .and_then(|h| h.into_string().ok())
+        .map(|h| h.split('.').next().unwrap_or_default().to_owned())
+        .unwrap_or_else(|| "localhost".to_owned())
+}
+
+struct Archiver {
+    options: Options,
+    /// One per topic.
+    outputs: Vec<Output>,
+    /// Written and not yet synced.
+    pending: Vec<Message>,
+    max_pending: usize,
+    sync_interval: Duration,
+    last_sync: Instant,
+    backoff: ExponentialBackoff,
+    failures: u32,
+}
+
+impl Archiver {
+    fn until_sync(&self) -> Duration {
+        (self.last_sync + self.sync_interval).saturating_duration_since(Instant::now())
+    }
+
+    fn write(&mut self, output: usize, msg: Message) -> io::Result<()> {
+        let name = self.options.filename(&self.outputs[output].topic, Utc::now());
+        let rotate = match &self.outputs[output].file {
+            Some(file) => {
+                file.name != name
+                    || (self.options.rotate_size > 0 && file.size >= self.options.rotate_size)
+                    || (self.options.rotate_interval > Duration::from_secs(0)
+                        && file.opened.elapsed() >= self.options.rotate_interval)
+            }
+            None => false,
+        };
+        // on error `msg` is dropped, so requeued
+        if rotate {
+            // the messages in the old file are finished before it is closed
+            self.sync()?;
+            if let Some(file) = self.outputs[output].file.take() {
+                file.close()?;
+            }
+        }
+        let out = &mut self.outputs[output];
+        if out.file.is_none() {
+            out.file = Some(OutFile::create(&self.options, name)?);
+        }
+        if let Some(file) = &mut out.file {
+            file.write(msg.body())?;
+        }
+        self.pending.push(msg);
+        if self.pending.len() >= self.max_pending {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    /// Sync every file and finish the messages written.
+    fn sync(&mut self) -> io::Result<()> {
+        self.last_sync = Instant::now();
+        for out in &mut self.outputs {
+            if let Some(file) = &mut out.file {
+                file.sync()?;
+            }
+        }
+        for msg in self.pending.drain(..) {
+            msg.finish();
+        }
+        self.failures = 0;
+        Ok(())
+    }
+
+    /// Requeue the messages not synced and wait before trying again.
+    async fn failed(&mut self, error: io::Error) {
+        self.failures += 1;
+        let delay = self.backoff.next_delay(self.failures).unwrap_or_default();
+        eprintln!(
+            "nsq-to-file: {}, requeueing {} messages and retrying in {:?}",
+            error,
+            self.pending.len(),
+            delay
+        );
+        for msg in self.pending.drain(..) {
+            msg.requeue(delay);
+        }
+        for out in &mut self.outputs {
+            // partially written, a new file is started
+            out.file = None;
+        }
+        rt::sleep(delay).await;
+    }
+
+    fn close(mut self) -> io::Result<()> {
+        self.sync()?;
+        for out in self.outputs {
+            if let Some(file) = out.file {
+                file.close()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Output {
+    topic: Topic,
+    file: Option<OutFile>,
+}
+
+struct OutFile {
+    writer: Writer,
+    /// File name before replacing `<REV>`.
+    name: String,
+    opened: Instant,
+    /// Bytes written, before compression.
+    size: u64,
+}
+
+impl OutFile {
+    /// Create a new file, `<REV>` is chosen to not overwrite existing files.
+    fn create(options: &Options, name: String) -> io::Result<Self> {
+        let file = if name.contains("<REV>") {
+            let mut rev = 0;
+            loop {
+                let suffix = if rev == 0 { String::new() } else { format!("-{}", rev) };
+                let path = options.dir.join(name.replace("<REV>", &suffix));
+                match OpenOptions::new().write(true).create_new(true).open(&path) {
+                    Ok(file) => break file,
+                    Err(e) if e.kind() == io::ErrorKind::AlreadyExists => rev += 1,
+                    Err(e) => return Err(io::Error::new(e.kind(), format!("{}: {}", path.display(), e))),
+                }
+            }
+        } else {
+            let path = options.dir.join(&name);
+            OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(&path)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?
+        };
+        Ok(OutFile {
+            writer: Writer::new(file, options.gzip),
+            name,
+            opened: Instant::now(),
+            size: 0,
+        })
+    }
+
+    fn write(&mut self, body: &[u8]) -> io::Result<()> {
+        self.writer.write_all(body)?;
+        self.writer.write_all(b"\n")?;
+        self.size += body.len() as u64 + 1;
+        Ok(())
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        self.writer.file().sync_data()
+    }
+
+    fn close(self) -> io::Result<()> {
+        self.writer.finish()?.sync_all()
+    }
+}
+
+enum Writer {
+    Plain(BufWriter<File>),
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::GzEncoder<File>),
+}
+
+impl Writer {
+    #[cfg(feature = "gzip")]
+    fn new(file: File, gzip: Option<u32>) -> Self {
+        match gzip {
+            Some(level) => Writer::Gzip(flate2::write::GzEncoder::new(file, flate2::Compression::new(level))),
+            None => Writer::Plain(BufWriter::new(file)),
+        }
+    }
+
+    #[cfg(not(feature = "gzip"))]
+    fn new(file: File, _gzip: Option<u32>) -> Self {
+        Writer::Plain(BufWriter::new(file))
+    }
+
+    fn file(&self) -> &File {
+        match self {
+            Writer::Plain(file) => file.get_ref(),
+            #[cfg(feature = "gzip")]
+            Writer::Gzip(encoder) => encoder.get_ref(),
+        }
+    }
+
+    /// Write the gzip trailer.
+    fn finish(self) -> io::Result<File> {
+        match self {
+            Writer::Plain(file) => file.into_inner().map_err(|e| e.into_error()),
+            #[cfg(feature = "gzip")]
+            Writer::Gzip(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl Write for Writer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Writer::Plain(file) => file.write(buf),
+            #[cfg(feature = "gzip")]
+            Writer::Gzip(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Writer::Plain(file) => file.flush(),
+            #[cfg(feature = "gzip")]
+            Writer::Gzip(encoder) => encoder.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn options(dir: PathBuf, filename: &str) -> Options {
+        Options {
+            dir,
+            filename: filename.to_owned(),
+            datetime: "%Y-%m-%d_%H".to_owned(),
+            host: "archiver".to_owned(),
+            rotate_size: 0,
+            rotate_interval: Duration::from_secs(0),
+            gzip: None,
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nsq-to-file-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn filename() {
+        let options = options(PathBuf::new(), "<TOPIC>.<HOST><REV>.<DATETIME>.<PID>.log");
+        let topic = Topic::new("events").unwrap();
+        let now = Utc::from_nanos(1_600_000_000_000_000_000);
+        let expected = format!("events.archiver<REV>.2020-09-13_12.{}.log", process::id());
+        assert_eq!(options.filename(&topic, now), expected);
+    }
+
+    #[test]
+    fn rev_avoids_existing_files() {
+        let dir = temp_dir("rev");
+        let options = options(dir.clone(), "<TOPIC><REV>.log");
+        for _ in 0..3 {
+            OutFile::create(&options, "events<REV>.log".to_owned()).unwrap().close().unwrap();
+        }
+        let mut names: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["events-1.log", "events-2.log", "events.log"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn without_rev_appends() {
+        let dir = temp_dir("append");
+        let options = options(dir.clone(), "<TOPIC>.log");
+        for body in &["a", "b"] {
+            let mut file = OutFile::create(&options, "events.log".to_owned()).unwrap();
+            file.write(body.as_bytes()).unwrap();
+            assert_eq!(file.size, 2);
+            file.close().unwrap();
+        }
+        assert_eq!(fs::read_to_string(dir.join("events.log")).unwrap(), "a\nb\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,374 @@
+// MIT License
+//
+// Copyright (c) 2019-2021 Alessandro Cresto Miseroglio <alex179ohm@gmail.com>
+// Copyright (c) 2019-2021 Tangram Technologies S.R.L. <https://tngrm.io>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+
+//! Forward the messages of a topic to HTTP endpoints.
+
+mod common;
+
+use common::{fatal, Flags};
+use futures::future::{join_all, select, try_join_all, Either};
+use futures::TryStreamExt;
+use nsq_rust::http::{encode, request};
+use nsq_rust::retry::{ExponentialBackoff, RetryPolicy};
+use nsq_rust::{rt, Channel, Client, Message, NsqResult};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+const USAGE: &str = "\
+usage: nsq-to-http --topic TOPIC (--post URL | --get URL) [options]
+
+Send every message of TOPIC to the HTTP endpoints. Messages are requeued with an
+increasing delay when an endpoint doesn't answer 2xx.
+
+options:
+  --topic TOPIC               topic to forward
+  --channel CHANNEL           channel to subscribe (default: nsq_to_http)
+  --post URL                  POST the message body to URL (may be given multiple times)
+  --get URL                   GET URL, %s is replaced by the url encoded message body
+                              (may be given multiple times)
+  --mode MODE                 round-robin: each message to one endpoint, fanout: to all
+                              of them (default: round-robin)
+  --n N                       messages forwarded concurrently from each nsqd (default: 1)
+  --header 'NAME: VALUE'      add a header to every request (may be given multiple times)
+  --content-type TYPE         Content-Type of POST requests (default: application/octet-stream)
+  --http-timeout DURATION     timeout of each request (default: 20s)";
+
+fn main() {
+    let flags = Flags::new(USAGE)
+        .options(&[
+            "topic",
+            "channel",
+            "post",
+            "get",
+            "mode",
+            "n",
+            "header",
+            "content-type",
+            "http-timeout",
+        ])
+        .parse();
+    if let Err(e) = rt::block_on(forward(flags)) {
+        fatal(e);
+    }
+}
+
+async fn forward(flags: Flags) -> NsqResult<()> {
+    let topic = flags.topic();
+    let channel = Channel::new(flags.value("channel").unwrap_or("nsq_to_http"))?;
+    let mut endpoints = Vec::new();
+    for url in flags.raw_values("post") {
+        endpoints.push(Endpoint::parse("POST", url).unwrap_or_else(|e| fatal(e)));
+    }
+    for url in flags.raw_values("get") {
+        endpoints.push(Endpoint::parse("GET", url).unwrap_or_else(|e| fatal(e)));
+    }
+    if endpoints.is_empty() {
+        fatal("--post or --get is required");
+    }
+    let fanout = match flags.value("mode").unwrap_or("round-robin") {
+        "round-robin" => false,
+        "fanout" => true,
+        mode => fatal(format!("invalid --mode {}: expected round-robin or fanout", mode)),
+    };
+    let mut headers = vec![(
+        "Content-Type".to_owned(),
+        flags.value("content-type").unwrap_or("application/octet-stream").to_owned(),
+    )];
+    for header in flags.raw_values("header") {
+        match parse_header(header) {
+            Some(header) => headers.push(header),
+            None => fatal(format!("invalid --header {}: expected 'NAME: VALUE'", header)),
+        }
+    }
+    let concurrency: u32 = flags.parse_or("n", 1).max(1);
+    let config = common::config(&flags, "nsq-to-http").max_in_flight(concurrency).build()?;
+    let forwarder = Forwarder {
+        endpoints: Arc::new(endpoints),
+        fanout,
+        next: Arc::new(AtomicUsize::new(0)),
+        headers: Arc::new(headers),
+        timeout: flags.duration_or("http-timeout", Duration::from_secs(20)),
+        backoff: ExponentialBackoff::new(
+            Duration::from_millis(config.backoff_multiplier),
+            Duration::from_millis(config.max_backoff_duration),
+        ),
+    };
+
+    let mut subscriptions = Vec::new();
+    for addr in common::nsqd_addresses(&config, &topic).await? {
+        let client = Client::new(addr.as_str(), config.clone(), None, None);
+        let stream = client.subscribe(topic.clone(), channel.clone()).await?;
+        let forwarder = forwarder.clone();
+        subscriptions.push(stream.try_for_each_concurrent(concurrency as usize, move |msg| {
+            let forwarder = forwarder.clone();
+            async move {
+                forwarder.forward(msg).await;
+                Ok(())
+            }
+        }));
+    }
+    // closing the connections makes nsqd requeue the messages being sent
+    match select(Box::pin(try_join_all(subscriptions)), Box::pin(common::shutdown())).await {
+        Either::Left((res, _)) => res.map(|_| ()),
+        Either::Right(_) => Ok(()),
+    }
+}
+
+struct Endpoint {
+    method: &'static str,
+    /// `host:port`
+    addr: String,
+    host: String,
+    path: String,
+}
+
+impl Endpoint {
+    fn parse(method: &'static str, url: &str) -> Result<Self, String> {
+        let rest = match url.strip_prefix("http://") {
+            Some(rest) => rest,
+            None if url.starts_with("https://") => return Err(format!("{}: https is not supported", url)),
+            None => return Err(format!("invalid url {}: expected http://host[:port]/path", url)),
+        };
+        let (host, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        if host.is_empty() {
+            return Err(format!("invalid url {}: missing host", url));
+        }
+        let addr = if host.contains(':') { host.to_owned() } else { format!("{}:80", host) };
+        Ok(Endpoint {
+            method,
+            addr,
+            host: host.to_owned(),
+            path: path.to_owned(),
+        })
+    }
+}
+
+/// `NAME: VALUE` header of the command line.
+fn parse_header(header: &str) -> Option<(String, String)> {
+    let (name, value) = header.split_once(':')?;
+    let name = name.trim();
+    if name.is_empty() {
+        return None;
+    }
+    Some((name.to_owned(), value.trim().to_owned()))
+}
+
+/// Sends each message to the endpoints.
+#[derive(Clone)]
+struct Forwarder {
+    endpoints: Arc<Vec<Endpoint>>,
+    fanout: bool,
+    /// Round robin position, shared by all the subscriptions.
+    next: Arc<AtomicUsize>,
+    headers: Arc<Vec<(String, String)>>,
+    timeout: Duration,
+    backoff: ExponentialBackoff,
+}
+
+impl Forwarder {
+    /// Finish `msg` if every endpoint accepted it, requeue it otherwise.
+    async fn forward(&self, msg: Message) {
+        let targets: Vec<&Endpoint> = if self.fanout {
+            self.endpoints.iter().collect()
+        } else {
+            let next = self.next.fetch_add(1, Ordering::Relaxed);
+            vec![&self.endpoints[next % self.endpoints.len()]]
+        };
+        let sent = join_all(targets.into_iter().map(|e| self.send(e, msg.body()))).await;
+        if sent.iter().all(|ok| *ok) {
+            msg.finish();
+        } else {
+            let delay = self.backoff.next_delay(msg.attempts() as u32).unwrap_or_default();
+            msg.requeue(delay);
+        }
+    }
+
+    /// Send the request, true if the endpoint answered 2xx.
+    async fn send(&self, endpoint: &Endpoint, body: &[u8]) -> bool {
+        let (path, body) = match endpoint.method {
+            "GET" => (endpoint.path.replace("%s", &encode(body)), &[][..]),
+            _ => (endpoint.path.clone(), body),
+        };
+        let user_agent = format!("nsq-to-http/{}", env!("CARGO_PKG_VERSION"));
+        let mut headers = vec![("Host", endpoint.host.as_str()), ("User-Agent", user_agent.as_str())];
+        for (name, value) in self.headers.iter() {
+            if endpoint.method == "POST" || name != "Content-Type" {
+                headers.push((name, value));
+            }
+        }
+        match request(&endpoint.addr, endpoint.method, &path, &headers, body, self.timeout).await {
+            Ok(response) if (200..300).contains(&response.status) => true,
+            Ok(response) => {
+                eprintln!("nsq-to-http: {} {} answered {}", endpoint.method, endpoint.addr, response.status);
+                false
+            }
+            Err(e) => {
+                eprintln!("nsq-to-http: {} {} failed: {}", endpoint.method, endpoint.addr, e);
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use nsq_rust::testing::{MockHttp, MockNsqd};
+    use nsq_rust::{Config, MessageStream, Topic};
+
+    fn forwarder(endpoints: Vec<Endpoint>, fanout: bool) -> Forwarder {
+        Forwarder {
+            endpoints: Arc::new(endpoints),
+            fanout,
+            next: Arc::new(AtomicUsize::new(0)),
+            headers: Arc::new(vec![
+                ("Content-Type".to_owned(), "text/plain".to_owned()),
+                ("X-Source".to_owned(), "nsq".to_owned()),
+            ]),
+            timeout: Duration::from_secs(5),
+            backoff: ExponentialBackoff::new(Duration::from_millis(10), Duration::from_millis(50)),
+        }
+    }
+
+    async fn subscribe(nsqd: &MockNsqd) -> MessageStream {
+        let client = Client::new(nsqd.addr(), Config::new(), None, None);
+        client.subscribe(Topic::new("test").unwrap(), Channel::new("ch").unwrap()).await.unwrap()
+    }
+
+    /// FIN and REQ received by `nsqd`, once there are `count` of them.
+    async fn replies(nsqd: &MockNsqd, count: usize) -> Vec<String> {
+        for _ in 0..200 {
+            let replies: Vec<String> =
+                nsqd.commands().into_iter().map(|c| c.name).filter(|name| name == "FIN" || name == "REQ").collect();
+            if replies.len() >= count {
+                return replies;
+            }
+            rt::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("expected {} replies, got {:?}", count, nsqd.commands());
+    }
+
+    #[test]
+    fn endpoint_parse() {
+        let endpoint = Endpoint::parse("POST", "http://example.com:8080/push?x=1").unwrap();
+        assert_eq!((endpoint.addr.as_str(), endpoint.host.as_str()), ("example.com:8080", "example.com:8080"));
+        assert_eq!(endpoint.path, "/push?x=1");
+        let endpoint = Endpoint::parse("GET", "http://example.com").unwrap();
+        assert_eq!((endpoint.addr.as_str(), endpoint.path.as_str()), ("example.com:80", "/"));
+        assert!(matches!(Endpoint::parse("POST", "https://example.com/"), Err(e) if e.contains("https")));
+        assert!(Endpoint::parse("POST", "example.com/push").is_err());
+        assert!(Endpoint::parse("POST", "http:///push").is_err());
+    }
+
+    #[test]
+    fn header_parse() {
+        assert_eq!(parse_header("X-A: 1:2 "), Some(("X-A".to_owned(), "1:2".to_owned())));
+        assert_eq!(parse_header("X-A:"), Some(("X-A".to_owned(), String::new())));
+        assert_eq!(parse_header("X-A"), None);
+        assert_eq!(parse_header(": 1"), None);
+    }
+
+    #[test]
+    fn get_encodes_the_body() {
+        rt::block_on(async {
+            let http = MockHttp::start().await.unwrap();
+            let url = format!("http://{}/push?msg=%s", http.addr());
+            let forwarder = forwarder(vec![Endpoint::parse("GET", &url).unwrap()], false);
+            assert!(forwarder.send(&forwarder.endpoints[0], b"a b&c=\xff").await);
+
+            let request = &http.requests()[0];
+            assert_eq!(request.method, "GET");
+            assert_eq!(request.path, "/push?msg=a%20b%26c%3D%FF");
+            assert!(request.body.is_empty());
+            assert!(request.headers.contains(&("host".to_owned(), http.addr())));
+            assert!(request.headers.contains(&("x-source".to_owned(), "nsq".to_owned())));
+            assert!(!request.headers.iter().any(|(name, _)| name == "content-type"));
+        });
+    }
+
+    #[test]
+    fn post_sends_the_body_and_headers() {
+        rt::block_on(async {
+            let http = MockHttp::start().await.unwrap();
+            let url = format!("http://{}/push", http.addr());
+            let forwarder = forwarder(vec![Endpoint::parse("POST", &url).unwrap()], false);
+            assert!(forwarder.send(&forwarder.endpoints[0], b"hello").await);
+            http.respond(500);
+            assert!(!forwarder.send(&forwarder.endpoints[0], b"hello").await);
+
+            let request = &http.requests()[0];
+            assert_eq!((request.method.as_str(), request.path.as_str()), ("POST", "/push"));
+            assert_eq!(request.body, b"hello");
+            let user_agent = format!("nsq-to-http/{}", env!("CARGO_PKG_VERSION"));
+            for header in &[("content-type", "text/plain"), ("x-source", "nsq"), ("user-agent", &user_agent)] {
+                assert!(request.headers.contains(&(header.0.to_owned(), header.1.to_owned())), "{:?}", header);
+            }
+        });
+    }
+
+    #[test]
+    fn requeue_unless_accepted() {
+        rt::block_on(async {
+            let nsqd = MockNsqd::start().await.unwrap();
+            let http = MockHttp::start().await.unwrap();
+            let url = format!("http://{}/push", http.addr());
+            let forwarder = forwarder(vec![Endpoint::parse("POST", &url).unwrap()], false);
+            let mut stream = subscribe(&nsqd).await;
+            nsqd.put("test", b"hello".to_vec());
+
+            http.respond(503);
+            forwarder.forward(stream.next().await.unwrap().unwrap()).await;
+            let msg = stream.next().await.unwrap().unwrap();
+            assert_eq!(msg.attempts(), 2);
+            forwarder.forward(msg).await;
+            assert_eq!(replies(&nsqd, 2).await, vec!["REQ", "FIN"]);
+            assert_eq!(http.requests().len(), 2);
+        });
+    }
+
+    #[test]
+    fn fanout_requeues_if_any_endpoint_fails() {
+        rt::block_on(async {
+            let nsqd = MockNsqd::start().await.unwrap();
+            let (ok, failing) = (MockHttp::start().await.unwrap(), MockHttp::start().await.unwrap());
+            let endpoints = vec![
+                Endpoint::parse("POST", &format!("http://{}/", ok.addr())).unwrap(),
+                Endpoint::parse("POST", &format!("http://{}/", failing.addr())).unwrap(),
+            ];
+            let forwarder = forwarder(endpoints, true);
+            let mut stream = subscribe(&nsqd).await;
+            nsqd.put("test", b"hello".to_vec());
+
+            failing.respond(500);
+            forwarder.forward(stream.next().await.unwrap().unwrap()).await;
+            assert_eq!(replies(&nsqd, 1).await, vec!["REQ"]);
+            assert_eq!((ok.requests().len(), failing.requests().len()), (1, 1));
+        });
+    }
+}
@@ -0,0 +1,125 @@
+// MIT License
+//
+// Copyright (c) 2019-2021 Alessandro Cresto Miseroglio <alex179ohm@gmail.com>
+// Copyright (c) 2019-2021 Tangram Technologies S.R.L. <https://tngrm.io>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+
+//! Replicate a topic to other nsqd.
+
+mod common;
+
+use common::{fatal, Flags};
+use futures::StreamExt;
+use nsq_rust::{rt, Bridge, Channel, Client, NsqResult, Topic};
+use serde_json::Value;
+use std::time::Duration;
+
+const USAGE: &str = "\
+usage: nsq-to-nsq --topic TOPIC --destination-nsqd-tcp-address ADDR [options]
+
+Republish the messages of TOPIC to the destination nsqd, used round robin. A message
+is finished only after the destination acknowledged it. The connection options apply
+to both the source and the destination.
+
+options:
+  --topic TOPIC                         topic to replicate
+  --channel CHANNEL                     channel to subscribe (default: nsq_to_nsq)
+  --destination-nsqd-tcp-address ADDR   destination nsqd (may be given multiple times)
+  --destination-topic TOPIC             topic to publish to (default: TOPIC)
+  --batch N                             publish up to N messages with each MPUB (default: 1)
+  --batch-timeout DURATION              wait at most DURATION for a batch to fill (default: 100ms)
+  --max-in-flight N                     messages in flight per nsqd (default: 200)
+  --require-json-field FIELD            only replicate JSON messages with FIELD
+  --require-json-value VALUE            only replicate messages where FIELD is VALUE";
+
+fn main() {
+    let flags = Flags::new(USAGE)
+        .options(&[
+            "topic",
+            "channel",
+            "destination-nsqd-tcp-address",
+            "destination-topic",
+            "batch",
+            "batch-timeout",
+            "max-in-flight",
+            "require-json-field",
+            "require-json-value",
+        ])
+        .parse();
+    if let Err(e) = rt::block_on(replicate(flags)) {
+        fatal(e);
+    }
+}
+
+async fn replicate(flags: Flags) -> NsqResult<()> {
+    let topic = flags.topic();
+    let channel = Channel::new(flags.value("channel").unwrap_or("nsq_to_nsq"))?;
+    let destination_topic = match flags.value("destination-topic") {
+        Some(t) => Topic::new(t)?,
+        None => topic.clone(),
+    };
+    let destinations = flags.values("destination-nsqd-tcp-address");
+    if destinations.is_empty() {
+        fatal("--destination-nsqd-tcp-address is required");
+    }
+    let batch: usize = flags.parse_or("batch", 1);
+    let max_in_flight: u32 = flags.parse_or("max-in-flight", 200);
+    if batch > max_in_flight as usize {
+        fatal("--batch can't be larger than --max-in-flight");
+    }
+    let field = flags.value("require-json-field").map(str::to_owned);
+    let value = flags.value("require-json-value").map(str::to_owned);
+    if value.is_some() && field.is_none() {
+        fatal("--require-json-value requires --require-json-field");
+    }
+
+    let config = common::config(&flags, "nsq-to-nsq").max_in_flight(max_in_flight).build()?;
+    let mut producers = Vec::new();
+    for addr in destinations {
+        producers.push(Client::new(addr, config.clone(), None, None).producer().await?);
+    }
+    let mut bridge = Bridge::new(destination_topic, producers)
+        .batch(batch, flags.duration_or("batch-timeout", Duration::from_millis(100)));
+    if let Some(field) = field {
+        bridge = bridge.filter(move |body| match json_field(body, &field) {
+            Some(found) if value.as_ref().is_none_or(|v| *v == found) => Some(body.to_vec()),
+            _ => None,
+        });
+    }
+
+    let mut messages = common::subscribe(&config, &topic, &channel).await?;
+    let res = {
+        let mut until_shutdown = messages.by_ref().take_until(Box::pin(common::shutdown()));
+        bridge.run(&mut until_shutdown).await
+    };
+    for stream in messages {
+        stream.close().await;
+    }
+    res
+}
+
+/// Top level `field` of a JSON object, strings without quotes.
+fn json_field(body: &[u8], field: &str) -> Option<String> {
+    match serde_json::from_slice::<Value>(body).ok()?.get(field)? {
+        Value::String(s) => Some(s.clone()),
+        v => Some(v.to_string()),
+    }
+}
@@ -0,0 +1,145 @@
+// MIT License
+//
+// Copyright (c) 2019-2021 Alessandro Cresto Miseroglio <alex179ohm@gmail.com>
+// Copyright (c) 2019-2021 Tangram Technologies S.R.L. <https://tngrm.io>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Blocking facade over the async [Producer](../struct.Producer.html) and consumer.
+//!
+//! The blocking clients drive the async ones on a background [Runtime](../rt/struct.Runtime.html)
+//! shared with [rt::block_on](../rt/fn.block_on.html), the API mirrors the async API.
+//!
+//! # Examples
+//!```no-run
+//! use nsq_rust::{blocking, Client, Config, Topic, Channel};
+//!
+//! let client = Client::new("localhost:4150", Config::new(), None, None);
+//! let topic = Topic::new("test")?;
+//! let mut producer = blocking::Producer::connect(client.clone())?;
+//! producer.publish(&topic, b"hello".to_vec())?;
+//!
+//! for msg in blocking::Consumer::connect(client, topic, Channel::new("printer")?)? {
+//!     let msg = msg?;
+//!     println!("{:?}", msg.body());
+//!     msg.finish();
+//! }
+//!```
+
+use crate::client::Client;
+use crate::consumer::{Message as AsyncMessage, MessageStream};
+use crate::msg::Msg;
+use crate::producer::Producer as AsyncProducer;
+use crate::response::Response;
+use crate::result::NsqResult;
+use crate::rt;
+use crate::topic::{Channel, Topic};
+use futures::StreamExt;
+use std::fmt;
+use std::ops::Deref;
+use std::time::Duration;
+
+/// Blocking [Producer](../struct.Producer.html).
+pub struct Producer {
+    inner: AsyncProducer,
+}
+
+impl Producer {
+    pub fn connect(client: Client) -> NsqResult<Self> {
+        let inner = rt::block_on(client.producer())?;
+        Ok(Producer { inner })
+    }
+
+    pub fn publish(&mut self, topic: &Topic, msg: Vec<u8>) -> NsqResult<Response> {
+        rt::block_on(self.inner.publish(topic, msg))
+    }
+
+    pub fn mpublish(&mut self, topic: &Topic, msgs: Vec<Vec<u8>>) -> NsqResult<Response> {
+        rt::block_on(self.inner.mpublish(topic, msgs))
+    }
+
+    pub fn dpublish(&mut self, topic: &Topic, delay: Duration, msg: Vec<u8>) -> NsqResult<Response> {
+        rt::block_on(self.inner.dpublish(topic, delay, msg))
+    }
+}
+
+/// Message received by a blocking [Consumer](struct.Consumer.html).
+///
+/// Dropping a message without calling [finish](#method.finish) or [requeue](#method.requeue)
+/// requeues it without delay.
+pub struct Message {
+    inner: AsyncMessage,
+}
+
+impl Message {
+    /// Send FIN, the message is done.
+    pub fn finish(self) {
+        self.inner.finish();
+    }
+
+    /// Send REQ, nsqd will deliver the message again after `delay`.
+    pub fn requeue(self, delay: Duration) {
+        self.inner.requeue(delay);
+    }
+
+    /// Send TOUCH, resetting the message timeout on nsqd.
+    pub fn touch(&self) {
+        self.inner.touch();
+    }
+}
+
+impl Deref for Message {
+    type Target = Msg;
+
+    fn deref(&self) -> &Msg {
+        &self.inner
+    }
+}
+
+impl fmt::Debug for Message {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Message").field("msg", &*self.inner).finish()
+    }
+}
+
+/// Blocking consumer, an iterator of the messages received.
+///
+/// Up to `max_in_flight` messages are delivered before the first ones are finished or
+/// requeued, as with a [MessageStream](../struct.MessageStream.html). Errors are yielded
+/// by the iterator, which ends when the connection is closed or the retry policy gives up.
+pub struct Consumer {
+    stream: MessageStream,
+}
+
+impl Consumer {
+    /// Connect and subscribe, returns once nsqd confirmed the subscription or with its error.
+    pub fn connect(client: Client, topic: Topic, channel: Channel) -> NsqResult<Self> {
+        let stream = rt::block_on(client.subscribe(topic, channel))?;
+        Ok(Consumer { stream })
+    }
+}
+
+impl Iterator for Consumer {
+    type Item = NsqResult<Message>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = rt::block_on(self.stream.next())?;
+        Some(next.map(|inner| Message { inner }))
+    }
+}
@@ -0,0 +1,218 @@
+// MIT License
+//
+// Copyright (c) 2019-2021 Alessandro Cresto Miseroglio <alex179ohm@gmail.com>
+// Copyright (c) 2019-2021 Tangram Technologies S.R.L. <https://tngrm.io>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+
+//! Replication of a topic to other nsqd.
+//!
+//! A [Bridge](struct.Bridge.html) republishes the messages of a subscription, finishing
+//! them only once the destination acknowledged the publish: replication is at least once.
+
+use crate::consumer::Message;
+use crate::error::NsqError;
+use crate::producer::Producer;
+use crate::result::NsqResult;
+use crate::retry::{ExponentialBackoff, RetryPolicy};
+use crate::rt;
+use crate::topic::Topic;
+use futures::future::{pending, Either};
+use futures::{pin_mut, select, FutureExt, Stream, StreamExt};
+use log::{debug, warn};
+use std::time::{Duration, Instant};
+
+type Filter = Box<dyn Fn(&[u8]) -> Option<Vec<u8>> + Send + Sync>;
+
+/// Republish messages to one or more nsqd, used round robin.
+///
+/// Messages are published in batches with MPUB, a batch is sent when it is full or
+/// `batch_timeout` after its first message. When the publish fails the messages of the
+/// batch are requeued with an increasing delay, a batch rejected by nsqd (`E_BAD_BODY`,
+/// `E_BAD_MESSAGE`) is published again message by message and only the invalid ones are requeued.
+///
+/// # Examples
+///```no-run
+/// use nsq_rust::{Bridge, Channel, Client, Config, Topic};
+///
+/// let source = Client::new("primary:4150", Config::new().max_in_flight(200), None, None);
+/// let mut messages = source.subscribe(Topic::new("events")?, Channel::new("replication")?).await?;
+/// let destination = Client::new("dr:4150", Config::new(), None, None).producer().await?;
+/// Bridge::new(Topic::new("events")?, vec![destination])
+///     .batch(100, Duration::from_millis(50))
+///     .filter(|body| if body.is_empty() { None } else { Some(body.to_vec()) })
+///     .run(&mut messages)
+///     .await?;
+///```
+pub struct Bridge {
+    topic: Topic,
+    producers: Vec<Producer>,
+    next: usize,
+    filter: Option<Filter>,
+    batch_size: usize,
+    batch_timeout: Duration,
+    retry: Box<dyn RetryPolicy>,
+    failures: u32,
+}
+
+impl Bridge {
+    /// Publish to `topic` of the `producers`, one message at a time.
+    ///
+    /// # Panics
+    /// If `producers` is empty.
+    pub fn new(topic: Topic, producers: Vec<Producer>) -> Self {
+        assert!(!producers.is_empty(), "bridge without destination");
+        Bridge {
+            topic,
+            producers,
+            next: 0,
+            filter: None,
+            batch_size: 1,
+            batch_timeout: Duration::from_millis(0),
+            retry: Box::new(ExponentialBackoff::new(Duration::from_secs(1), Duration::from_secs(120))),
+            failures: 0,
+        }
+    }
+
+    /// Publish up to `size` messages with each MPUB, waiting at most `timeout` for the
+    /// batch to fill. `size` should not exceed the `max_in_flight` of the subscription.
+    pub fn batch(mut self, size: usize, timeout: Duration) -> Self {
+        self.batch_size = size.max(1);
+        self.batch_timeout = timeout;
+        self
+    }
+
+    /// Transform the body of each message, messages mapped to `None` or to an empty body
+    /// are finished without being published.
+    pub fn filter<F: Fn(&[u8]) -> Option<Vec<u8>> + Send + Sync + 'static>(mut self, filter: F) -> Self {
+        self.filter = Some(Box::new(filter));
+        self
+    }
+
+    /// Delay of the requeues after the publish failed `attempt` times in a row,
+    /// exponential backoff from 1 second to 2 minutes by default.
+    pub fn requeue_policy<P: RetryPolicy + 'static>(mut self, policy: P) -> Self {
+        self.retry = Box::new(policy);
+        self
+    }
+
+    /// Republish `messages` until the stream ends or fails.
+    ///
+    /// The pending batch is published before returning.
+    pub async fn run<S>(&mut self, messages: &mut S) -> NsqResult<()>
+    where
+        S: Stream<Item = NsqResult<Message>> + Unpin,
+    {
+        let mut batch = Vec::with_capacity(self.batch_size);
+        let mut deadline = Instant::now();
+        loop {
+            let timer = if batch.is_empty() {
+                Either::Left(pending())
+            } else {
+                Either::Right(rt::sleep(deadline.saturating_duration_since(Instant::now())))
+            }
+            .fuse();
+            pin_mut!(timer);
+            select! {
+                msg = messages.next().fuse() => match msg {
+                    Some(Ok(msg)) => {
+                        let body = match &self.filter {
+                            Some(filter) => filter(msg.body()),
+                            None => Some(msg.body().to_vec()),
+                        };
+                        match body {
+                            // nsqd rejects empty messages
+                            Some(body) if !body.is_empty() => {
+                                if batch.is_empty() {
+                                    deadline = Instant::now() + self.batch_timeout;
+                                }
+                                batch.push((msg, body));
+                            }
+                            _ => {
+                                debug!("message {} filtered", msg.id());
+                                msg.finish();
+                            }
+                        }
+                        if batch.len() >= self.batch_size {
+                            self.flush(&mut batch).await;
+                        }
+                    }
+                    Some(Err(e)) => {
+                        self.flush(&mut batch).await;
+                        return Err(e);
+                    }
+                    None => {
+                        self.flush(&mut batch).await;
+                        return Ok(());
+                    }
+                },
+                _ = timer => self.flush(&mut batch).await,
+            }
+        }
+    }
+
+    /// Publish the batch, finishing the messages on success and requeueing them on failure.
+    async fn flush(&mut self, batch: &mut Vec<(Message, Vec<u8>)>) {
+        if batch.is_empty() {
+            return;
+        }
+        let (msgs, bodies): (Vec<_>, Vec<_>) = batch.drain(..).unzip();
+        match self.publish(bodies.clone()).await {
+            Ok(()) => self.finish(msgs),
+            // nsqd rejects the whole MPUB for one invalid message or when it is too big
+            Err(e @ NsqError::Body) | Err(e @ NsqError::Message) if msgs.len() > 1 => {
+                warn!("MPUB to {} rejected: {}, publishing its {} messages one by one", self.topic, e, msgs.len());
+                for (msg, body) in msgs.into_iter().zip(bodies) {
+                    match self.publish(vec![body]).await {
+                        Ok(()) => self.finish(vec![msg]),
+                        Err(e) => self.requeue(vec![msg], e),
+                    }
+                }
+            }
+            Err(e) => self.requeue(msgs, e),
+        }
+    }
+
+    /// Publish to the next producer, with PUB for a single message.
+    async fn publish(&mut self, mut bodies: Vec<Vec<u8>>) -> NsqResult<()> {
+        let next = self.next;
+        self.next = (next + 1) % self.producers.len();
+        let producer = &mut self.producers[next];
+        let res = match bodies.len() {
+            1 => producer.publish(&self.topic, bodies.remove(0)).await,
+            _ => producer.mpublish(&self.topic, bodies).await,
+        };
+        res.map(|_| ())
+    }
+
+    fn finish(&mut self, msgs: Vec<Message>) {
+        self.failures = 0;
+        msgs.into_iter().for_each(Message::finish);
+    }
+
+    fn requeue(&mut self, msgs: Vec<Message>, e: NsqError) {
+        self.failures += 1;
+        let delay = self.retry.next_delay(self.failures).unwrap_or_default();
+        warn!("publish to {} failed: {}, requeueing {} messages in {:?}", self.topic, e, msgs.len(), delay);
+        for msg in msgs {
+            msg.requeue(delay);
+        }
+    }
+}
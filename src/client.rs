@@ -21,156 +21,358 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use async_std::net::{ToSocketAddrs, TcpStream};
-use async_std::stream::StreamExt;
-use async_std::io;
-use log::{debug, info};
+use log::{debug, info, warn};
 use std::fmt::Debug;
-use std::io::Cursor;
+use std::io::{self, Cursor};
 use std::sync::Arc;
 use crate::error::NsqError;
 use crate::codec::Encoder;
-use crate::utils;
+use crate::trace::{self, span};
+use crate::rt;
 use crate::result::NsqResult;
-use crate::auth::Authentication;
-use crate::io::NsqStream as NsqIO;
+use crate::connection::Connection;
 use crate::response::Response;
-use crate::config::{Config, NsqConfig};
-use bytes::BytesMut;
+use crate::config::Config;
 use std::future::Future;
 use async_tls::TlsConnector;
-use rustls::ClientConfig;
-use std::path::{Path, PathBuf};
-use crate::publish::io_pub;
+use rustls::internal::pemfile;
+use rustls::{Certificate, ClientConfig, RootCertStore, ServerCertVerified, ServerCertVerifier, TLSError};
+use std::path::PathBuf;
+use crate::producer::Producer;
+use crate::consumer::{self, consume, Handler, MessageStream};
+use crate::topic::{Channel, Topic};
+use crate::metrics::{Meter, Metrics, MetricsSink, Snapshot};
+use crate::retry::{ExponentialBackoff, Outage, RetryPolicy};
+use crate::events::{ConnectionEvent, Hooks, OnEvent};
+use std::sync::Mutex;
+use std::time::Duration;
 
 #[derive(Clone)]
 pub struct Client {
     addr: String,
-    config: Config,
+    config: Arc<Config>,
     auth: Option<String>,
     cafile: Option<PathBuf>,
+    metrics: Arc<Metrics>,
+    retry: Arc<dyn RetryPolicy>,
+    outage: Outage,
+    on_event: Option<OnEvent>,
 }
 
 impl Client {
+    /// Create a client for the nsqd at `addr`.
+    ///
+    /// Dropped connections are established again with an [ExponentialBackoff](retry/struct.ExponentialBackoff.html)
+    /// from `backoff_multiplier` to `max_backoff_duration` of `config`, retrying forever.
     pub fn new<ADDR: Into<String> + Debug>(addr: ADDR, config: Config, auth: Option<String>, cafile: Option<PathBuf>) -> Self {
+        let backoff = ExponentialBackoff::new(
+            Duration::from_millis(config.backoff_multiplier),
+            Duration::from_millis(config.max_backoff_duration),
+        );
         Client {
             addr: addr.into(),
-            config,
+            config: Arc::new(config),
             auth,
             cafile,
+            metrics: Arc::new(Metrics::default()),
+            retry: Arc::new(backoff),
+            outage: Outage::Wait,
+            on_event: None,
         }
     }
 
-    pub async fn consumer(self, buf: &mut BytesMut) -> NsqResult<()> {
-        let mut tcp_stream = connect(self.addr.clone()).await?;
-        if let Err(e) = utils::magic(&mut tcp_stream, buf).await {
-            return Err(e);
+    /// Change the [RetryPolicy](retry/trait.RetryPolicy.html) used when a connection drops,
+    /// [NoRetry](retry/struct.NoRetry.html) disables reconnection.
+    pub fn retry_policy<P: RetryPolicy + 'static>(mut self, policy: P) -> Self {
+        self.retry = Arc::new(policy);
+        self
+    }
+
+    /// Choose if publishes wait or fail while a producer is reconnecting.
+    pub fn outage(mut self, outage: Outage) -> Self {
+        self.outage = outage;
+        self
+    }
+
+    /// Call `callback` on every [ConnectionEvent](events/enum.ConnectionEvent.html) of the
+    /// connections opened by this client, from the handshake to the reconnections.
+    pub fn on_event<F: Fn(&ConnectionEvent) + Send + Sync + 'static>(mut self, callback: F) -> Self {
+        self.on_event = Some(Arc::new(callback));
+        self
+    }
+
+    /// Deliver only `rate` percent of the channel's messages to the consumers of this client,
+    /// overriding [Config::sample_rate](struct.Config.html#structfield.sample_rate).
+    ///
+    /// `rate` must be between 0 (every message) and 99, connecting fails if it's out of
+    /// range or if nsqd doesn't confirm it. The effective rate of every connection is in
+    /// [stats](#method.stats).
+    pub fn sample_rate(mut self, rate: u16) -> Self {
+        Arc::make_mut(&mut self.config).sample_rate = rate;
+        self
+    }
+
+    pub(crate) fn outage_mode(&self) -> Outage {
+        self.outage
+    }
+
+    /// Forward the metrics of every connection opened by this client to `sink`.
+    ///
+    /// Counters restart from zero, clones made before share the previous ones.
+    pub fn metrics<M: MetricsSink + 'static>(mut self, sink: M) -> Self {
+        self.metrics = Arc::new(Metrics::with_sink(Arc::new(sink)));
+        self
+    }
+
+    /// Counters of the producers and consumers created by this client and its clones.
+    pub fn stats(&self) -> Snapshot {
+        self.metrics.snapshot()
+    }
+
+    /// Subscribe to `topic`/`channel` and run `handler` on every message received,
+    /// until the connection is closed.
+    ///
+    /// When the connection drops the consumer reconnects according to the retry policy.
+    pub async fn consumer<H: Handler>(self, topic: Topic, channel: Channel, mut handler: H) -> NsqResult<()> {
+        let span = span!("nsq.subscription", addr = %self.addr, topic = %topic, channel = %channel);
+        let max_in_flight = self.config.max_in_flight;
+        let consumer = async {
+            let mut conn = self.clone().handshake().await?;
+            let mut attempts = 0;
+            loop {
+                let res = match conn.subscribe(&topic, &channel).await {
+                    Ok(()) => {
+                        attempts = 0;
+                        consume(conn, max_in_flight, &mut handler).await
+                    }
+                    Err(e) => Err(e),
+                };
+                match res {
+                    Err(e) if e.is_connection_error() => conn = self.reconnect(e, &mut attempts).await?,
+                    res => return res,
+                }
+            }
+        };
+        trace::instrument(consumer, span).await
+    }
+
+    /// Subscribe to `topic`/`channel` and return the messages as a [MessageStream](struct.MessageStream.html).
+    pub async fn subscribe(self, topic: Topic, channel: Channel) -> NsqResult<MessageStream> {
+        let span = span!("nsq.subscription", addr = %self.addr, topic = %topic, channel = %channel);
+        let max_in_flight = self.config.max_in_flight;
+        let subscribe = async {
+            let conn = self.clone().handshake().await?;
+            consumer::stream(self, conn, topic, channel, max_in_flight, span.clone()).await
+        };
+        trace::instrument(subscribe, span.clone()).await
+    }
+
+    /// Connect to nsqd and return a [Producer](struct.Producer.html) keeping the connection open.
+    pub async fn producer(self) -> NsqResult<Producer> {
+        let span = span!("nsq.producer", addr = %self.addr);
+        let conn = trace::instrument(self.clone().handshake(), span.clone()).await?;
+        Ok(Producer::new(self, conn.start(), span))
+    }
+
+    /// Wait for the retry policy and run the handshake again, until it succeeds
+    /// or the policy gives up. `error` made the connection drop.
+    ///
+    /// `attempts` counts the attempts already made, callers that fail right after the
+    /// handshake (like subscribing) carry it to the next call so the policy still applies.
+    pub(crate) async fn reconnect(&self, error: NsqError, attempts: &mut u32) -> NsqResult<Connection> {
+        warn!("connection to {} lost: {}", self.addr, error);
+        self.event(|addr| ConnectionEvent::Disconnected {
+            addr,
+            error: error.to_string(),
+        });
+        let mut last_error = error;
+        loop {
+            *attempts += 1;
+            let attempt = *attempts;
+            let delay = match self.retry.next_delay(attempt) {
+                Some(delay) => delay,
+                None => {
+                    self.event(|addr| ConnectionEvent::GaveUp {
+                        addr,
+                        attempts: attempt - 1,
+                    });
+                    return Err(last_error);
+                }
+            };
+            self.event(|addr| ConnectionEvent::Reconnecting { addr, attempt, delay });
+            rt::sleep(delay).await;
+            match self.clone().handshake().await {
+                Ok(conn) => {
+                    info!("reconnected to {} after {} attempts", self.addr, attempt);
+                    if let Some(meter) = conn.meter() {
+                        meter.reconnected();
+                    }
+                    self.event(|addr| ConnectionEvent::Reconnected {
+                        addr,
+                        attempts: attempt,
+                    });
+                    return Ok(conn);
+                }
+                Err(e) if e.is_connection_error() => {
+                    debug!("reconnection attempt {} to {} failed: {}", attempt, self.addr, e);
+                    last_error = e;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn hooks(&self) -> Option<Hooks> {
+        self.on_event.as_ref().map(|on_event| Hooks::new(&self.addr, on_event.clone()))
+    }
+
+    fn event<F: FnOnce(String) -> ConnectionEvent>(&self, event: F) {
+        if let Some(hooks) = self.hooks() {
+            hooks.emit(event);
         }
-        let mut io = NsqIO::new(&mut tcp_stream, 1024);
-        let nsqd_cfg: NsqConfig;
-        match utils::identify(&mut io, self.config.clone(), buf).await {
-            Ok(Response::Json(s)) => nsqd_cfg = serde_json::from_str(&s).expect("json deserialize error"),
-            Err(e) => return Err(e),
-            _ => unreachable!(),
+    }
+
+    /// Open a connection and run the handshake: MAGIC, IDENTIFY, TLS upgrade and AUTH.
+    pub(crate) async fn handshake(self) -> NsqResult<Connection> {
+        let span = span!("nsq.connect", addr = %self.addr);
+        trace::instrument(self.run_handshake(), span).await
+    }
+
+    async fn run_handshake(self) -> NsqResult<Connection> {
+        self.config.validate()?;
+        let meter = Meter::new(&self.addr, self.metrics.clone());
+        let mut conn = Connection::open(&self.addr, meter, self.hooks()).await?;
+        self.event(|addr| ConnectionEvent::Connected { addr });
+        let negotiated = trace::instrument(conn.identify(&self.config), span!("nsq.identify")).await?;
+        if let Some(nsqd_cfg) = &negotiated {
+            self.config.validate_negotiated(nsqd_cfg)?;
         }
-        debug!("{:?}", nsqd_cfg);
+        let nsqd_cfg = negotiated.unwrap_or_default();
         info!("Configuration OK: {:?}", nsqd_cfg);
-        let addr: Vec<&str> = self.addr.split(':').collect();
-        let cafile = self.cafile;
-        let connector = if let Some(cafile) = cafile {
-            connector_from_cafile(&cafile).await.expect("failed to read cafile")
-        } else {
-            TlsConnector::new()
-        };
+        self.event(|addr| ConnectionEvent::Identified {
+            addr,
+            config: nsqd_cfg.clone(),
+        });
+        if let Some(meter) = conn.meter() {
+            // confirmed by nsqd if feature negotiation is enabled
+            meter.sample_rate(self.config.sample_rate);
+        }
         if nsqd_cfg.tls_v1 {
-            let mut tls_stream = connector.connect(addr[0], tcp_stream).unwrap().await?;
-            let mut stream = NsqIO::new(&mut tls_stream, 1024);
-            stream.next().await.unwrap()?;
-            info!("TLS Ok");
-            Ok(())
-        } else {
-            Ok(())
+            let verifier = Arc::new(PeerVerifier::new());
+            let upgrade = async {
+                let host = self.addr.split(':').next().unwrap_or_default();
+                let cafile = self.cafile.as_ref().or_else(|| self.config.tls_root_ca_file.as_ref());
+                let connector = tls_connector(&self.config, cafile, verifier.clone()).await?;
+                conn.upgrade_tls(&connector, host).await
+            };
+            conn = trace::instrument(upgrade, span!("nsq.tls")).await?;
+            self.event(|addr| ConnectionEvent::TlsEstablished {
+                addr,
+                peer_certificate: verifier.certificate(),
+            });
+        }
+        if nsqd_cfg.deflate {
+            conn.compress("deflate")?;
+        } else if nsqd_cfg.snappy {
+            conn.compress("snappy")?;
         }
+        let auth = self.auth.clone().or_else(|| self.config.auth_secret.clone());
+        if let Some(secret) = auth.filter(|_| nsqd_cfg.auth_required) {
+            let authenticate = async {
+                if let Some(auth) = conn.authenticate(&secret).await? {
+                    info!("AUTH: {:?}", auth);
+                    self.event(|addr| ConnectionEvent::Authenticated { addr, auth });
+                }
+                Ok::<(), NsqError>(())
+            };
+            trace::instrument(authenticate, span!("nsq.auth")).await?;
+        }
+        conn.ready()?;
+        Ok(conn)
     }
 
+    /// Connect to nsqd, send a single publish command and close the connection.
     pub async fn publish<F, T>(self, future: F) -> NsqResult<Response>
     where
         F: Future<Output = T>,
         T: Encoder,
     {
-        let mut buf = BytesMut::new();
-        let mut tcp_stream = connect(self.addr.clone()).await?;
-        let mut stream = NsqIO::new(&mut tcp_stream, 1024);
-        if let Err(e) = utils::magic(&mut stream, &mut buf).await {
-            return Err(e);
-        }
-        let nsqd_cfg: NsqConfig;
-        match utils::identify(&mut stream, self.config.clone(), &mut buf).await {
-            Ok(Response::Json(s)) => nsqd_cfg = serde_json::from_str(&s).expect("json deserialize error"),
-            Err(e) => return Err(e),
-            _ => unreachable!(),
-        }
-        debug!("{:?}", nsqd_cfg);
-        println!("Configuration OK: {:?}", nsqd_cfg);
-        let addr: Vec<&str> = self.addr.split(':').collect();
-        let cafile = self.cafile;
-        let connector = if let Some(cafile) = cafile {
-            connector_from_cafile(&cafile).await.expect("failed to read cafile")
-        } else {
-            TlsConnector::new()
-        };
-        if nsqd_cfg.tls_v1 {
-            let mut tls_stream = connector.connect(addr[0], tcp_stream).unwrap().await?;
-            let mut stream = NsqIO::new(&mut tls_stream, 1024);
-            stream.next().await.unwrap()?;
-            info!("TLS Ok");
-            if nsqd_cfg.auth_required && self.auth.is_some() {
-                let auth_token = self.auth.unwrap();
-                stream.reset();
-                if let Response::Json(s) = utils::auth(&mut stream, auth_token, &mut buf).await? {
-                    let auth: Authentication = serde_json::from_str(&s).expect("json deserialize error");
-                    info!("AUTH: {:?}", auth);
-                }
-            }
-            let msg = future.await;
-            msg.encode(&mut buf);
-            stream.reset();
-            io_pub(&mut stream, &mut buf).await
-        } else {
-            if nsqd_cfg.auth_required && self.auth.is_some() {
-                let auth_token = self.auth.unwrap();
-                stream.reset();
-                if let Response::Json(s) = utils::auth(&mut stream, auth_token, &mut buf).await? {
-                    let auth: Authentication = serde_json::from_str(&s).expect("json deserialize error");
-                    info!("AUTH: {:?}", auth);
-                }
-            }
-            let msg = future.await;
-            msg.encode(&mut buf);
-            stream.reset();
-            io_pub(&mut stream, &mut buf).await
+        let mut producer = self.producer().await?;
+        let cmd = future.await;
+        let span = span!(parent: &producer.span, "nsq.publish");
+        trace::instrument(producer.send(cmd), span).await
+    }
+}
+
+/// Verifies the certificate of nsqd as rustls does and keeps it for
+/// [ConnectionEvent::TlsEstablished](events/enum.ConnectionEvent.html#variant.TlsEstablished).
+struct PeerVerifier {
+    /// Holds the default (webpki) verifier, rustls 0.16 doesn't export it.
+    defaults: ClientConfig,
+    certificate: Mutex<Option<Vec<u8>>>,
+}
+
+impl PeerVerifier {
+    fn new() -> Self {
+        PeerVerifier {
+            defaults: ClientConfig::new(),
+            certificate: Mutex::new(None),
         }
     }
+
+    fn certificate(&self) -> Option<Vec<u8>> {
+        self.certificate.lock().expect("peer certificate lock poisoned").clone()
+    }
+}
+
+impl ServerCertVerifier for PeerVerifier {
+    fn verify_server_cert(
+        &self,
+        roots: &RootCertStore,
+        presented_certs: &[Certificate],
+        dns_name: webpki::DNSNameRef,
+        ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        let webpki = self.defaults.get_verifier();
+        let verified = webpki.verify_server_cert(roots, presented_certs, dns_name, ocsp_response)?;
+        let certificate = presented_certs.first().map(|c| c.0.clone());
+        *self.certificate.lock().expect("peer certificate lock poisoned") = certificate;
+        Ok(verified)
+    }
 }
 
-async fn connect<ADDR: ToSocketAddrs + Debug>(addr: ADDR) -> NsqResult<TcpStream> {
-    debug!("Trying to connect to: {:?}", addr);
-    match TcpStream::connect(addr).await {
-        Ok(s) => {
-            debug!("Connected: {:?}", s.peer_addr());
-            Ok(s)
-        },
-        Err(e) => Err(NsqError::from(e)),
+/// TLS connector trusting `cafile` (or the webpki roots) and presenting the client
+/// certificate of `config`, if any.
+async fn tls_connector(
+    config: &Config,
+    cafile: Option<&PathBuf>,
+    verifier: Arc<PeerVerifier>,
+) -> io::Result<TlsConnector> {
+    let mut tls = ClientConfig::new();
+    tls.dangerous().set_certificate_verifier(verifier);
+    match cafile {
+        Some(cafile) => {
+            let mut pem = Cursor::new(rt::read_file(cafile).await?);
+            tls.root_store
+                .add_pem_file(&mut pem)
+                .map_err(|_| invalid_input("invalid cert"))?;
+        }
+        None => tls.root_store.add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS),
+    }
+    if let (Some(cert), Some(key)) = (&config.tls_cert, &config.tls_key) {
+        let certs = pemfile::certs(&mut Cursor::new(rt::read_file(cert).await?))
+            .map_err(|_| invalid_input("invalid client cert"))?;
+        let key_pem = rt::read_file(key).await?;
+        let mut keys = pemfile::pkcs8_private_keys(&mut Cursor::new(&key_pem)).unwrap_or_default();
+        if keys.is_empty() {
+            keys = pemfile::rsa_private_keys(&mut Cursor::new(&key_pem)).unwrap_or_default();
+        }
+        let key = keys.into_iter().next().ok_or_else(|| invalid_input("invalid client key"))?;
+        tls.set_single_client_cert(certs, key);
     }
+    Ok(TlsConnector::from(Arc::new(tls)))
 }
 
-async fn connector_from_cafile(cafile: &Path) -> io::Result<TlsConnector> {
-    let mut config = ClientConfig::new();
-    let file = async_std::fs::read(cafile).await?;
-    let mut pem = Cursor::new(file);
-    config
-        .root_store
-        .add_pem_file(&mut pem)
-        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid cert"))?;
-    Ok(TlsConnector::from(Arc::new(config)))
+fn invalid_input(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg.to_owned())
 }
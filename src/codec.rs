@@ -21,27 +21,30 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use crate::error::NsqError;
+use crate::result::NsqResult;
+use crate::topic::{Channel, Topic};
 use bytes::{BufMut, BytesMut};
-use std::str;
+use std::time::Duration;
 
 use byteorder::{BigEndian, ByteOrder};
 
 pub trait Encoder {
     fn encode(self, buf: &mut BytesMut);
+    /// Name of the command, e.g. `PUB`, checked against the state of the connection.
+    fn name(&self) -> &'static str;
 }
 
-pub trait Decoder {
-    fn decode<MSG: Message>(buf: BytesMut) -> MSG;
-}
-
-pub trait Message {}
-
 pub struct Magic;
 
 impl Encoder for Magic {
     fn encode(self, buf: &mut BytesMut) {
         buf.put(&b"  V2"[..]);
     }
+
+    fn name(&self) -> &'static str {
+        "MAGIC"
+    }
 }
 
 pub struct Identify<'a>(&'a str);
@@ -60,6 +63,10 @@ impl<'a> Encoder for Identify<'a> {
         buf.put_u32_be(len as u32);
         buf.put(self.0.as_bytes());
     }
+
+    fn name(&self) -> &'static str {
+        "IDENTIFY"
+    }
 }
 
 pub struct Auth<'a>(&'a str);
@@ -78,26 +85,40 @@ impl<'a> Encoder for Auth<'a> {
         buf.put_u32_be(len as u32);
         buf.put(self.0.as_bytes());
     }
+
+    fn name(&self) -> &'static str {
+        "AUTH"
+    }
 }
 
-pub struct Sub<'a>(&'a str, &'a str);
+pub struct Sub<'a>(&'a Topic, &'a Channel);
+
+impl<'a> Sub<'a> {
+    pub fn new(topic: &'a Topic, channel: &'a Channel) -> Self {
+        Sub(topic, channel)
+    }
+}
 
 impl<'a> Encoder for Sub<'a> {
     fn encode(self, buf: &mut BytesMut) {
-        let len = self.0.len() + self.1.len();
+        let len = self.0.as_str().len() + self.1.as_str().len();
         check_and_reserve(buf, 6 + len);
         buf.put(&b"SUB "[..]);
-        buf.put(self.0.as_bytes());
+        buf.put(self.0.as_str().as_bytes());
         buf.put(&b" "[..]);
-        buf.put(self.1.as_bytes());
+        buf.put(self.1.as_str().as_bytes());
         buf.put(&b"\n"[..]);
     }
+
+    fn name(&self) -> &'static str {
+        "SUB"
+    }
 }
 
-pub struct Pub(String, Vec<u8>);
+pub struct Pub(Topic, Vec<u8>);
 
 impl Pub {
-    pub fn new(topic: String, msg: Vec<u8>) -> Self {
+    pub fn new(topic: Topic, msg: Vec<u8>) -> Self {
         Pub(topic, msg)
     }
 }
@@ -105,51 +126,194 @@ impl Pub {
 impl Encoder for Pub {
     fn encode(self, buf: &mut BytesMut) {
         let msg_len = self.1.len();
-        let len = self.0.len() + msg_len;
+        let len = self.0.as_str().len() + msg_len;
         check_and_reserve(buf, 9 + len);
         buf.put(&b"PUB "[..]);
-        buf.put(self.0.as_bytes());
+        buf.put(self.0.as_str().as_bytes());
         buf.put(&b"\n"[..]);
         buf.put_u32_be(msg_len as u32);
         buf.put(self.1.as_slice());
     }
+
+    fn name(&self) -> &'static str {
+        "PUB"
+    }
 }
 
-pub struct Mpub(String, Vec<Vec<u8>>);
+pub struct Mpub(Topic, Vec<Vec<u8>>);
+
+impl Mpub {
+    /// Multiple publish, nsqd rejects an MPUB without messages so `msgs` must not be empty.
+    pub fn new(topic: Topic, msgs: Vec<Vec<u8>>) -> NsqResult<Self> {
+        if msgs.is_empty() {
+            return Err(NsqError::Body);
+        }
+        Ok(Mpub(topic, msgs))
+    }
+}
 
 impl Encoder for Mpub {
     fn encode(self, buf: &mut BytesMut) {
         let num_msgs = self.1.len();
         let total_msgs_len = self.1.iter().fold(0, |acc, e| { acc + e.len() + 4 });
-        let len = self.0.len();
+        let len = self.0.as_str().len();
         check_and_reserve(buf, 14 + len + total_msgs_len);
         buf.put(&b"MPUB "[..]);
-        buf.put(self.0.as_bytes());
+        buf.put(self.0.as_str().as_bytes());
         buf.put(&b"\n"[..]);
-        buf.put_u32_be(total_msgs_len as u32);
+        buf.put_u32_be(total_msgs_len as u32 + 4);
         buf.put_u32_be(num_msgs as u32);
         for msg in self.1 {
             buf.put_u32_be(msg.len() as u32);
             buf.put(msg);
         }
     }
+
+    fn name(&self) -> &'static str {
+        "MPUB"
+    }
 }
 
-pub struct Dpub(String, String, Vec<u8>);
+pub struct Dpub(Topic, String, Vec<u8>);
+
+impl Dpub {
+    /// Deferred publish, `delay` is sent to nsqd with milliseconds precision.
+    pub fn new(topic: Topic, delay: Duration, msg: Vec<u8>) -> Self {
+        Dpub(topic, delay.as_millis().to_string(), msg)
+    }
+}
 
 impl Encoder for Dpub {
     fn encode(self, buf: &mut BytesMut) {
         let msg_len = self.2.len();
-        let len = self.0.len() + self.1.len() + msg_len;
+        let len = self.0.as_str().len() + self.1.len() + msg_len;
         check_and_reserve(buf, 11 + len);
         buf.put(&b"DPUB "[..]);
-        buf.put(self.0.as_bytes());
+        buf.put(self.0.as_str().as_bytes());
         buf.put(&b" "[..]);
         buf.put(self.1.as_bytes());
         buf.put(&b"\n"[..]);
         buf.put_u32_be(msg_len as u32);
         buf.put(self.2.as_slice());
     }
+
+    fn name(&self) -> &'static str {
+        "DPUB"
+    }
+}
+
+pub struct Rdy(u32);
+
+impl Rdy {
+    pub fn new(count: u32) -> Self {
+        Rdy(count)
+    }
+}
+
+impl Encoder for Rdy {
+    fn encode(self, buf: &mut BytesMut) {
+        let count = self.0.to_string();
+        check_and_reserve(buf, 5 + count.len());
+        buf.put(&b"RDY "[..]);
+        buf.put(count.as_bytes());
+        buf.put(&b"\n"[..]);
+    }
+
+    fn name(&self) -> &'static str {
+        "RDY"
+    }
+}
+
+pub struct Fin<'a>(&'a str);
+
+impl<'a> Fin<'a> {
+    pub fn new(id: &'a str) -> Self {
+        Fin(id)
+    }
+}
+
+impl<'a> Encoder for Fin<'a> {
+    fn encode(self, buf: &mut BytesMut) {
+        check_and_reserve(buf, 5 + self.0.len());
+        buf.put(&b"FIN "[..]);
+        buf.put(self.0.as_bytes());
+        buf.put(&b"\n"[..]);
+    }
+
+    fn name(&self) -> &'static str {
+        "FIN"
+    }
+}
+
+pub struct Req<'a>(&'a str, String);
+
+impl<'a> Req<'a> {
+    /// Requeue, `delay` is sent to nsqd with milliseconds precision.
+    pub fn new(id: &'a str, delay: Duration) -> Self {
+        Req(id, delay.as_millis().to_string())
+    }
+}
+
+impl<'a> Encoder for Req<'a> {
+    fn encode(self, buf: &mut BytesMut) {
+        check_and_reserve(buf, 6 + self.0.len() + self.1.len());
+        buf.put(&b"REQ "[..]);
+        buf.put(self.0.as_bytes());
+        buf.put(&b" "[..]);
+        buf.put(self.1.as_bytes());
+        buf.put(&b"\n"[..]);
+    }
+
+    fn name(&self) -> &'static str {
+        "REQ"
+    }
+}
+
+pub struct Touch<'a>(&'a str);
+
+impl<'a> Touch<'a> {
+    pub fn new(id: &'a str) -> Self {
+        Touch(id)
+    }
+}
+
+impl<'a> Encoder for Touch<'a> {
+    fn encode(self, buf: &mut BytesMut) {
+        check_and_reserve(buf, 7 + self.0.len());
+        buf.put(&b"TOUCH "[..]);
+        buf.put(self.0.as_bytes());
+        buf.put(&b"\n"[..]);
+    }
+
+    fn name(&self) -> &'static str {
+        "TOUCH"
+    }
+}
+
+pub struct Cls;
+
+impl Encoder for Cls {
+    fn encode(self, buf: &mut BytesMut) {
+        check_and_reserve(buf, 4);
+        buf.put(&b"CLS\n"[..]);
+    }
+
+    fn name(&self) -> &'static str {
+        "CLS"
+    }
+}
+
+pub struct Nop;
+
+impl Encoder for Nop {
+    fn encode(self, buf: &mut BytesMut) {
+        check_and_reserve(buf, 4);
+        buf.put(&b"NOP\n"[..]);
+    }
+
+    fn name(&self) -> &'static str {
+        "NOP"
+    }
 }
 
 pub fn decode_msg(buf: &mut [u8]) -> (i64, u16, String, Vec<u8>) {
@@ -157,8 +321,8 @@ pub fn decode_msg(buf: &mut [u8]) -> (i64, u16, String, Vec<u8>) {
     let timestamp = BigEndian::read_i64(&buf[..8]);
     let attemps = BigEndian::read_u16(&buf[8..10]);
     let id_bytes = &buf[10..26];
-    let id = str::from_utf8(id_bytes).expect("failed to decode id message");
-    (timestamp, attemps, id.to_owned(), Vec::from(&buf[26..]))
+    let id = String::from_utf8_lossy(id_bytes);
+    (timestamp, attemps, id.into_owned(), Vec::from(&buf[26..]))
 }
 
 fn check_and_reserve(buf: &mut BytesMut, size: usize) {
@@ -166,3 +330,27 @@ fn check_and_reserve(buf: &mut BytesMut, size: usize) {
         buf.reserve(size);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mpub_body_size_counts_the_message_count() {
+        let topic = Topic::new("test").unwrap();
+        let mut buf = BytesMut::new();
+        Mpub::new(topic, vec![b"ab".to_vec(), b"cde".to_vec()]).unwrap().encode(&mut buf);
+        let mut expected = b"MPUB test\n".to_vec();
+        // message count, then size and body of every message
+        expected.extend_from_slice(&[0, 0, 0, 17, 0, 0, 0, 2]);
+        expected.extend_from_slice(&[0, 0, 0, 2, b'a', b'b']);
+        expected.extend_from_slice(&[0, 0, 0, 3, b'c', b'd', b'e']);
+        assert_eq!(&buf[..], &expected[..]);
+    }
+
+    #[test]
+    fn mpub_without_messages() {
+        let res = Mpub::new(Topic::new("test").unwrap(), Vec::new());
+        assert!(matches!(res, Err(NsqError::Body)));
+    }
+}
@@ -21,28 +21,38 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use serde::{Deserialize, Serialize};
+use crate::error::NsqError;
+use crate::result::NsqResult;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::env;
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-/// Configuration sent to nsqd to properly config the [Connection](struct.Connection.html)
+/// IDENTIFY sent to nsqd to configure the connection, plus client side settings.
+///
+/// Builders don't check values, [build](#method.build) validates the whole configuration
+/// and connections refuse an invalid one before opening the socket.
 ///
 /// # Examples
 ///```no-run
-/// use nsq_client::{Connection, Config};
+/// use nsq_rust::{Client, Config};
 ///
-/// let sys = System::new("consumer");
-/// let config = Config::new().client_id("consumer").user_agent("node-1");
-/// Supervisor::start(|_| Connection::new(
-///     "test",
-///     "test",
-///     "0.0.0.0:4150",
-///     Some(config),
-///     None,
-///     None,
-/// ));
-/// sys.run();
+/// let config = Config::new()
+///     .client_id("consumer")
+///     .user_agent("node-1")
+///     .heartbeat_interval(10000)
+///     .msg_timeout(120000)
+///     .build()?;
+/// let client = Client::new("localhost:4150", config, None, None);
 ///```
 ///
+/// Fields marked as client side are not sent to nsqd. A configuration can also be
+/// loaded from the environment with [from_env](#method.from_env) or from a file with
+/// [from_file](#method.from_file).
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
     /// Identifiers sent to nsqd representing this client (consumer specific)
     ///
@@ -54,7 +64,7 @@ pub struct Config {
     /// Default: **hostname** where connection is started
     pub hostname: Option<String>,
 
-    /// Enable feature_negotiation
+    /// Enable feature_negotiation, nsqd answers IDENTIFY with its own configuration.
     ///
     /// Default: **true**
     pub feature_negotiation: bool,
@@ -63,6 +73,7 @@ pub struct Config {
     ///
     /// Valid values:
     /// * -1 disables heartbeats
+    /// * 0 uses nsqd default
     /// * 1000 <= heartbeat_interval <= configured_max
     ///
     /// Default: **30000**
@@ -72,61 +83,127 @@ pub struct Config {
     ///
     /// Valid values:
     /// * -1 disable output buffer
+    /// * 0 uses nsqd default
     /// * 64 <= output_buffer_size <= configured_max
     ///
     /// Default: **16384**
-    pub output_buffer_size: u64,
+    pub output_buffer_size: i64,
 
-    /// The timeout after which data nsqd has buffered will be flushed to this client.
+    /// The timeout (milliseconds) after which data nsqd has buffered will be flushed to this client.
     ///
     /// Valid values:
     /// * -1 disable buffer timeout
-    /// * 1ms <= output_buffer_timeout <= configured_max
+    /// * 0 uses nsqd default
+    /// * 1 <= output_buffer_timeout <= configured_max
     ///
     /// Default: **250**
-    pub output_buffer_timeout: u32,
+    pub output_buffer_timeout: i64,
 
-    /// Enable TLS negotiation
+    /// Enable TLS negotiation, the connection is upgraded if nsqd supports it.
     ///
-    /// Default: **false** (Not implemented)
-    tls_v1: bool,
+    /// Default: **true**
+    pub tls_v1: bool,
 
     /// Enable snappy compression.
     ///
-    /// Default: **false** (Not implemented)
+    /// Default: **false** (not supported by this client yet)
     pub snappy: bool,
 
     /// Enable deflate compression.
     ///
-    /// Default: **false** (Not implemented)
-    deflate: bool,
+    /// Default: **false** (not supported by this client yet)
+    pub deflate: bool,
+
     /// Configure deflate compression level.
     ///
     /// Valid range:
     /// * 1 <= deflate_level <= configured_max
     ///
     /// Default: **6**
-    deflate_level: u16,
+    pub deflate_level: u16,
 
     /// Integer percentage to sample the channel.
     ///
-    /// Deliver a perventage of all messages received to this connection.
+    /// Deliver a percentage of all messages received to this connection.
+    ///
+    /// Valid range:
+    /// * 0 <= sample_rate <= 99 (0 disables sampling)
     ///
     /// Default: **0**
     pub sample_rate: u16,
 
     /// String indentifying the agent for this connection.
     ///
-    /// Default: **hostname** where connection is started
+    /// Default: **rust nsq**
     pub user_agent: String,
 
-    /// Timeout used by nsqd before flushing buffered writes (set to 0 to disable).
+    /// Time (milliseconds) nsqd waits for FIN, REQ or TOUCH before requeueing a message
+    /// delivered to this client.
+    ///
+    /// Valid values:
+    /// * 0 uses nsqd default
+    /// * 1000 <= msg_timeout <= configured_max
     ///
     /// Default: **0**
-    pub message_timeout: u32,
-}
-use hostname;
+    pub msg_timeout: u32,
+
+    /// Maximum number of messages a consumer allows in flight (client side, not sent to nsqd).
+    ///
+    /// Default: **1**
+    #[serde(skip_serializing)]
+    pub max_in_flight: u32,
+
+    /// Unit of time (milliseconds) multiplied by the number of consecutive failures
+    /// to compute the back off delay (client side).
+    ///
+    /// Default: **1000**
+    #[serde(skip_serializing)]
+    pub backoff_multiplier: u64,
+
+    /// Maximum back off delay (milliseconds) (client side).
+    ///
+    /// Default: **120000**
+    #[serde(skip_serializing)]
+    pub max_backoff_duration: u64,
+
+    /// nsqd TCP addresses to connect to (client side).
+    ///
+    /// Default: **empty**
+    #[serde(skip_serializing)]
+    pub nsqd_tcp_addresses: Vec<String>,
+
+    /// nsqlookupd HTTP addresses used to discover nsqd (client side).
+    ///
+    /// Default: **empty**
+    #[serde(skip_serializing)]
+    pub lookupd_http_addresses: Vec<String>,
+
+    /// PEM file with the CA certificates used to verify nsqd, when not given to
+    /// [Client::new](struct.Client.html#method.new) (client side).
+    ///
+    /// Default: **None**, the webpki roots are used
+    #[serde(skip_serializing)]
+    pub tls_root_ca_file: Option<PathBuf>,
 
+    /// PEM file with the client certificate chain, requires [tls_key](#structfield.tls_key) (client side).
+    ///
+    /// Default: **None**
+    #[serde(skip_serializing)]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM file with the client private key (PKCS8 or RSA) (client side).
+    ///
+    /// Default: **None**
+    #[serde(skip_serializing)]
+    pub tls_key: Option<PathBuf>,
+
+    /// Secret sent with AUTH when nsqd requires it, when not given to
+    /// [Client::new](struct.Client.html#method.new) (client side).
+    ///
+    /// Default: **None**
+    #[serde(skip_serializing)]
+    pub auth_secret: Option<String>,
+}
 fn get_hostname() -> Option<String> {
     if let Ok(h) = hostname::get() {
         if let Ok(s) = h.into_string() {
@@ -149,36 +226,60 @@ impl Default for Config {
             tls_v1: true,
             feature_negotiation: true,
             heartbeat_interval: 30000,
-            message_timeout: 0,
+            msg_timeout: 0,
             output_buffer_size: 16384,
             output_buffer_timeout: 250,
             sample_rate: 0,
+            max_in_flight: 1,
+            backoff_multiplier: 1000,
+            max_backoff_duration: 120_000,
+            nsqd_tcp_addresses: Vec::new(),
+            lookupd_http_addresses: Vec::new(),
+            tls_root_ca_file: None,
+            tls_cert: None,
+            tls_key: None,
+            auth_secret: None,
         }
     }
 }
 
+/// IDENTIFY response, the configuration nsqd negotiated for the connection.
+///
+/// nsqd answers with a plain `OK` when feature negotiation is disabled, in that
+/// case every field keeps its default value.
 #[derive(Clone, Debug, Deserialize, PartialEq, Default)]
+#[serde(default)]
 pub struct NsqConfig {
-    pub max_rdy_count: u32,
+    /// Maximum RDY count nsqd accepts from a client.
+    pub max_rdy_count: i64,
+    /// nsqd version.
     pub version: String,
-    pub max_msg_timeout: u64,
-    pub msg_timeout: u64,
+    /// Maximum msg_timeout (milliseconds) a client can request.
+    pub max_msg_timeout: i64,
+    /// Message timeout (milliseconds) used for this connection.
+    pub msg_timeout: i64,
+    /// The connection must be upgraded to TLS.
     pub tls_v1: bool,
+    /// The connection must be upgraded to deflate.
     pub deflate: bool,
-    pub deflate_level: u16,
-    pub max_deflate_level: u16,
+    pub deflate_level: i32,
+    /// Maximum deflate_level a client can request.
+    pub max_deflate_level: i32,
+    /// The connection must be upgraded to snappy.
     pub snappy: bool,
-    pub sample_rate: u16,
+    /// Percentage of the channel's messages delivered to the connection, 0 for all.
+    pub sample_rate: i32,
+    /// AUTH is required before publishing or subscribing.
     pub auth_required: bool,
-    pub output_buffer_size: u64,
-    pub output_buffer_timeout: u32,
+    pub output_buffer_size: i64,
+    /// Output buffer timeout (milliseconds).
+    pub output_buffer_timeout: i64,
 }
 
-#[allow(dead_code)]
 impl Config {
     /// Create default [Config](struct.Config.html)
     /// ```no-run
-    /// use nsq_client::{Config};
+    /// use nsq_rust::Config;
     ///
     /// let config = Config::new();
     /// assert_eq!(config, Config::default());
@@ -191,7 +292,7 @@ impl Config {
 
     /// Change [client_id](struct.Config.html#structfield.client_id)
     /// ```no-run
-    /// use nsq_client::Config;
+    /// use nsq_rust::Config;
     ///
     /// let config = Config::new().client_id("consumer");
     /// assert_eq!(config.client_id, Some("consumer".to_owned()));
@@ -203,7 +304,7 @@ impl Config {
 
     /// Change [hostname](struct.Config.html#structfield.hostname)
     /// ```no-run
-    /// use nsq_client::Config;
+    /// use nsq_rust::Config;
     ///
     /// let config = Config::new().hostname("node-1");
     /// assert_eq!(config.hostname, Some("node-1".to_owned()));
@@ -215,17 +316,485 @@ impl Config {
 
     /// Change [user_agent](struct.Config.html#structfield.user_agent)
     /// ```no-run
-    /// use nsq_client::Config;
+    /// use nsq_rust::Config;
     ///
     /// let config = Config::new().user_agent("consumer-1");
-    /// assert_eq!(config.user_agent, Some("consumer-1".to_owned()));
+    /// assert_eq!(config.user_agent, "consumer-1");
     /// ```
     pub fn user_agent<UA: Into<String>>(mut self, user_agent: UA) -> Self {
         self.user_agent = user_agent.into();
         self
     }
 
-    pub fn tls_v1(&mut self, tls: bool) {
-        self.tls_v1 = tls;
+    /// Change [feature_negotiation](struct.Config.html#structfield.feature_negotiation)
+    pub fn feature_negotiation(mut self, feature_negotiation: bool) -> Self {
+        self.feature_negotiation = feature_negotiation;
+        self
+    }
+
+    /// Change [heartbeat_interval](struct.Config.html#structfield.heartbeat_interval)
+    pub fn heartbeat_interval(mut self, heartbeat_interval: i64) -> Self {
+        self.heartbeat_interval = heartbeat_interval;
+        self
+    }
+
+    /// Change [output_buffer_size](struct.Config.html#structfield.output_buffer_size)
+    pub fn output_buffer_size(mut self, output_buffer_size: i64) -> Self {
+        self.output_buffer_size = output_buffer_size;
+        self
+    }
+
+    /// Change [output_buffer_timeout](struct.Config.html#structfield.output_buffer_timeout)
+    pub fn output_buffer_timeout(mut self, output_buffer_timeout: i64) -> Self {
+        self.output_buffer_timeout = output_buffer_timeout;
+        self
+    }
+
+    /// Change [tls_v1](struct.Config.html#structfield.tls_v1)
+    pub fn tls_v1(mut self, tls_v1: bool) -> Self {
+        self.tls_v1 = tls_v1;
+        self
+    }
+
+    /// Change [snappy](struct.Config.html#structfield.snappy)
+    pub fn snappy(mut self, snappy: bool) -> Self {
+        self.snappy = snappy;
+        self
+    }
+
+    /// Change [deflate](struct.Config.html#structfield.deflate)
+    pub fn deflate(mut self, deflate: bool) -> Self {
+        self.deflate = deflate;
+        self
+    }
+
+    /// Change [deflate_level](struct.Config.html#structfield.deflate_level)
+    pub fn deflate_level(mut self, deflate_level: u16) -> Self {
+        self.deflate_level = deflate_level;
+        self
+    }
+
+    /// Change [sample_rate](struct.Config.html#structfield.sample_rate)
+    pub fn sample_rate(mut self, sample_rate: u16) -> Self {
+        self.sample_rate = sample_rate;
+        self
+    }
+
+    /// Change [msg_timeout](struct.Config.html#structfield.msg_timeout)
+    /// ```no-run
+    /// use nsq_rust::Config;
+    ///
+    /// let config = Config::new().msg_timeout(120000);
+    /// assert_eq!(config.msg_timeout, 120000);
+    /// ```
+    pub fn msg_timeout(mut self, msg_timeout: u32) -> Self {
+        self.msg_timeout = msg_timeout;
+        self
+    }
+
+    /// Change [max_in_flight](struct.Config.html#structfield.max_in_flight)
+    /// ```no-run
+    /// use nsq_rust::Config;
+    ///
+    /// let config = Config::new().max_in_flight(100);
+    /// assert_eq!(config.max_in_flight, 100);
+    /// ```
+    pub fn max_in_flight(mut self, max_in_flight: u32) -> Self {
+        self.max_in_flight = max_in_flight;
+        self
+    }
+
+    /// Change [backoff_multiplier](struct.Config.html#structfield.backoff_multiplier)
+    pub fn backoff_multiplier(mut self, backoff_multiplier: u64) -> Self {
+        self.backoff_multiplier = backoff_multiplier;
+        self
+    }
+
+    /// Change [max_backoff_duration](struct.Config.html#structfield.max_backoff_duration)
+    pub fn max_backoff_duration(mut self, max_backoff_duration: u64) -> Self {
+        self.max_backoff_duration = max_backoff_duration;
+        self
+    }
+
+    /// Change [nsqd_tcp_addresses](struct.Config.html#structfield.nsqd_tcp_addresses)
+    pub fn nsqd_tcp_addresses<I: IntoIterator<Item = S>, S: Into<String>>(mut self, addresses: I) -> Self {
+        self.nsqd_tcp_addresses = addresses.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Change [lookupd_http_addresses](struct.Config.html#structfield.lookupd_http_addresses)
+    pub fn lookupd_http_addresses<I: IntoIterator<Item = S>, S: Into<String>>(mut self, addresses: I) -> Self {
+        self.lookupd_http_addresses = addresses.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Change [tls_root_ca_file](struct.Config.html#structfield.tls_root_ca_file)
+    pub fn tls_root_ca_file<P: Into<PathBuf>>(mut self, cafile: P) -> Self {
+        self.tls_root_ca_file = Some(cafile.into());
+        self
+    }
+
+    /// Change [tls_cert](struct.Config.html#structfield.tls_cert) and [tls_key](struct.Config.html#structfield.tls_key)
+    pub fn tls_client_cert<C: Into<PathBuf>, K: Into<PathBuf>>(mut self, cert: C, key: K) -> Self {
+        self.tls_cert = Some(cert.into());
+        self.tls_key = Some(key.into());
+        self
+    }
+
+    /// Change [auth_secret](struct.Config.html#structfield.auth_secret)
+    pub fn auth_secret<S: Into<String>>(mut self, secret: S) -> Self {
+        self.auth_secret = Some(secret.into());
+        self
+    }
+
+    /// Load the configuration from the environment variables named after the fields,
+    /// uppercase and prefixed, e.g. `NSQ_HEARTBEAT_INTERVAL` with prefix `NSQ`.
+    ///
+    /// Unset variables keep the default, lists are comma separated and booleans are
+    /// `true` or `false`. The variable name is reported as field in errors.
+    /// ```no-run
+    /// use nsq_rust::Config;
+    ///
+    /// std::env::set_var("NSQ_MAX_IN_FLIGHT", "100");
+    /// std::env::set_var("NSQ_LOOKUPD_HTTP_ADDRESSES", "lookupd-1:4161,lookupd-2:4161");
+    /// let config = Config::from_env("NSQ")?;
+    /// assert_eq!(config.max_in_flight, 100);
+    /// ```
+    pub fn from_env(prefix: &str) -> NsqResult<Config> {
+        let env = Env::new(prefix);
+        let mut config = Config::new();
+        env.string("CLIENT_ID", &mut config.client_id);
+        env.string("HOSTNAME", &mut config.hostname);
+        env.parse("FEATURE_NEGOTIATION", &mut config.feature_negotiation)?;
+        env.parse("HEARTBEAT_INTERVAL", &mut config.heartbeat_interval)?;
+        env.parse("OUTPUT_BUFFER_SIZE", &mut config.output_buffer_size)?;
+        env.parse("OUTPUT_BUFFER_TIMEOUT", &mut config.output_buffer_timeout)?;
+        env.parse("TLS_V1", &mut config.tls_v1)?;
+        env.parse("SNAPPY", &mut config.snappy)?;
+        env.parse("DEFLATE", &mut config.deflate)?;
+        env.parse("DEFLATE_LEVEL", &mut config.deflate_level)?;
+        env.parse("SAMPLE_RATE", &mut config.sample_rate)?;
+        env.parse("USER_AGENT", &mut config.user_agent)?;
+        env.parse("MSG_TIMEOUT", &mut config.msg_timeout)?;
+        env.parse("MAX_IN_FLIGHT", &mut config.max_in_flight)?;
+        env.parse("BACKOFF_MULTIPLIER", &mut config.backoff_multiplier)?;
+        env.parse("MAX_BACKOFF_DURATION", &mut config.max_backoff_duration)?;
+        env.list("NSQD_TCP_ADDRESSES", &mut config.nsqd_tcp_addresses);
+        env.list("LOOKUPD_HTTP_ADDRESSES", &mut config.lookupd_http_addresses);
+        env.path("TLS_ROOT_CA_FILE", &mut config.tls_root_ca_file);
+        env.path("TLS_CERT", &mut config.tls_cert);
+        env.path("TLS_KEY", &mut config.tls_key);
+        env.string("AUTH_SECRET", &mut config.auth_secret);
+        config.build().map_err(|e| match e {
+            NsqError::Config(field, reason) => NsqError::Config(env.key(&field.to_uppercase()), reason),
+            e => e,
+        })
+    }
+
+    /// Load the configuration from a file, the format is chosen by extension:
+    /// `.json`, `.toml` (`toml` feature) or `.yaml`/`.yml` (`yaml` feature).
+    ///
+    /// Missing fields keep the default, errors report the path of the invalid field.
+    /// ```no-run
+    /// use nsq_rust::Config;
+    ///
+    /// let config = Config::from_file("/etc/consumer/nsq.toml")?;
+    /// ```
+    pub fn from_file<P: AsRef<Path>>(path: P) -> NsqResult<Config> {
+        let path = path.as_ref();
+        let name = path.display().to_string();
+        let content = fs::read_to_string(path).map_err(|e| invalid(&name, &e.to_string()))?;
+        let config = match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => deserialize(&mut serde_json::Deserializer::from_str(&content), ToString::to_string)?,
+            #[cfg(feature = "toml")]
+            Some("toml") => {
+                let de = toml::Deserializer::parse(&content).map_err(|e| invalid(&name, e.message()))?;
+                deserialize(de, |e: &toml::de::Error| e.message().to_owned())?
+            }
+            #[cfg(feature = "yaml")]
+            Some("yaml") | Some("yml") => deserialize(serde_yaml::Deserializer::from_str(&content), ToString::to_string)?,
+            _ => return Err(invalid(&name, "unsupported file format")),
+        };
+        config.build()
+    }
+
+    /// Validate and return the configuration.
+    /// ```no-run
+    /// use nsq_rust::Config;
+    ///
+    /// assert!(Config::new().sample_rate(100).build().is_err());
+    /// ```
+    pub fn build(self) -> NsqResult<Self> {
+        self.validate()?;
+        Ok(self)
+    }
+
+    /// Check the ranges nsqd accepts and the features this client supports.
+    pub fn validate(&self) -> NsqResult<()> {
+        if self.heartbeat_interval < -1 || (self.heartbeat_interval > 0 && self.heartbeat_interval < 1000) {
+            return Err(invalid("heartbeat_interval", "must be -1, 0 or at least 1000ms"));
+        }
+        if self.output_buffer_size < -1 || (self.output_buffer_size > 0 && self.output_buffer_size < 64) {
+            return Err(invalid("output_buffer_size", "must be -1, 0 or at least 64 bytes"));
+        }
+        if self.output_buffer_timeout < -1 {
+            return Err(invalid("output_buffer_timeout", "must be -1, 0 or at least 1ms"));
+        }
+        if self.sample_rate > 99 {
+            return Err(invalid("sample_rate", "must be between 0 and 99"));
+        }
+        if self.msg_timeout > 0 && self.msg_timeout < 1000 {
+            return Err(invalid("msg_timeout", "must be 0 or at least 1000ms"));
+        }
+        if self.deflate && (self.deflate_level < 1 || self.deflate_level > 9) {
+            return Err(invalid("deflate_level", "must be between 1 and 9"));
+        }
+        if self.deflate && self.snappy {
+            return Err(invalid("deflate", "can't be enabled together with snappy"));
+        }
+        if self.snappy {
+            return Err(invalid("snappy", "compression is not supported"));
+        }
+        if self.deflate {
+            return Err(invalid("deflate", "compression is not supported"));
+        }
+        if self.max_in_flight == 0 {
+            return Err(invalid("max_in_flight", "must be at least 1"));
+        }
+        if self.backoff_multiplier == 0 {
+            return Err(invalid("backoff_multiplier", "must be at least 1ms"));
+        }
+        if self.tls_cert.is_some() != self.tls_key.is_some() {
+            let field = if self.tls_cert.is_some() { "tls_key" } else { "tls_cert" };
+            return Err(invalid(field, "tls_cert and tls_key must be set together"));
+        }
+        Ok(())
+    }
+
+    /// Check the configuration nsqd negotiated against the one requested.
+    pub fn validate_negotiated(&self, nsqd: &NsqConfig) -> NsqResult<()> {
+        if !self.feature_negotiation {
+            return Ok(());
+        }
+        if nsqd.max_msg_timeout > 0 && i64::from(self.msg_timeout) > nsqd.max_msg_timeout {
+            return Err(invalid("msg_timeout", &format!("nsqd maximum is {}ms", nsqd.max_msg_timeout)));
+        }
+        if self.deflate && nsqd.max_deflate_level > 0 && i32::from(self.deflate_level) > nsqd.max_deflate_level {
+            return Err(invalid("deflate_level", &format!("nsqd maximum is {}", nsqd.max_deflate_level)));
+        }
+        if nsqd.snappy {
+            return Err(invalid("snappy", "nsqd enabled compression, which is not supported"));
+        }
+        if nsqd.deflate {
+            return Err(invalid("deflate", "nsqd enabled compression, which is not supported"));
+        }
+        if i32::from(self.sample_rate) != nsqd.sample_rate {
+            let reason = format!("requested {}, nsqd negotiated {}", self.sample_rate, nsqd.sample_rate);
+            return Err(invalid("sample_rate", &reason));
+        }
+        if nsqd.max_rdy_count > 0 && i64::from(self.max_in_flight) > nsqd.max_rdy_count {
+            return Err(invalid("max_in_flight", &format!("nsqd maximum RDY count is {}", nsqd.max_rdy_count)));
+        }
+        Ok(())
+    }
+}
+
+fn invalid(field: &str, reason: &str) -> NsqError {
+    NsqError::Config(field.to_owned(), reason.to_owned())
+}
+
+fn deserialize<'de, D, F>(de: D, message: F) -> NsqResult<Config>
+where
+    D: Deserializer<'de>,
+    F: Fn(&D::Error) -> String,
+{
+    serde_path_to_error::deserialize(de).map_err(|e| invalid(&e.path().to_string(), &message(e.inner())))
+}
+
+/// Environment variables named `{prefix}_{name}`.
+struct Env {
+    prefix: String,
+}
+
+impl Env {
+    fn new(prefix: &str) -> Self {
+        Env {
+            prefix: prefix.trim_end_matches('_').to_owned(),
+        }
+    }
+
+    fn key(&self, name: &str) -> String {
+        if self.prefix.is_empty() {
+            name.to_owned()
+        } else {
+            format!("{}_{}", self.prefix, name)
+        }
+    }
+
+    fn var(&self, name: &str) -> Option<String> {
+        env::var(self.key(name)).ok()
+    }
+
+    fn parse<T>(&self, name: &str, field: &mut T) -> NsqResult<()>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        if let Some(value) = self.var(name) {
+            *field = value
+                .trim()
+                .parse()
+                .map_err(|e| invalid(&self.key(name), &format!("invalid value {:?}: {}", value, e)))?;
+        }
+        Ok(())
+    }
+
+    fn string(&self, name: &str, field: &mut Option<String>) {
+        if let Some(value) = self.var(name) {
+            *field = Some(value);
+        }
+    }
+
+    fn path(&self, name: &str, field: &mut Option<PathBuf>) {
+        if let Some(value) = self.var(name) {
+            *field = Some(PathBuf::from(value));
+        }
+    }
+
+    fn list(&self, name: &str, field: &mut Vec<String>) {
+        if let Some(value) = self.var(name) {
+            *field = value
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(str::to_owned)
+                .collect();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Field reported by a configuration error.
+    fn field<T: std::fmt::Debug>(res: NsqResult<T>) -> String {
+        match res {
+            Err(NsqError::Config(field, _)) => field,
+            res => panic!("expected a config error, got {:?}", res),
+        }
+    }
+
+    fn negotiated() -> NsqConfig {
+        NsqConfig {
+            max_rdy_count: 2500,
+            max_msg_timeout: 900_000,
+            msg_timeout: 60_000,
+            ..NsqConfig::default()
+        }
+    }
+
+    #[test]
+    fn validate() {
+        assert!(Config::new().build().is_ok());
+        assert!(Config::new().sample_rate(99).build().is_ok());
+        assert_eq!(field(Config::new().sample_rate(100).build()), "sample_rate");
+        assert_eq!(field(Config::new().msg_timeout(999).build()), "msg_timeout");
+        assert_eq!(field(Config::new().max_in_flight(0).build()), "max_in_flight");
+        assert_eq!(field(Config::new().backoff_multiplier(0).build()), "backoff_multiplier");
+        assert_eq!(field(Config::new().heartbeat_interval(999).build()), "heartbeat_interval");
+        let config = Config { tls_cert: Some(PathBuf::from("cert.pem")), ..Config::new() };
+        assert_eq!(field(config.build()), "tls_key");
+    }
+
+    #[test]
+    fn validate_negotiated() {
+        let config = Config::new().msg_timeout(60_000).max_in_flight(2500);
+        assert!(config.validate_negotiated(&negotiated()).is_ok());
+
+        let res = Config::new().msg_timeout(900_001).validate_negotiated(&negotiated());
+        assert_eq!(field(res), "msg_timeout");
+        let res = Config::new().max_in_flight(2501).validate_negotiated(&negotiated());
+        assert_eq!(field(res), "max_in_flight");
+        let res = Config::new().sample_rate(10).validate_negotiated(&negotiated());
+        assert_eq!(field(res), "sample_rate");
+        let sampled = NsqConfig { sample_rate: 10, ..negotiated() };
+        assert!(Config::new().sample_rate(10).validate_negotiated(&sampled).is_ok());
+    }
+
+    #[test]
+    fn validate_negotiated_without_feature_negotiation() {
+        // nsqd answered IDENTIFY with OK, nothing was negotiated
+        let config = Config::new().feature_negotiation(false).sample_rate(10).max_in_flight(5000);
+        assert!(config.validate_negotiated(&NsqConfig::default()).is_ok());
+    }
+
+    #[test]
+    fn from_env() {
+        env::set_var("NSQ_TEST_ENV_MAX_IN_FLIGHT", "100");
+        env::set_var("NSQ_TEST_ENV_LOOKUPD_HTTP_ADDRESSES", "lookupd-1:4161, lookupd-2:4161,");
+        env::set_var("NSQ_TEST_ENV_TLS_V1", "true");
+        env::set_var("NSQ_TEST_ENV_CLIENT_ID", "worker");
+        let config = Config::from_env("NSQ_TEST_ENV_").unwrap();
+        assert_eq!(config.max_in_flight, 100);
+        assert_eq!(config.lookupd_http_addresses, vec!["lookupd-1:4161", "lookupd-2:4161"]);
+        assert!(config.tls_v1);
+        assert_eq!(config.client_id.as_deref(), Some("worker"));
+        assert_eq!(config.heartbeat_interval, Config::new().heartbeat_interval);
+    }
+
+    #[test]
+    fn from_env_errors() {
+        env::set_var("NSQ_TEST_PARSE_MAX_IN_FLIGHT", "many");
+        let res = Config::from_env("NSQ_TEST_PARSE");
+        match res {
+            Err(NsqError::Config(field, reason)) => {
+                assert_eq!(field, "NSQ_TEST_PARSE_MAX_IN_FLIGHT");
+                assert!(reason.contains("\"many\""), "{}", reason);
+            }
+            res => panic!("{:?}", res),
+        }
+        env::set_var("NSQ_TEST_BOOL_TLS_V1", "yes");
+        assert_eq!(field(Config::from_env("NSQ_TEST_BOOL")), "NSQ_TEST_BOOL_TLS_V1");
+        // validation errors are reported with the variable name too
+        env::set_var("NSQ_TEST_RANGE_SAMPLE_RATE", "100");
+        assert_eq!(field(Config::from_env("NSQ_TEST_RANGE")), "NSQ_TEST_RANGE_SAMPLE_RATE");
+    }
+
+    fn write(name: &str, content: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("nsq-rust-{}-{}", std::process::id(), name));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn from_file() {
+        let path = write("config.json", r#"{"max_in_flight": 10, "nsqd_tcp_addresses": ["nsqd:4150"]}"#);
+        let config = Config::from_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(config.max_in_flight, 10);
+        assert_eq!(config.nsqd_tcp_addresses, vec!["nsqd:4150"]);
+        assert_eq!(config.backoff_multiplier, 1000);
+    }
+
+    #[test]
+    fn from_file_errors() {
+        let path = write("mistyped.json", r#"{"max_in_flight": "ten"}"#);
+        let res = Config::from_file(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(field(res), "max_in_flight");
+
+        let path = write("invalid.json", r#"{"sample_rate": 100}"#);
+        let res = Config::from_file(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(field(res), "sample_rate");
+
+        let path = write("config.ini", "max_in_flight = 10");
+        let res = Config::from_file(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(field(res), path.display().to_string());
+
+        let missing = env::temp_dir().join("nsq-rust-missing.json");
+        assert_eq!(field(Config::from_file(&missing)), missing.display().to_string());
     }
 }
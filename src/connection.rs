@@ -0,0 +1,473 @@
+// MIT License
+//
+// Copyright (c) 2019-2021 Alessandro Cresto Miseroglio <alex179ohm@gmail.com>
+// Copyright (c) 2019-2021 Tangram Technologies S.R.L. <https://tngrm.io>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Connection to nsqd shared by producers and consumers, tracking the state of the protocol.
+//!
+//! The handshake and SUB run in request/response order on a [Connection](struct.Connection.html).
+//! Once done the connection is [started](struct.Connection.html#method.start): a reader task decodes
+//! the frames and answers heartbeats, a writer task batches the commands until they are flushed.
+//! Producers and consumers talk to both through a [Session](struct.Session.html), so messages keep
+//! being received while replies are written.
+
+use crate::auth::Authentication;
+use crate::codec::{Auth, Encoder, Identify, Magic, Nop, Sub};
+use crate::config::{Config, NsqConfig};
+use crate::error::NsqError;
+use crate::events::Hooks;
+use crate::io::{BoxedIo, NsqStream};
+use crate::metrics::{Meter, Metered};
+use crate::response::Response;
+use crate::result::NsqResult;
+use crate::rt;
+use crate::topic::{Channel, Topic};
+use async_tls::TlsConnector;
+use bytes::{Bytes, BytesMut};
+use futures::channel::mpsc::{self, Receiver, Sender, UnboundedReceiver, UnboundedSender};
+use futures::future::poll_fn;
+use futures::io::{AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
+use futures::{ready, select, FutureExt, SinkExt, Stream, StreamExt};
+use log::{debug, info};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Commands waiting for the writer task before `push` applies backpressure.
+const COMMAND_CHANNEL_SIZE: usize = 64;
+/// Frames waiting for the session before the reader task keeps them in its backlog.
+const RESPONSE_CHANNEL_SIZE: usize = 128;
+/// Frames in the backlog of the reader task before it stops reading, and answering heartbeats.
+const BACKLOG_SIZE: usize = 4096;
+
+/// State of a connection to nsqd, from the handshake to the end of a subscription.
+///
+/// Commands are checked against the state before being sent, as nsqd does: publishing
+/// needs a completed handshake, RDY needs a subscription and so on. Invalid commands
+/// fail with `NsqError::InvalidState` without reaching nsqd.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// TCP connection open, MAGIC sent.
+    Connecting,
+    /// IDENTIFY sent.
+    Identifying,
+    /// Upgrading to TLS, as negotiated in IDENTIFY.
+    UpgradingTls,
+    /// Upgrading to snappy or deflate, as negotiated in IDENTIFY (not supported yet).
+    Compressing,
+    /// AUTH sent.
+    Authenticating,
+    /// Handshake done, ready to publish or subscribe.
+    Ready,
+    /// SUB accepted, messages are delivered according to RDY.
+    Subscribed,
+    /// CLS sent, nsqd closes the connection once the messages in flight are done.
+    Closing,
+}
+
+impl ConnectionState {
+    /// Check the command `name` is valid in this state and move to the state it leads to.
+    fn command(&mut self, name: &'static str) -> NsqResult<()> {
+        use ConnectionState::*;
+        let state = *self;
+        match name {
+            "IDENTIFY" => self.transition(name, &[Connecting], Identifying),
+            "AUTH" => self.transition(name, &[Identifying, UpgradingTls, Compressing], Authenticating),
+            "PUB" | "MPUB" | "DPUB" => self.transition(name, &[Ready, Subscribed], state),
+            "SUB" => self.transition(name, &[Ready], Subscribed),
+            "RDY" => self.transition(name, &[Subscribed], Subscribed),
+            "FIN" | "REQ" | "TOUCH" => self.transition(name, &[Subscribed, Closing], state),
+            "CLS" => self.transition(name, &[Subscribed], Closing),
+            "NOP" => Ok(()),
+            _ => Err(NsqError::InvalidState(name, state)),
+        }
+    }
+
+    fn transition(&mut self, operation: &'static str, from: &[ConnectionState], to: ConnectionState) -> NsqResult<()> {
+        if !from.contains(self) {
+            return Err(NsqError::InvalidState(operation, *self));
+        }
+        *self = to;
+        Ok(())
+    }
+}
+
+impl fmt::Display for ConnectionState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// Socket to nsqd during the handshake, commands are sent one at a time.
+///
+/// Commands are buffered with [push](#method.push) and written with [flush](#method.flush),
+/// see [start](#method.start) once the handshake is done.
+pub(crate) struct Connection {
+    stream: NsqStream<BoxedIo>,
+    state: ConnectionState,
+    buf: BytesMut,
+}
+
+impl Connection {
+    /// Connect to `addr` and send MAGIC.
+    pub(crate) async fn open(addr: &str, meter: Meter, hooks: Option<Hooks>) -> NsqResult<Self> {
+        debug!("Trying to connect to: {:?}", addr);
+        let tcp = rt::connect(addr).await?;
+        debug!("Connected: {:?}", addr);
+        let io: BoxedIo = Box::new(Metered::new(tcp, meter.clone()));
+        let mut conn = Connection {
+            stream: NsqStream::new(io, 1024).with_meter(meter).with_hooks(hooks),
+            state: ConnectionState::Connecting,
+            buf: BytesMut::new(),
+        };
+        Magic.encode(&mut conn.buf);
+        conn.flush().await?;
+        Ok(conn)
+    }
+
+    pub(crate) fn meter(&self) -> Option<&Meter> {
+        self.stream.meter()
+    }
+
+    /// Send IDENTIFY and return the configuration nsqd negotiated, `None` if nsqd answered
+    /// OK without negotiating features.
+    pub(crate) async fn identify(&mut self, config: &Config) -> NsqResult<Option<NsqConfig>> {
+        let body = serde_json::to_string(config)?;
+        self.push(Identify::new(&body))?;
+        self.flush().await?;
+        match self.response().await? {
+            Response::Json(s) => Ok(Some(serde_json::from_str::<NsqConfig>(&s)?)),
+            Response::Ok => Ok(None),
+            r => Err(NsqError::Unknown(format!("unexpected IDENTIFY response: {:?}", r))),
+        }
+    }
+
+    /// Wrap the socket in TLS and wait for nsqd to confirm the upgrade.
+    pub(crate) async fn upgrade_tls(mut self, connector: &TlsConnector, host: &str) -> NsqResult<Self> {
+        let (from, to) = ([ConnectionState::Identifying], ConnectionState::UpgradingTls);
+        self.state.transition("TLS upgrade", &from, to)?;
+        let (io, meter, hooks) = self.stream.into_parts();
+        let tls: BoxedIo = Box::new(connector.connect(host, io)?.await?);
+        self.stream = NsqStream::from_parts(tls, meter, hooks);
+        // nsqd confirms the upgrade with OK over TLS
+        self.response().await?;
+        info!("TLS Ok");
+        Ok(self)
+    }
+
+    /// Upgrade to `algorithm`, snappy or deflate, as negotiated in IDENTIFY.
+    pub(crate) fn compress(&mut self, algorithm: &str) -> NsqResult<()> {
+        let from = [ConnectionState::Identifying, ConnectionState::UpgradingTls];
+        self.state.transition("compression", &from, ConnectionState::Compressing)?;
+        Err(NsqError::Config(algorithm.to_owned(), "compression is not supported".to_owned()))
+    }
+
+    /// Send AUTH, returns the identity if nsqd answered with one.
+    pub(crate) async fn authenticate(&mut self, secret: &str) -> NsqResult<Option<Authentication>> {
+        self.push(Auth::new(secret))?;
+        self.flush().await?;
+        match self.response().await? {
+            Response::Json(s) => Ok(Some(serde_json::from_str(&s)?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// The handshake is done.
+    pub(crate) fn ready(&mut self) -> NsqResult<()> {
+        use ConnectionState::*;
+        self.state.transition("handshake", &[Identifying, UpgradingTls, Compressing, Authenticating], Ready)
+    }
+
+    /// Send SUB and wait for nsqd to accept it.
+    pub(crate) async fn subscribe(&mut self, topic: &Topic, channel: &Channel) -> NsqResult<()> {
+        self.push(Sub::new(topic, channel))?;
+        self.flush().await?;
+        match self.response().await? {
+            Response::Ok => {
+                debug!("subscribed to {}/{}", topic, channel);
+                Ok(())
+            }
+            r => Err(NsqError::Unknown(format!("unexpected SUB response: {:?}", r))),
+        }
+    }
+
+    /// Split the socket between a reader and a writer task and return the session driving them.
+    pub(crate) fn start(self) -> Session {
+        let meter = self.stream.meter().cloned();
+        let (reader, writer) = self.stream.split();
+        let (commands, commands_rx) = mpsc::channel(COMMAND_CHANNEL_SIZE);
+        let (responses_tx, responses) = mpsc::channel(RESPONSE_CHANNEL_SIZE);
+        let (nops, nops_rx) = mpsc::unbounded();
+        rt::spawn(read(reader, responses_tx.clone(), nops));
+        rt::spawn(write(writer, self.buf, commands_rx, nops_rx, responses_tx));
+        Session {
+            state: self.state,
+            commands,
+            responses,
+            pending: false,
+            meter,
+        }
+    }
+
+    /// Buffer `cmd` if it is valid in the current state, see [flush](#method.flush).
+    pub(crate) fn push<T: Encoder>(&mut self, cmd: T) -> NsqResult<()> {
+        self.state.command(cmd.name())?;
+        cmd.encode(&mut self.buf);
+        Ok(())
+    }
+
+    /// Write the buffered commands.
+    pub(crate) async fn flush(&mut self) -> NsqResult<()> {
+        if !self.buf.is_empty() {
+            self.stream.write_all(&self.buf.take()[..]).await?;
+        }
+        Ok(())
+    }
+
+    /// Next response other than a heartbeat, heartbeats are answered.
+    pub(crate) async fn response(&mut self) -> NsqResult<Response> {
+        loop {
+            match self.stream.next().await {
+                Some(Ok(Response::HeartBeat)) => {
+                    self.push(Nop)?;
+                    self.flush().await?;
+                }
+                Some(res) => return res,
+                None => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+            }
+        }
+    }
+}
+
+/// Command for the writer task.
+enum Command {
+    /// Encoded commands, buffered until the next flush.
+    Write(Bytes),
+    Flush,
+}
+
+/// Started connection: commands go to the writer task, responses, errors and messages
+/// come from the reader task in the order nsqd sent them.
+///
+/// Heartbeats are answered by the tasks and never returned. Dropping the session, or
+/// [closing](#method.close) it, writes the commands left and closes the connection.
+pub(crate) struct Session {
+    state: ConnectionState,
+    commands: Sender<Command>,
+    responses: Receiver<NsqResult<Response>>,
+    /// Commands sent since the last flush.
+    pending: bool,
+    meter: Option<Meter>,
+}
+
+impl Session {
+    pub(crate) fn meter(&self) -> Option<&Meter> {
+        self.meter.as_ref()
+    }
+
+    /// Send `cmd` to the writer if it is valid in the current state, waiting for room in the
+    /// command channel. It is written on the next [flush](#method.flush).
+    pub(crate) async fn push<T: Encoder>(&mut self, cmd: T) -> NsqResult<()> {
+        let name = cmd.name();
+        let mut buf = BytesMut::new();
+        cmd.encode(&mut buf);
+        self.push_encoded(name, buf.freeze()).await
+    }
+
+    /// Send the command `name`, already encoded.
+    pub(crate) async fn push_encoded(&mut self, name: &'static str, cmd: Bytes) -> NsqResult<()> {
+        poll_fn(|cx| self.poll_ready(cx)).await?;
+        self.start_push(name, cmd)
+    }
+
+    /// Wait for room in the command channel, see [start_push](#method.start_push).
+    pub(crate) fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<NsqResult<()>> {
+        self.commands.poll_ready(cx).map_err(|_| closed())
+    }
+
+    /// Send the command `name`, already encoded, once [poll_ready](#method.poll_ready) succeeded.
+    /// A new session always has room for one command.
+    pub(crate) fn start_push(&mut self, name: &'static str, cmd: Bytes) -> NsqResult<()> {
+        self.state.command(name)?;
+        self.commands.start_send(Command::Write(cmd)).map_err(|_| closed())?;
+        self.pending = true;
+        Ok(())
+    }
+
+    /// Commands sent to the writer since the last flush.
+    pub(crate) fn has_pending(&self) -> bool {
+        self.pending
+    }
+
+    /// Ask the writer to write the commands sent so far, without waiting for it: write
+    /// errors are returned by the stream of responses.
+    pub(crate) async fn flush(&mut self) -> NsqResult<()> {
+        poll_fn(|cx| self.poll_flush(cx)).await
+    }
+
+    pub(crate) fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<NsqResult<()>> {
+        if self.pending {
+            ready!(self.poll_ready(cx))?;
+            self.commands.start_send(Command::Flush).map_err(|_| closed())?;
+            self.pending = false;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Write the commands left and close the connection.
+    pub(crate) fn close(&mut self) {
+        self.commands.close_channel();
+    }
+
+    /// Next response, error or message.
+    pub(crate) async fn response(&mut self) -> NsqResult<Response> {
+        match self.next().await {
+            Some(res) => res,
+            None => Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+        }
+    }
+}
+
+impl Stream for Session {
+    type Item = NsqResult<Response>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.responses.poll_next_unpin(cx)
+    }
+}
+
+fn closed() -> NsqError {
+    io::Error::from(io::ErrorKind::BrokenPipe).into()
+}
+
+/// Reader task: forward the frames received until the connection fails or the session is dropped,
+/// heartbeats are answered by the writer.
+///
+/// The socket is read even while the response channel is full, so heartbeats behind a slow
+/// session are still answered. The frames read meanwhile wait in a backlog, nsqd sends at most
+/// the messages up to RDY and one response per command but the backlog is bounded anyway.
+async fn read(
+    mut stream: NsqStream<ReadHalf<BoxedIo>>,
+    mut responses: Sender<NsqResult<Response>>,
+    nops: UnboundedSender<()>,
+) {
+    let mut backlog = VecDeque::new();
+    let mut reading = true;
+    poll_fn(|cx| loop {
+        while !backlog.is_empty() {
+            match responses.poll_ready(cx) {
+                Poll::Ready(Ok(())) => {
+                    let frame: NsqResult<Response> = backlog.pop_front().unwrap();
+                    let fatal = matches!(&frame, Err(e) if e.is_connection_error());
+                    if responses.start_send(frame).is_err() || fatal {
+                        return Poll::Ready(());
+                    }
+                }
+                // session dropped
+                Poll::Ready(Err(_)) => return Poll::Ready(()),
+                Poll::Pending => break,
+            }
+        }
+        if !reading {
+            return if backlog.is_empty() { Poll::Ready(()) } else { Poll::Pending };
+        }
+        if backlog.len() >= BACKLOG_SIZE {
+            // woken once the response channel has room
+            return Poll::Pending;
+        }
+        match ready!(stream.poll_next_unpin(cx)) {
+            Some(Ok(Response::HeartBeat)) => {
+                let _ = nops.unbounded_send(());
+            }
+            Some(frame) => {
+                reading = !matches!(&frame, Err(e) if e.is_connection_error());
+                backlog.push_back(frame);
+            }
+            None => reading = false,
+        }
+    })
+    .await
+}
+
+/// Writer task: buffer the commands until flushed, write NOP as soon as a heartbeat is received
+/// without flushing the commands buffered.
+/// Write errors are returned to the session with the responses.
+async fn write(
+    mut io: WriteHalf<BoxedIo>,
+    mut buf: BytesMut,
+    mut commands: Receiver<Command>,
+    mut nops: UnboundedReceiver<()>,
+    mut responses: Sender<NsqResult<Response>>,
+) {
+    let res: NsqResult<()> = async {
+        loop {
+            select! {
+                cmd = commands.next() => match cmd {
+                    Some(Command::Write(cmd)) => buf.extend_from_slice(&cmd),
+                    Some(Command::Flush) => {
+                        // commands already queued join the same write
+                        let mut open = true;
+                        while let Some(cmd) = commands.next().now_or_never() {
+                            match cmd {
+                                Some(Command::Write(cmd)) => buf.extend_from_slice(&cmd),
+                                Some(Command::Flush) => {}
+                                None => {
+                                    open = false;
+                                    break;
+                                }
+                            }
+                        }
+                        write_all(&mut io, &mut buf).await?;
+                        if !open {
+                            io.close().await?;
+                            return Ok(());
+                        }
+                    }
+                    None => {
+                        write_all(&mut io, &mut buf).await?;
+                        io.close().await?;
+                        return Ok(());
+                    }
+                },
+                nop = nops.next() => if nop.is_some() {
+                    let mut nop = BytesMut::new();
+                    Nop.encode(&mut nop);
+                    write_all(&mut io, &mut nop).await?;
+                },
+            }
+        }
+    }
+    .await;
+    if let Err(e) = res {
+        debug!("write failed: {}", e);
+        let _ = responses.send(Err(e)).await;
+    }
+}
+
+async fn write_all<W: AsyncWrite + Unpin>(io: &mut W, buf: &mut BytesMut) -> NsqResult<()> {
+    if !buf.is_empty() {
+        io.write_all(&buf.take()[..]).await?;
+        io.flush().await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,498 @@
+// MIT License
+//
+// Copyright (c) 2019-2021 Alessandro Cresto Miseroglio <alex179ohm@gmail.com>
+// Copyright (c) 2019-2021 Tangram Technologies S.R.L. <https://tngrm.io>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::client::Client;
+use crate::codec::{Cls, Fin, Rdy, Req, Touch};
+use crate::connection::{Connection, Session};
+use crate::error::NsqError;
+use crate::metrics::{Meter, Snapshot};
+use crate::msg::Msg;
+use crate::response::Response;
+use crate::result::NsqResult;
+use crate::rt;
+use crate::trace::{self, span, Span};
+use crate::topic::{Channel, Topic};
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use futures::{select, FutureExt, Stream, StreamExt};
+use log::{debug, warn};
+use std::collections::HashSet;
+use std::future::Future;
+use std::io;
+use std::ops::Deref;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+/// What the consumer replies to nsqd once a message has been handled.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    /// Send FIN, the message is done.
+    Finish,
+    /// Send REQ, nsqd will deliver the message again after the delay.
+    Requeue(Duration),
+}
+
+/// Handler called by the consumer for every message received.
+///
+/// # Examples
+///```no-run
+/// use nsq_rust::{Action, Client, Config, Handler, Msg, Topic, Channel};
+/// use std::future::Future;
+///
+/// struct Printer;
+///
+/// impl Handler for Printer {
+///     fn handle(&mut self, msg: &Msg) -> impl Future<Output = Action> + Send {
+///         println!("{:?}", msg.body());
+///         async { Action::Finish }
+///     }
+/// }
+///
+/// let client = Client::new("localhost:4150", Config::new(), None, None);
+/// client.consumer(Topic::new("test")?, Channel::new("printer")?, Printer).await?;
+///```
+pub trait Handler: Send {
+    fn handle(&mut self, msg: &Msg) -> impl Future<Output = Action> + Send;
+}
+
+/// Run `handler` on the messages of a subscribed connection.
+pub(crate) async fn consume<H: Handler>(conn: Connection, max_in_flight: u32, handler: &mut H) -> NsqResult<()> {
+    let meter = conn.meter().cloned();
+    let mut conn = conn.start();
+    conn.push(Rdy::new(max_in_flight)).await?;
+    conn.flush().await?;
+    if let Some(meter) = &meter {
+        meter.rdy(max_in_flight);
+    }
+    while let Some(res) = conn.next().await {
+        match res {
+            Ok(Response::Msg(msg)) => {
+                if let Some(meter) = &meter {
+                    meter.message_received(msg.body().len());
+                }
+                let span = span!("nsq.message", id = %msg.id(), attempts = msg.attempts());
+                match trace::instrument(handler.handle(&msg), span).await {
+                    Action::Finish => {
+                        conn.push(Fin::new(msg.id())).await?;
+                        if let Some(meter) = &meter {
+                            meter.message_finished();
+                        }
+                    }
+                    Action::Requeue(delay) => {
+                        conn.push(Req::new(msg.id(), delay)).await?;
+                        if let Some(meter) = &meter {
+                            meter.message_requeued();
+                        }
+                    }
+                }
+            }
+            Ok(r) => debug!("response: {:?}", r),
+            // non fatal, the connection is still usable
+            Err(e @ NsqError::Fin) | Err(e @ NsqError::Req) | Err(e @ NsqError::Touch) => {
+                warn!("{}", e);
+                if let Some(meter) = &meter {
+                    meter.message_timed_out();
+                }
+            }
+            Err(e) => return Err(e),
+        }
+        conn.flush().await?;
+    }
+    Ok(())
+}
+
+enum Ack {
+    Ready,
+    /// A message was taken out of the stream buffer.
+    Taken,
+    Fin(String),
+    Req(String, Duration),
+    Touch(String),
+    Close,
+}
+
+/// Message received from a [MessageStream](struct.MessageStream.html).
+///
+/// Reply to nsqd with [finish](#method.finish) or [requeue](#method.requeue),
+/// a message dropped without reply is requeued without delay.
+pub struct Message {
+    msg: Msg,
+    acks: UnboundedSender<Ack>,
+    replied: bool,
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+impl Message {
+    /// Send FIN, the message is done.
+    pub fn finish(mut self) {
+        self.reply(Ack::Fin(self.msg.id().to_owned()));
+    }
+
+    /// Send REQ, nsqd will deliver the message again after `delay`.
+    pub fn requeue(mut self, delay: Duration) {
+        self.reply(Ack::Req(self.msg.id().to_owned(), delay));
+    }
+
+    /// Span of the message (`nsq.message`, with id and attempts), child of the subscription span.
+    ///
+    /// Use it to instrument the processing of the message.
+    #[cfg(feature = "tracing")]
+    pub fn span(&self) -> &tracing::Span {
+        &self.span
+    }
+
+    /// Send TOUCH, resetting the message timeout on nsqd.
+    pub fn touch(&self) {
+        let _ = self.acks.unbounded_send(Ack::Touch(self.msg.id().to_owned()));
+    }
+
+    fn reply(&mut self, ack: Ack) {
+        self.replied = true;
+        let _ = self.acks.unbounded_send(ack);
+    }
+}
+
+impl Deref for Message {
+    type Target = Msg;
+
+    fn deref(&self) -> &Msg {
+        &self.msg
+    }
+}
+
+impl Drop for Message {
+    fn drop(&mut self) {
+        if !self.replied {
+            self.reply(Ack::Req(self.msg.id().to_owned(), Duration::from_secs(0)));
+        }
+    }
+}
+
+/// Messages received from a subscription, see [Client::subscribe](struct.Client.html#method.subscribe).
+///
+/// A background task owns the connection, answers heartbeats and sends the replies
+/// of the [Message](struct.Message.html)s. RDY is sent on the first poll, so nothing is
+/// delivered until the stream is used, and every message counts as in flight from
+/// its delivery until it is finished or requeued: once `max_in_flight` messages are
+/// buffered or being processed nsqd stops delivering, so the stream goes as fast as
+/// it is polled and messages are acknowledged.
+///
+/// RDY is also lowered by the number of messages waiting in the stream buffer, so nsqd
+/// slows down when the stream is polled slower than messages arrive, and RDY goes back
+/// to `max_in_flight` as the stream catches up.
+///
+/// Dropping the stream sends RDY 0, waits for the messages still in flight and closes
+/// the connection.
+///
+/// When the connection drops the task reconnects according to the [retry policy](retry/index.html)
+/// of the client, subscribes again and restores RDY. nsqd delivers again the messages that
+/// were in flight, replies to them from the old connection are ignored. The stream ends
+/// with an error only if the retry policy gives up.
+///
+/// # Examples
+///```no-run
+/// use futures::StreamExt;
+/// use nsq_rust::{Client, Config, Topic, Channel};
+///
+/// let client = Client::new("localhost:4150", Config::new().max_in_flight(10), None, None);
+/// let stream = client.subscribe(Topic::new("test")?, Channel::new("printer")?).await?;
+/// stream
+///     .for_each_concurrent(10, |msg| async move {
+///         match msg {
+///             Ok(msg) => {
+///                 println!("{:?}", msg.body());
+///                 msg.finish();
+///             }
+///             Err(e) => eprintln!("{}", e),
+///         }
+///     })
+///     .await;
+///```
+pub struct MessageStream {
+    meter: Option<Meter>,
+    messages: UnboundedReceiver<NsqResult<Message>>,
+    acks: UnboundedSender<Ack>,
+    ready: bool,
+}
+
+impl MessageStream {
+    /// Counters of the client that created this stream.
+    pub fn stats(&self) -> Snapshot {
+        self.meter.as_ref().map(|m| m.snapshot()).unwrap_or_default()
+    }
+
+    /// Close the subscription like dropping the stream, but wait until the replies of the
+    /// messages still in flight are sent and the connection is closed.
+    ///
+    /// Buffered messages are requeued, messages held elsewhere must be finished or
+    /// requeued for `close` to complete.
+    pub async fn close(mut self) {
+        let _ = self.acks.unbounded_send(Ack::Close);
+        while self.messages.next().await.is_some() {}
+    }
+}
+
+impl Stream for MessageStream {
+    type Item = NsqResult<Message>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if !self.ready {
+            self.ready = true;
+            let _ = self.acks.unbounded_send(Ack::Ready);
+        }
+        let item = self.messages.poll_next_unpin(cx);
+        if let Poll::Ready(Some(Ok(_))) = item {
+            let _ = self.acks.unbounded_send(Ack::Taken);
+        }
+        item
+    }
+}
+
+impl Drop for MessageStream {
+    fn drop(&mut self) {
+        let _ = self.acks.unbounded_send(Ack::Close);
+    }
+}
+
+pub(crate) async fn stream(
+    client: Client,
+    mut conn: Connection,
+    topic: Topic,
+    channel: Channel,
+    max_in_flight: u32,
+    span: Span,
+) -> NsqResult<MessageStream> {
+    conn.subscribe(&topic, &channel).await?;
+    let conn = conn.start();
+    let (messages_tx, messages) = unbounded();
+    let (acks, acks_rx) = unbounded();
+    let task = Task {
+        client,
+        topic,
+        channel,
+        meter: conn.meter().cloned(),
+        conn,
+        max_in_flight,
+        ready: false,
+        rdy: 0,
+        buffered: 0,
+        messages: messages_tx,
+        acks: acks.clone(),
+        outstanding: HashSet::new(),
+    };
+    let meter = task.meter.clone();
+    rt::spawn(trace::instrument(task.run(acks_rx), span));
+    Ok(MessageStream {
+        meter,
+        messages,
+        acks,
+        ready: false,
+    })
+}
+
+struct Task {
+    client: Client,
+    topic: Topic,
+    channel: Channel,
+    conn: Session,
+    meter: Option<Meter>,
+    max_in_flight: u32,
+    /// RDY was sent, restored after a reconnection.
+    ready: bool,
+    /// Last RDY count sent.
+    rdy: u32,
+    /// Messages sent to the stream and not yet taken out of it.
+    buffered: u32,
+    messages: UnboundedSender<NsqResult<Message>>,
+    acks: UnboundedSender<Ack>,
+    /// Messages delivered on the current connection and not yet replied.
+    outstanding: HashSet<String>,
+}
+
+impl Task {
+    async fn run(mut self, mut acks: UnboundedReceiver<Ack>) {
+        let mut closing = false;
+        loop {
+            let mut lost = None;
+            let res = select! {
+                res = self.conn.next().fuse() => match res {
+                    Some(Ok(Response::Msg(msg))) if closing => {
+                        self.conn.push(Req::new(msg.id(), Duration::from_secs(0))).await
+                    }
+                    Some(Ok(Response::Msg(msg))) => {
+                        if let Some(meter) = &self.meter {
+                            meter.message_received(msg.body().len());
+                        }
+                        self.outstanding.insert(msg.id().to_owned());
+                        let msg = Message {
+                            #[cfg(feature = "tracing")]
+                            span: span!("nsq.message", id = %msg.id(), attempts = msg.attempts()),
+                            msg,
+                            acks: self.acks.clone(),
+                            replied: false,
+                        };
+                        // if the stream is gone the message is requeued on drop
+                        if self.messages.unbounded_send(Ok(msg)).is_ok() {
+                            self.buffered += 1;
+                        }
+                        self.adjust_rdy().await
+                    }
+                    Some(Ok(r)) => {
+                        debug!("response: {:?}", r);
+                        Ok(())
+                    }
+                    // non fatal, the connection is still usable
+                    Some(Err(e @ NsqError::Fin)) | Some(Err(e @ NsqError::Req)) | Some(Err(e @ NsqError::Touch)) => {
+                        warn!("{}", e);
+                        if let Some(meter) = &self.meter {
+                            meter.message_timed_out();
+                        }
+                        Ok(())
+                    }
+                    Some(Err(e)) if e.is_connection_error() => {
+                        lost = Some(e);
+                        Ok(())
+                    }
+                    Some(Err(e)) => Err(e),
+                    None => {
+                        lost = Some(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+                        Ok(())
+                    }
+                },
+                ack = acks.next() => match ack {
+                    Some(Ack::Ready) => {
+                        self.ready = true;
+                        self.rdy(self.rdy_target()).await
+                    }
+                    Some(Ack::Taken) => {
+                        self.buffered = self.buffered.saturating_sub(1);
+                        if closing {
+                            Ok(())
+                        } else {
+                            self.adjust_rdy().await
+                        }
+                    }
+                    // replies to messages delivered on a previous connection are dropped
+                    Some(Ack::Fin(id)) if self.outstanding.remove(&id) => {
+                        if let Some(meter) = &self.meter {
+                            meter.message_finished();
+                        }
+                        self.conn.push(Fin::new(&id)).await
+                    }
+                    Some(Ack::Req(id, delay)) if self.outstanding.remove(&id) => {
+                        if let Some(meter) = &self.meter {
+                            meter.message_requeued();
+                        }
+                        self.conn.push(Req::new(&id, delay)).await
+                    }
+                    Some(Ack::Touch(id)) if self.outstanding.contains(&id) => self.conn.push(Touch::new(&id)).await,
+                    Some(Ack::Fin(_)) | Some(Ack::Req(..)) | Some(Ack::Touch(_)) => Ok(()),
+                    Some(Ack::Close) => {
+                        debug!("message stream dropped, waiting for {} messages", self.outstanding.len());
+                        closing = true;
+                        self.rdy(0).await
+                    }
+                    None => return,
+                },
+            };
+            let res = match res {
+                Ok(()) if lost.is_none() && closing && self.outstanding.is_empty() => self.conn.push(Cls).await,
+                res => res,
+            };
+            if let Err(e) = res {
+                let _ = self.messages.unbounded_send(Err(e));
+                return;
+            }
+            if lost.is_none() {
+                if let Err(e) = self.conn.flush().await {
+                    lost = Some(e);
+                }
+            }
+            if closing && (lost.is_some() || self.outstanding.is_empty()) {
+                return;
+            }
+            if let Some(e) = lost {
+                if let Err(e) = self.reconnect(e).await {
+                    let _ = self.messages.unbounded_send(Err(e));
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Connect again, subscribe and restore RDY.
+    async fn reconnect(&mut self, error: NsqError) -> NsqResult<()> {
+        self.outstanding.clear();
+        let mut error = error;
+        let mut attempts = 0;
+        loop {
+            let conn = self.client.reconnect(error, &mut attempts).await?;
+            self.meter = conn.meter().cloned();
+            match self.resubscribe(conn).await {
+                Ok(()) => return Ok(()),
+                Err(e) if e.is_connection_error() => error = e,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    async fn resubscribe(&mut self, mut conn: Connection) -> NsqResult<()> {
+        conn.subscribe(&self.topic, &self.channel).await?;
+        self.conn = conn.start();
+        if self.ready {
+            self.rdy(self.rdy_target()).await?;
+            self.conn.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// `max_in_flight` less the messages waiting in the stream buffer.
+    fn rdy_target(&self) -> u32 {
+        self.max_in_flight.saturating_sub(self.buffered)
+    }
+
+    /// Lower RDY when the target dropped by a quarter of `max_in_flight` or reached zero,
+    /// and restore it once the stream buffer is drained, to avoid a RDY per message.
+    async fn adjust_rdy(&mut self) -> NsqResult<()> {
+        let target = self.rdy_target();
+        if !self.ready || target == self.rdy {
+            return Ok(());
+        }
+        let step = (self.max_in_flight / 4).max(1);
+        let lower = target < self.rdy && (target == 0 || self.rdy - target >= step);
+        if lower || target == self.max_in_flight {
+            self.rdy(target).await?;
+        }
+        Ok(())
+    }
+
+    async fn rdy(&mut self, count: u32) -> NsqResult<()> {
+        self.conn.push(Rdy::new(count)).await?;
+        self.rdy = count;
+        if let Some(meter) = &self.meter {
+            meter.rdy(count);
+        }
+        Ok(())
+    }
+}
@@ -21,7 +21,7 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use serde_json;
+use crate::connection::ConnectionState;
 use std::error::Error;
 use std::{fmt, io};
 
@@ -42,6 +42,17 @@ pub enum NsqError {
     Touch,
     Auth,
     Unauthorized,
+    InvalidTopic(String),
+    InvalidChannel(String),
+    Codec(Box<dyn Error + Send + Sync>),
+    Unknown(String),
+    Http(u16, String),
+    /// Invalid configuration: field and reason.
+    Config(String, String),
+    /// The connection dropped and is being established again.
+    Disconnected,
+    /// Operation not allowed in the state of the connection.
+    InvalidState(&'static str, ConnectionState),
 }
 
 impl fmt::Display for NsqError {
@@ -63,6 +74,14 @@ impl fmt::Display for NsqError {
             Touch => write!(f, "E_TOUCH_FAILED"),
             Auth => write!(f, "E_AUTH_FAILED"),
             Unauthorized => write!(f, "E_UNAUTHORIZED"),
+            InvalidTopic(t) => write!(f, "invalid topic name: {:?}", t),
+            InvalidChannel(c) => write!(f, "invalid channel name: {:?}", c),
+            Codec(e) => write!(f, "codec: {}", e),
+            Unknown(s) => write!(f, "{}", s),
+            Http(status, message) => write!(f, "HTTP {}: {}", status, message),
+            Config(field, reason) => write!(f, "invalid config {}: {}", field, reason),
+            Disconnected => write!(f, "disconnected from nsqd, reconnecting"),
+            InvalidState(operation, state) => write!(f, "cannot {} in state {}", operation, state),
         }
     }
 }
@@ -73,6 +92,7 @@ impl Error for NsqError {
         match self {
             Io(e) => Some(e),
             Json(e) => Some(e),
+            Codec(e) => Some(e.as_ref()),
             _ => None,
         }
     }
@@ -80,7 +100,8 @@ impl Error for NsqError {
 
 impl From<&'_ str> for NsqError {
     fn from(s: &'_ str) -> NsqError {
-        match s {
+        // error frames carry a description after the code, e.g. "E_BAD_TOPIC PUB topic name ..."
+        match s.split(' ').next().unwrap_or(s) {
             "E_INVALID" => NsqError::Invalid,
             "E_BAD_BODY" => NsqError::Body,
             "E_BAD_TOPIC" => NsqError::Topic,
@@ -94,7 +115,26 @@ impl From<&'_ str> for NsqError {
             "E_TOUCH_FAILED" => NsqError::Touch,
             "E_AUTH_FAILED" => NsqError::Auth,
             "E_UNAUTHORIZED" => NsqError::Unauthorized,
-            _ => unreachable!("invalid error str"),
+            _ => NsqError::Unknown(s.to_owned()),
+        }
+    }
+}
+
+impl NsqError {
+    /// The connection failed, as opposed to an error returned by nsqd.
+    pub(crate) fn is_connection_error(&self) -> bool {
+        matches!(self, NsqError::Io(_) | NsqError::Disconnected)
+    }
+
+    /// Map an error returned by the nsqd HTTP API to the TCP protocol equivalent, if any.
+    pub(crate) fn from_http(status: u16, message: String) -> NsqError {
+        match message.as_str() {
+            "MISSING_ARG_TOPIC" | "INVALID_ARG_TOPIC" | "BAD_TOPIC" => NsqError::Topic,
+            "MISSING_ARG_CHANNEL" | "INVALID_ARG_CHANNEL" | "BAD_CHANNEL" => NsqError::Channel,
+            "MSG_EMPTY" | "MSG_TOO_BIG" | "BAD_MESSAGE" => NsqError::Message,
+            "BODY_TOO_BIG" | "BAD_BODY" | "INVALID_BODY" => NsqError::Body,
+            "INVALID_DEFER" | "INVALID_REQUEST" | "INVALID_MESSAGE" => NsqError::Invalid,
+            _ => NsqError::Http(status, message),
         }
     }
 }
@@ -0,0 +1,92 @@
+// MIT License
+//
+// Copyright (c) 2019-2021 Alessandro Cresto Miseroglio <alex179ohm@gmail.com>
+// Copyright (c) 2019-2021 Tangram Technologies S.R.L. <https://tngrm.io>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Lifecycle events of the connections to nsqd, to feed alerts and audit logs.
+//!
+//! The callback set with [Client::on_event](../struct.Client.html#method.on_event) is called
+//! from the tasks driving the connections, it must not block.
+//!
+//! # Examples
+//!```no-run
+//! use nsq_rust::events::ConnectionEvent;
+//! use nsq_rust::{Client, Config};
+//!
+//! let client = Client::new("localhost:4150", Config::new(), None, None).on_event(|event: &ConnectionEvent| {
+//!     if let ConnectionEvent::ErrorFrame { addr, error } = event {
+//!         eprintln!("{}: {}", addr, error);
+//!     }
+//! });
+//!```
+
+use crate::auth::Authentication;
+use crate::config::NsqConfig;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Reported to the callback set with [Client::on_event](../struct.Client.html#method.on_event).
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConnectionEvent {
+    /// TCP connection to `addr` established, the handshake follows.
+    Connected { addr: String },
+    /// IDENTIFY answered, `config` is what nsqd negotiated.
+    Identified { addr: String, config: NsqConfig },
+    /// Connection upgraded to TLS, `peer_certificate` is the DER certificate presented by nsqd.
+    TlsEstablished { addr: String, peer_certificate: Option<Vec<u8>> },
+    /// AUTH accepted by nsqd.
+    Authenticated { addr: String, auth: Authentication },
+    /// Heartbeat received.
+    Heartbeat { addr: String },
+    /// nsqd sent an error frame, like `E_FIN_FAILED ...`.
+    ErrorFrame { addr: String, error: String },
+    /// The connection dropped because of `error`.
+    Disconnected { addr: String, error: String },
+    /// Waiting `delay` before the reconnection attempt number `attempt`.
+    Reconnecting { addr: String, attempt: u32, delay: Duration },
+    /// Connected again after `attempts` attempts.
+    Reconnected { addr: String, attempts: u32 },
+    /// The retry policy gave up after `attempts` attempts.
+    GaveUp { addr: String, attempts: u32 },
+}
+
+pub(crate) type OnEvent = Arc<dyn Fn(&ConnectionEvent) + Send + Sync>;
+
+/// Callback of a client bound to the address of a connection.
+#[derive(Clone)]
+pub(crate) struct Hooks {
+    addr: String,
+    on_event: OnEvent,
+}
+
+impl Hooks {
+    pub(crate) fn new(addr: &str, on_event: OnEvent) -> Self {
+        Hooks {
+            addr: addr.to_owned(),
+            on_event,
+        }
+    }
+
+    /// Call the callback with the event built from the address of the connection.
+    pub(crate) fn emit<F: FnOnce(String) -> ConnectionEvent>(&self, event: F) {
+        (self.on_event)(&event(self.addr.clone()));
+    }
+}
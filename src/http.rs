@@ -0,0 +1,438 @@
+// MIT License
+//
+// Copyright (c) 2019-2021 Alessandro Cresto Miseroglio <alex179ohm@gmail.com>
+// Copyright (c) 2019-2021 Tangram Technologies S.R.L. <https://tngrm.io>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Client for the nsqd HTTP API.
+//!
+//! Requests are plain HTTP/1.1 (no TLS) over a new connection each, which suits
+//! short lived processes publishing a handful of messages. The underlying [request](fn.request.html)
+//! and [encode](fn.encode.html) are exposed for tools talking to other HTTP endpoints.
+
+use crate::error::NsqError;
+use crate::result::NsqResult;
+use crate::rt;
+use crate::stats::Stats;
+use crate::topic::{Channel, Topic};
+use byteorder::{BigEndian, ByteOrder};
+use futures::io::{AsyncReadExt, AsyncWriteExt};
+use log::debug;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::Value;
+use std::io;
+use std::str::from_utf8;
+use std::time::Duration;
+
+/// Body format of [HttpClient::mpublish](struct.HttpClient.html#method.mpublish).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MpubMode {
+    /// Messages separated by `\n`, messages must not contain newlines.
+    Newline,
+    /// Length prefixed messages, any content allowed.
+    Binary,
+}
+
+/// nsqd HTTP API client.
+///
+/// # Examples
+///```no-run
+/// use nsq_rust::{HttpClient, Topic};
+///
+/// let client = HttpClient::new("localhost:4151");
+/// client.publish(&Topic::new("test")?, b"hello").await?;
+///```
+#[derive(Clone, Debug)]
+pub struct HttpClient {
+    addr: String,
+    timeout: Duration,
+}
+
+impl HttpClient {
+    /// `addr` is the nsqd HTTP address (`host:port`), by default nsqd listens on 4151.
+    pub fn new<ADDR: Into<String>>(addr: ADDR) -> Self {
+        HttpClient {
+            addr: addr.into(),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Fail the requests not answered within `timeout` (default: 20 seconds).
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub async fn ping(&self) -> NsqResult<()> {
+        self.get("/ping").await.map(|_| ())
+    }
+
+    pub async fn publish(&self, topic: &Topic, msg: &[u8]) -> NsqResult<()> {
+        let path = format!("/pub?topic={}", encode(topic.as_str()));
+        self.post(&path, msg).await.map(|_| ())
+    }
+
+    pub async fn mpublish<M: AsRef<[u8]>>(&self, topic: &Topic, msgs: &[M], mode: MpubMode) -> NsqResult<()> {
+        let mut body = Vec::new();
+        let path = match mode {
+            MpubMode::Newline => {
+                for msg in msgs {
+                    if msg.as_ref().contains(&b'\n') {
+                        return Err(NsqError::Message);
+                    }
+                    body.extend_from_slice(msg.as_ref());
+                    body.push(b'\n');
+                }
+                format!("/mpub?topic={}", encode(topic.as_str()))
+            }
+            MpubMode::Binary => {
+                let mut len = [0u8; 4];
+                BigEndian::write_u32(&mut len, msgs.len() as u32);
+                body.extend_from_slice(&len);
+                for msg in msgs {
+                    BigEndian::write_u32(&mut len, msg.as_ref().len() as u32);
+                    body.extend_from_slice(&len);
+                    body.extend_from_slice(msg.as_ref());
+                }
+                format!("/mpub?topic={}&binary=true", encode(topic.as_str()))
+            }
+        };
+        self.post(&path, &body).await.map(|_| ())
+    }
+
+    /// Deferred publish (`/pub` with the `defer` parameter), milliseconds precision.
+    pub async fn dpublish(&self, topic: &Topic, delay: Duration, msg: &[u8]) -> NsqResult<()> {
+        let path = format!("/pub?topic={}&defer={}", encode(topic.as_str()), delay.as_millis());
+        self.post(&path, msg).await.map(|_| ())
+    }
+
+    /// Fetch `/stats`, optionally filtered to a topic and one of its channels.
+    ///
+    /// # Examples
+    ///```no-run
+    /// use nsq_rust::{HttpClient, Topic, Channel};
+    ///
+    /// let client = HttpClient::new("localhost:4151");
+    /// let stats = client.stats(Some(&Topic::new("test")?), Some(&Channel::new("workers")?)).await?;
+    /// for topic in stats.topics {
+    ///     for channel in topic.channels {
+    ///         println!("{}/{}: {}", topic.topic_name, channel.channel_name, channel.depth);
+    ///     }
+    /// }
+    ///```
+    pub async fn stats(&self, topic: Option<&Topic>, channel: Option<&Channel>) -> NsqResult<Stats> {
+        let mut path = String::from("/stats?format=json");
+        if let Some(topic) = topic {
+            path.push_str("&topic=");
+            path.push_str(&encode(topic.as_str()));
+            if let Some(channel) = channel {
+                path.push_str("&channel=");
+                path.push_str(&encode(channel.as_str()));
+            }
+        }
+        decode_json(&self.get(&path).await?)
+    }
+
+    pub async fn create_topic(&self, topic: &Topic) -> NsqResult<()> {
+        self.topic_action("create", topic).await
+    }
+
+    pub async fn delete_topic(&self, topic: &Topic) -> NsqResult<()> {
+        self.topic_action("delete", topic).await
+    }
+
+    pub async fn empty_topic(&self, topic: &Topic) -> NsqResult<()> {
+        self.topic_action("empty", topic).await
+    }
+
+    pub async fn pause_topic(&self, topic: &Topic) -> NsqResult<()> {
+        self.topic_action("pause", topic).await
+    }
+
+    pub async fn unpause_topic(&self, topic: &Topic) -> NsqResult<()> {
+        self.topic_action("unpause", topic).await
+    }
+
+    pub async fn create_channel(&self, topic: &Topic, channel: &Channel) -> NsqResult<()> {
+        self.channel_action("create", topic, channel).await
+    }
+
+    pub async fn delete_channel(&self, topic: &Topic, channel: &Channel) -> NsqResult<()> {
+        self.channel_action("delete", topic, channel).await
+    }
+
+    pub async fn empty_channel(&self, topic: &Topic, channel: &Channel) -> NsqResult<()> {
+        self.channel_action("empty", topic, channel).await
+    }
+
+    pub async fn pause_channel(&self, topic: &Topic, channel: &Channel) -> NsqResult<()> {
+        self.channel_action("pause", topic, channel).await
+    }
+
+    pub async fn unpause_channel(&self, topic: &Topic, channel: &Channel) -> NsqResult<()> {
+        self.channel_action("unpause", topic, channel).await
+    }
+
+    async fn topic_action(&self, action: &str, topic: &Topic) -> NsqResult<()> {
+        let path = format!("/topic/{}?topic={}", action, encode(topic.as_str()));
+        self.post(&path, &[]).await.map(|_| ())
+    }
+
+    async fn channel_action(&self, action: &str, topic: &Topic, channel: &Channel) -> NsqResult<()> {
+        let path = format!(
+            "/channel/{}?topic={}&channel={}",
+            action,
+            encode(topic.as_str()),
+            encode(channel.as_str())
+        );
+        self.post(&path, &[]).await.map(|_| ())
+    }
+
+    pub(crate) async fn get(&self, path: &str) -> NsqResult<Vec<u8>> {
+        request(&self.addr, "GET", path, NSQ_HEADERS, &[], self.timeout).await?.into_result()
+    }
+
+    async fn post(&self, path: &str, body: &[u8]) -> NsqResult<Vec<u8>> {
+        request(&self.addr, "POST", path, NSQ_HEADERS, body, self.timeout).await?.into_result()
+    }
+}
+
+/// Timeout of the requests of [HttpClient](struct.HttpClient.html) and
+/// [LookupdClient](../struct.LookupdClient.html) unless changed.
+pub(crate) const DEFAULT_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Headers of the requests to nsqd and nsqlookupd, asking for the >= 1.0 response format.
+pub(crate) const NSQ_HEADERS: &[(&str, &str)] = &[
+    ("User-Agent", "nsq-rust"),
+    ("Accept", "application/vnd.nsq; version=1.0"),
+];
+
+/// Response returned by [request](fn.request.html).
+#[derive(Clone, Debug)]
+pub struct HttpResponse {
+    pub status: u16,
+    /// Body, without the chunked encoding.
+    pub body: Vec<u8>,
+}
+
+/// Decode a response body wrapped in the pre 1.0 envelope (`status_code`, `status_txt`
+/// and `data`) or plain (nsqd/nsqlookupd >= 1.0).
+///
+/// The envelope is recognized by its keys rather than by trying both formats, so a body
+/// not matching `T` is reported instead of decoding to defaults.
+pub(crate) fn decode_json<T: DeserializeOwned>(body: &[u8]) -> NsqResult<T> {
+    let mut value: Value = serde_json::from_slice(body)?;
+    let enveloped = value.as_object().is_some_and(|o| o.contains_key("status_code") && o.contains_key("data"));
+    if enveloped {
+        let status_code = value["status_code"].as_u64().unwrap_or(0) as u16;
+        if status_code != 200 {
+            let status_txt = value["status_txt"].as_str().unwrap_or_default().to_owned();
+            return Err(NsqError::from_http(status_code, status_txt));
+        }
+        value = value["data"].take();
+    }
+    Ok(serde_json::from_value(value)?)
+}
+
+/// Go encodes empty lists as `null`, deserialize them as empty.
+pub(crate) fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    message: Option<String>,
+    status_txt: Option<String>,
+}
+
+impl HttpResponse {
+    /// Body of a 2xx response, the error reported by nsqd otherwise.
+    pub fn into_result(self) -> NsqResult<Vec<u8>> {
+        if (200..300).contains(&self.status) {
+            return Ok(self.body);
+        }
+        // nsqd >= 1.0 replies {"message": ...}, older versions the status_txt envelope
+        let message = match serde_json::from_slice::<ErrorBody>(&self.body) {
+            Ok(ErrorBody { message: Some(m), .. }) | Ok(ErrorBody { status_txt: Some(m), .. }) => m,
+            _ => String::from_utf8_lossy(&self.body).trim().to_owned(),
+        };
+        Err(NsqError::from_http(self.status, message))
+    }
+}
+
+/// Send a single request to `addr` (`host:port`) on a new connection and read the whole response.
+///
+/// `Host` is `addr` unless given in `headers`, `Content-Length` and `Connection: close` are added.
+/// Fails with `ErrorKind::TimedOut` if the response is not read within `timeout`.
+///
+/// # Examples
+///```no-run
+/// use nsq_rust::http::{encode, request};
+/// use std::time::Duration;
+///
+/// let path = format!("/search?q={}", encode("nsq client"));
+/// let response = request("localhost:8080", "GET", &path, &[("Accept", "text/plain")], &[], Duration::from_secs(5)).await?;
+/// println!("{} {:?}", response.status, response.body);
+///```
+pub async fn request(
+    addr: &str,
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+    timeout: Duration,
+) -> NsqResult<HttpResponse> {
+    debug!("HTTP {} {}{}", method, addr, path);
+    match rt::timeout(timeout, send(addr, method, path, headers, body)).await {
+        Some(res) => res,
+        None => Err(io::Error::new(io::ErrorKind::TimedOut, "request timed out").into()),
+    }
+}
+
+async fn send(addr: &str, method: &str, path: &str, headers: &[(&str, &str)], body: &[u8]) -> NsqResult<HttpResponse> {
+    let mut head = format!("{} {} HTTP/1.1\r\n", method, path);
+    if !headers.iter().any(|(name, _)| name.eq_ignore_ascii_case("host")) {
+        head.push_str(&format!("Host: {}\r\n", addr));
+    }
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str(&format!("Connection: close\r\nContent-Length: {}\r\n\r\n", body.len()));
+    let mut stream = rt::connect(addr).await?;
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body).await?;
+    stream.flush().await?;
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw).await?;
+    parse_response(raw)
+}
+
+fn parse_response(mut raw: Vec<u8>) -> NsqResult<HttpResponse> {
+    let end = raw
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| invalid_response("incomplete headers"))?;
+    let head = from_utf8(&raw[..end]).map_err(|_| invalid_response("headers are not utf8"))?;
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|l| l.split(' ').nth(1))
+        .and_then(|s| s.parse::<u16>().ok())
+        .ok_or_else(|| invalid_response("bad status line"))?;
+    let mut chunked = false;
+    let mut content_length = None;
+    for line in lines {
+        let mut kv = line.splitn(2, ':');
+        let key = kv.next().unwrap_or("").trim().to_ascii_lowercase();
+        let value = kv.next().unwrap_or("").trim();
+        match key.as_str() {
+            "transfer-encoding" => chunked = value.eq_ignore_ascii_case("chunked"),
+            "content-length" => content_length = value.parse::<usize>().ok(),
+            _ => {}
+        }
+    }
+    let mut body = raw.split_off(end + 4);
+    if chunked {
+        body = decode_chunked(&body)?;
+    } else if let Some(len) = content_length {
+        body.truncate(len);
+    }
+    Ok(HttpResponse { status, body })
+}
+
+fn decode_chunked(mut data: &[u8]) -> NsqResult<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let eol = data
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .ok_or_else(|| invalid_response("bad chunk"))?;
+        let size = from_utf8(&data[..eol])
+            .ok()
+            .and_then(|s| usize::from_str_radix(s.split(';').next().unwrap_or("").trim(), 16).ok())
+            .ok_or_else(|| invalid_response("bad chunk size"))?;
+        if size == 0 {
+            return Ok(body);
+        }
+        let start = eol + 2;
+        if data.len() < start + size {
+            return Err(invalid_response("truncated chunk"));
+        }
+        body.extend_from_slice(&data[start..start + size]);
+        data = &data[(start + size + 2).min(data.len())..];
+    }
+}
+
+fn invalid_response(reason: &str) -> NsqError {
+    io::Error::new(io::ErrorKind::InvalidData, reason).into()
+}
+
+/// Percent encode a query string value.
+pub fn encode<V: AsRef<[u8]>>(value: V) -> String {
+    let value = value.as_ref();
+    let mut encoded = String::with_capacity(value.len());
+    for &b in value {
+        match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => encoded.push(b as char),
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn decode_enveloped_and_plain() {
+        let plain: HashMap<String, u32> = decode_json(br#"{"a":1}"#).unwrap();
+        assert_eq!(plain["a"], 1);
+        let body = br#"{"status_code":200,"status_txt":"OK","data":{"a":1}}"#;
+        let enveloped: HashMap<String, u32> = decode_json(body).unwrap();
+        assert_eq!(enveloped, plain);
+        // a plain body may have a `data` field of its own
+        let plain: HashMap<String, u32> = decode_json(br#"{"data":1}"#).unwrap();
+        assert_eq!(plain["data"], 1);
+    }
+
+    #[test]
+    fn decode_enveloped_error() {
+        let body = br#"{"status_code":400,"status_txt":"INVALID_ARG_TOPIC","data":null}"#;
+        let res = decode_json::<HashMap<String, u32>>(body);
+        assert!(matches!(res, Err(NsqError::Topic)), "{:?}", res);
+        let body = br#"{"status_code":404,"status_txt":"TOPIC_NOT_FOUND","data":null}"#;
+        let res = decode_json::<HashMap<String, u32>>(body);
+        assert!(matches!(res, Err(NsqError::Http(404, ref txt)) if txt == "TOPIC_NOT_FOUND"), "{:?}", res);
+    }
+
+    #[test]
+    fn decode_malformed() {
+        assert!(matches!(decode_json::<HashMap<String, u32>>(b"not json"), Err(NsqError::Json(_))));
+        let res = decode_json::<HashMap<String, u32>>(br#"{"status_code":200,"status_txt":"OK","data":[1]}"#);
+        assert!(matches!(res, Err(NsqError::Json(_))), "{:?}", res);
+    }
+}
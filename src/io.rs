@@ -23,12 +23,14 @@
 
 use crate::codec::decode_msg;
 use crate::error::NsqError;
+use crate::events::{ConnectionEvent, Hooks};
+use crate::metrics::Meter;
 use crate::response::Response;
 use crate::result::NsqResult;
-use async_std::stream::Stream;
 use byteorder::{BigEndian, ByteOrder};
 use bytes::BytesMut;
-use futures::io::{AsyncRead, AsyncWrite, Result};
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadHalf, Result, WriteHalf};
+use futures::Stream;
 use log::debug;
 use std::{
     convert::TryFrom,
@@ -38,90 +40,164 @@ use std::{
     task::{Context, Poll},
 };
 
+/// Any connection to nsqd: plain TCP, TLS, compressed.
+pub trait AsyncIo: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> AsyncIo for T {}
+
+pub type BoxedIo = Box<dyn AsyncIo>;
+
 const HEADER_SIZE: usize = 8;
+const MSG_HEADER_SIZE: usize = 26;
+
+const FRAME_TYPE_RESPONSE: u32 = 0;
+const FRAME_TYPE_ERROR: u32 = 1;
+const FRAME_TYPE_MESSAGE: u32 = 2;
 
-pub struct NsqStream<'a, S: AsyncRead + AsyncWrite + Unpin> {
-    stream: &'a mut S,
+pub struct NsqStream<S: AsyncRead + Unpin> {
+    stream: S,
     read_buffer: BytesMut,
     exit: bool,
+    meter: Option<Meter>,
+    hooks: Option<Hooks>,
 }
 
-impl<'a, S: AsyncRead + AsyncWrite + Unpin> NsqStream<'a, S> {
-    pub fn new(stream: &'a mut S, max_size: usize) -> Self {
+impl<S: AsyncRead + Unpin> NsqStream<S> {
+    pub fn new(stream: S, max_size: usize) -> Self {
         Self {
             stream,
             read_buffer: BytesMut::with_capacity(max_size),
             exit: false,
+            meter: None,
+            hooks: None,
         }
     }
 
-    pub fn reset(&mut self) {
-        self.exit = false;
+    /// Record the heartbeats of the connection, the meter is available to producers and consumers.
+    pub(crate) fn with_meter(mut self, meter: Meter) -> Self {
+        self.meter = Some(meter);
+        self
+    }
+
+    /// Report the heartbeats and error frames received to the client callback, if any.
+    pub(crate) fn with_hooks(mut self, hooks: Option<Hooks>) -> Self {
+        self.hooks = hooks;
+        self
+    }
+
+    /// Take the socket back to wrap it, e.g. in TLS, see [from_parts](#method.from_parts).
+    ///
+    /// Bytes received and not parsed yet are dropped: nsqd must be waiting for the client.
+    pub(crate) fn into_parts(self) -> (S, Option<Meter>, Option<Hooks>) {
+        debug_assert!(self.read_buffer.is_empty(), "unread bytes before replacing the socket");
+        (self.stream, self.meter, self.hooks)
+    }
+
+    pub(crate) fn from_parts(stream: S, meter: Option<Meter>, hooks: Option<Hooks>) -> Self {
+        let mut stream = Self::new(stream, 1024).with_hooks(hooks);
+        stream.meter = meter;
+        stream
+    }
+
+    pub(crate) fn meter(&self) -> Option<&Meter> {
+        self.meter.as_ref()
+    }
+
+    /// Split a complete frame off the read buffer, if one has been received.
+    fn parse_frame(&mut self) -> Option<NsqResult<Response>> {
+        if self.read_buffer.len() < 4 {
+            return None;
+        }
+        let size = usize::try_from(BigEndian::read_u32(&self.read_buffer[..4]))
+            .expect("cannot convert u32 to usize");
+        if size < HEADER_SIZE - 4 {
+            return Some(Err(invalid_data("frame size too small")));
+        }
+        if self.read_buffer.len() < size + 4 {
+            return None;
+        }
+        let mut frame = self.read_buffer.split_to(size + 4);
+        let frame_type = BigEndian::read_u32(&frame[4..HEADER_SIZE]);
+        let data = &mut frame[HEADER_SIZE..];
+        debug!("frame: {:?}, size: {:?}", frame_type, size);
+        match frame_type {
+            FRAME_TYPE_RESPONSE => Some(
+                from_utf8(data)
+                    .map(|s| s.into())
+                    .map_err(|_| invalid_data("response is not utf8")),
+            ),
+            FRAME_TYPE_ERROR => match from_utf8(data) {
+                Ok(error) => {
+                    if let Some(hooks) = &self.hooks {
+                        hooks.emit(|addr| ConnectionEvent::ErrorFrame { addr, error: error.to_owned() });
+                    }
+                    Some(Err(NsqError::from(error)))
+                }
+                Err(_) => Some(Err(invalid_data("error is not utf8"))),
+            },
+            FRAME_TYPE_MESSAGE if data.len() >= MSG_HEADER_SIZE => Some(Ok(decode_msg(data).into())),
+            FRAME_TYPE_MESSAGE => Some(Err(invalid_data("message frame too small"))),
+            _ => Some(Err(invalid_data("unknown frame type"))),
+        }
     }
 }
 
-impl<'a, S: AsyncRead + AsyncWrite + Unpin> Stream for NsqStream<'a, S> {
+impl<S: AsyncRead + AsyncWrite + Unpin> NsqStream<S> {
+    /// Split the socket to read frames and write commands from different tasks,
+    /// the bytes received and not parsed yet stay with the read half.
+    pub(crate) fn split(self) -> (NsqStream<ReadHalf<S>>, WriteHalf<S>) {
+        let (read, write) = self.stream.split();
+        let stream = NsqStream {
+            stream: read,
+            read_buffer: self.read_buffer,
+            exit: self.exit,
+            meter: self.meter,
+            hooks: self.hooks,
+        };
+        (stream, write)
+    }
+}
+
+fn invalid_data(reason: &str) -> NsqError {
+    stdio::Error::new(stdio::ErrorKind::InvalidData, reason).into()
+}
+
+impl<S: AsyncRead + Unpin> Stream for NsqStream<S> {
     type Item = NsqResult<Response>;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         let this = self.get_mut();
-        let mut buffer = [0u8; 1024];
-        match Pin::new(&mut this.stream).poll_read(cx, &mut buffer) {
-            Poll::Pending => {
-                if this.exit {
-                    Poll::Ready(None)
-                } else {
-                    Poll::Pending
+        let mut buffer = [0u8; 4096];
+        loop {
+            if let Some(frame) = this.parse_frame() {
+                if let Ok(Response::HeartBeat) = frame {
+                    if let Some(meter) = &this.meter {
+                        meter.heartbeat();
+                    }
+                    if let Some(hooks) = &this.hooks {
+                        hooks.emit(|addr| ConnectionEvent::Heartbeat { addr });
+                    }
                 }
+                return Poll::Ready(Some(frame));
             }
-            Poll::Ready(Ok(l)) if l == 0 => {
-                this.exit = true;
-                let e = stdio::Error::new(stdio::ErrorKind::UnexpectedEof, "Received 0 bytes");
-                Poll::Ready(Some(Err(e.into())))
+            if this.exit {
+                return Poll::Ready(None);
             }
-            Poll::Ready(Ok(l)) => {
-                this.read_buffer.extend(&buffer[..l]);
-                debug!("{:?}", this.read_buffer);
-                if l < HEADER_SIZE {
-                    return Poll::Pending;
-                }
-                let size = usize::try_from(BigEndian::read_u32(&buffer[..4]))
-                    .expect("cannot convert u32 to usize");
-                if l < size {
-                    return Poll::Pending;
-                }
-                let frame = BigEndian::read_u32(&this.read_buffer.split_to(8)[4..]);
-                println!("frame: {:?}", frame);
-                println!("buffer: {:?}", this.read_buffer);
-                match frame {
-                    0 => {
-                        this.exit = true;
-                        Poll::Ready(Some(Ok(from_utf8(
-                            &this.read_buffer.split_to(size + 4)[..],
-                        )
-                        .expect("failed to encode utf8")
-                        .into())))
-                    }
-                    1 => {
-                        this.exit = true;
-                        Poll::Ready(Some(Err(NsqError::from(
-                            from_utf8(&this.read_buffer.split_to(size + 4)[..])
-                                .expect("failed to encode utf8"),
-                        ))))
-                    }
-                    2 => Poll::Ready(Some(Ok(decode_msg(
-                        &mut this.read_buffer.split_to(size + 4)[..],
-                    )
-                    .into()))),
-                    _ => unreachable!(),
+            match Pin::new(&mut this.stream).poll_read(cx, &mut buffer) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Ok(0)) => {
+                    this.exit = true;
+                    let e = stdio::Error::new(stdio::ErrorKind::UnexpectedEof, "Received 0 bytes");
+                    return Poll::Ready(Some(Err(e.into())));
                 }
+                Poll::Ready(Ok(l)) => this.read_buffer.extend_from_slice(&buffer[..l]),
+                Poll::Ready(Err(e)) => return Poll::Ready(Some(Err(e.into()))),
             }
-            Poll::Ready(Err(e)) => Poll::Ready(Some(Err(e.into()))),
         }
     }
 }
 
-impl<'a, S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for NsqStream<'a, S> {
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for NsqStream<S> {
     fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
         let this = self.get_mut();
         Pin::new(&mut this.stream).poll_write(cx, buf)
@@ -138,7 +214,7 @@ impl<'a, S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for NsqStream<'a, S> {
     }
 }
 
-impl<'a, S: AsyncRead + AsyncWrite + Unpin> AsyncRead for NsqStream<'a, S> {
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for NsqStream<S> {
     fn poll_read(
         self: Pin<&mut Self>,
         cx: &mut Context<'_>,
@@ -23,16 +23,49 @@
 
 mod config;
 mod io;
-mod utils;
+mod trace;
 mod codec;
 mod client;
+mod connection;
 mod error;
 mod response;
 mod auth;
 mod msg;
 mod result;
-mod publish;
+mod topic;
+mod consumer;
+mod typed;
+pub mod http;
+pub mod stats;
+pub mod metrics;
+mod lookupd;
+pub mod rt;
+mod producer;
+mod bridge;
+pub mod blocking;
+pub mod retry;
+pub mod events;
+#[cfg(feature = "testing")]
+pub mod testing;
 
 pub use client::Client;
-pub use config::Config;
-pub use codec::{Pub, Dpub, Mpub};
+pub use connection::ConnectionState;
+pub use consumer::{Action, Handler, Message, MessageStream};
+pub use producer::{Batches, Producer};
+pub use bridge::Bridge;
+pub use bytes::Bytes;
+pub use response::Response;
+pub use auth::Authentication;
+pub use config::{Config, NsqConfig};
+pub use http::{HttpClient, MpubMode};
+pub use lookupd::{Lookup, LookupdClient, ProducerInfo};
+pub use codec::{Pub, Dpub, Mpub, Sub};
+pub use error::NsqError;
+pub use msg::Msg;
+pub use result::NsqResult;
+pub use topic::{Topic, Channel};
+#[cfg(feature = "msgpack")]
+pub use typed::MsgPack;
+#[cfg(feature = "bincode")]
+pub use typed::Bincode;
+pub use typed::{Codec, DecodePolicy, Json, Typed, TypedHandler, TypedProducer};
@@ -0,0 +1,244 @@
+// MIT License
+//
+// Copyright (c) 2019-2021 Alessandro Cresto Miseroglio <alex179ohm@gmail.com>
+// Copyright (c) 2019-2021 Tangram Technologies S.R.L. <https://tngrm.io>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Client for the nsqlookupd HTTP API.
+
+use crate::http::{decode_json, encode, null_as_default, request, DEFAULT_TIMEOUT, NSQ_HEADERS};
+use crate::result::NsqResult;
+use crate::topic::{Channel, Topic};
+use serde::Deserialize;
+use std::time::Duration;
+
+/// nsqd instance as registered in nsqlookupd.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct ProducerInfo {
+    pub remote_address: String,
+    pub hostname: String,
+    pub broadcast_address: String,
+    pub tcp_port: u16,
+    pub http_port: u16,
+    pub version: String,
+    /// Only returned by `/nodes`, tombstoned state of each of the `topics`.
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub tombstones: Vec<bool>,
+    /// Only returned by `/nodes`.
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub topics: Vec<String>,
+}
+
+impl ProducerInfo {
+    /// Address of the nsqd TCP protocol.
+    pub fn tcp_address(&self) -> String {
+        format!("{}:{}", self.broadcast_address, self.tcp_port)
+    }
+
+    /// Address of the nsqd HTTP API.
+    pub fn http_address(&self) -> String {
+        format!("{}:{}", self.broadcast_address, self.http_port)
+    }
+}
+
+/// Response of [LookupdClient::lookup](struct.LookupdClient.html#method.lookup).
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct Lookup {
+    #[serde(deserialize_with = "null_as_default")]
+    pub channels: Vec<String>,
+    #[serde(deserialize_with = "null_as_default")]
+    pub producers: Vec<ProducerInfo>,
+}
+
+#[derive(Deserialize)]
+struct Topics {
+    #[serde(deserialize_with = "null_as_default")]
+    topics: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Channels {
+    #[serde(deserialize_with = "null_as_default")]
+    channels: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Nodes {
+    #[serde(deserialize_with = "null_as_default")]
+    producers: Vec<ProducerInfo>,
+}
+
+/// nsqlookupd HTTP API client.
+///
+/// Both the pre 1.0 (`{"status_code": .., "data": ..}`) and the plain response
+/// formats are accepted.
+///
+/// # Examples
+///```no-run
+/// use nsq_rust::{LookupdClient, Topic};
+///
+/// let lookupd = LookupdClient::new("localhost:4161");
+/// for producer in lookupd.lookup(&Topic::new("test")?).await?.producers {
+///     println!("{}", producer.tcp_address());
+/// }
+///```
+#[derive(Clone, Debug)]
+pub struct LookupdClient {
+    addr: String,
+    timeout: Duration,
+}
+
+impl LookupdClient {
+    /// `addr` is the nsqlookupd HTTP address (`host:port`), by default nsqlookupd listens on 4161.
+    pub fn new<ADDR: Into<String>>(addr: ADDR) -> Self {
+        LookupdClient {
+            addr: addr.into(),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Fail the requests not answered within `timeout` (default: 20 seconds).
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    pub async fn ping(&self) -> NsqResult<()> {
+        self.get("/ping").await.map(|_| ())
+    }
+
+    /// Producers of `topic` and its channels.
+    pub async fn lookup(&self, topic: &Topic) -> NsqResult<Lookup> {
+        let path = format!("/lookup?topic={}", encode(topic.as_str()));
+        decode_json(&self.get(&path).await?)
+    }
+
+    pub async fn topics(&self) -> NsqResult<Vec<String>> {
+        decode_json::<Topics>(&self.get("/topics").await?).map(|t| t.topics)
+    }
+
+    pub async fn channels(&self, topic: &Topic) -> NsqResult<Vec<String>> {
+        let path = format!("/channels?topic={}", encode(topic.as_str()));
+        decode_json::<Channels>(&self.get(&path).await?).map(|c| c.channels)
+    }
+
+    /// All the nsqd registered, with their topics and tombstones.
+    pub async fn nodes(&self) -> NsqResult<Vec<ProducerInfo>> {
+        decode_json::<Nodes>(&self.get("/nodes").await?).map(|n| n.producers)
+    }
+
+    pub async fn create_topic(&self, topic: &Topic) -> NsqResult<()> {
+        let path = format!("/topic/create?topic={}", encode(topic.as_str()));
+        self.post(&path).await
+    }
+
+    pub async fn delete_topic(&self, topic: &Topic) -> NsqResult<()> {
+        let path = format!("/topic/delete?topic={}", encode(topic.as_str()));
+        self.post(&path).await
+    }
+
+    pub async fn create_channel(&self, topic: &Topic, channel: &Channel) -> NsqResult<()> {
+        let path = format!(
+            "/channel/create?topic={}&channel={}",
+            encode(topic.as_str()),
+            encode(channel.as_str())
+        );
+        self.post(&path).await
+    }
+
+    pub async fn delete_channel(&self, topic: &Topic, channel: &Channel) -> NsqResult<()> {
+        let path = format!(
+            "/channel/delete?topic={}&channel={}",
+            encode(topic.as_str()),
+            encode(channel.as_str())
+        );
+        self.post(&path).await
+    }
+
+    /// Tombstone `topic` for the nsqd identified by `node` (`broadcast_address:http_port`),
+    /// see [ProducerInfo::http_address](struct.ProducerInfo.html#method.http_address).
+    pub async fn tombstone_topic(&self, topic: &Topic, node: &str) -> NsqResult<()> {
+        let path = format!("/topic/tombstone?topic={}&node={}", encode(topic.as_str()), encode(node));
+        self.post(&path).await
+    }
+
+    async fn get(&self, path: &str) -> NsqResult<Vec<u8>> {
+        request(&self.addr, "GET", path, NSQ_HEADERS, &[], self.timeout).await?.into_result()
+    }
+
+    async fn post(&self, path: &str) -> NsqResult<()> {
+        request(&self.addr, "POST", path, NSQ_HEADERS, &[], self.timeout).await?.into_result().map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::NsqError;
+
+    #[test]
+    fn lookup_enveloped() {
+        // nsqlookupd 0.3.8
+        let body = br#"{"status_code":200,"status_txt":"OK","data":{"channels":["ch"],"producers":[
+            {"remote_address":"127.0.0.1:52410","hostname":"nsqd.local","broadcast_address":"nsqd.local",
+            "tcp_port":4150,"http_port":4151,"version":"0.3.8"}]}}"#;
+        let lookup: Lookup = decode_json(body).unwrap();
+        assert_eq!(lookup.channels, vec!["ch"]);
+        assert_eq!(lookup.producers.len(), 1);
+        assert_eq!(lookup.producers[0].tcp_address(), "nsqd.local:4150");
+        assert_eq!(lookup.producers[0].http_address(), "nsqd.local:4151");
+        assert!(lookup.producers[0].topics.is_empty());
+    }
+
+    #[test]
+    fn lookup_plain() {
+        // nsqlookupd 1.2.1, a topic without channels
+        let body = br#"{"channels":null,"producers":[{"remote_address":"127.0.0.1:52410","hostname":"nsqd.local",
+            "broadcast_address":"10.0.0.1","tcp_port":4150,"http_port":4151,"version":"1.2.1"}]}"#;
+        let lookup: Lookup = decode_json(body).unwrap();
+        assert!(lookup.channels.is_empty());
+        assert_eq!(lookup.producers[0].tcp_address(), "10.0.0.1:4150");
+        assert_eq!(lookup.producers[0].version, "1.2.1");
+    }
+
+    #[test]
+    fn nodes_plain() {
+        let body = br#"{"producers":[{"remote_address":"127.0.0.1:52410","hostname":"nsqd.local",
+            "broadcast_address":"nsqd.local","tcp_port":4150,"http_port":4151,"version":"1.2.1",
+            "tombstones":[false,true],"topics":["a","b"]}]}"#;
+        let nodes: Nodes = decode_json(body).unwrap();
+        assert_eq!(nodes.producers[0].topics, vec!["a", "b"]);
+        assert_eq!(nodes.producers[0].tombstones, vec![false, true]);
+    }
+
+    #[test]
+    fn lookup_malformed() {
+        let res = decode_json::<Lookup>(br#"{"message":"TOPIC_NOT_FOUND"}"#);
+        assert!(matches!(res, Err(NsqError::Json(_))), "{:?}", res);
+        let res = decode_json::<Lookup>(br#"{"channels":[],"producers":[{"hostname":"nsqd.local"}]}"#);
+        assert!(matches!(res, Err(NsqError::Json(_))), "{:?}", res);
+        let res = decode_json::<Lookup>(br#"{"status_code":500,"status_txt":"INTERNAL_ERROR","data":null}"#);
+        assert!(matches!(res, Err(NsqError::Http(500, _))), "{:?}", res);
+    }
+}
@@ -0,0 +1,555 @@
+// MIT License
+//
+// Copyright (c) 2019-2021 Alessandro Cresto Miseroglio <alex179ohm@gmail.com>
+// Copyright (c) 2019-2021 Tangram Technologies S.R.L. <https://tngrm.io>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Client metrics: built-in counters read with `stats()` and a pluggable [MetricsSink](trait.MetricsSink.html).
+//!
+//! Every [Client](../struct.Client.html) keeps counters shared by the producers and
+//! consumers it creates, [Client::stats](../struct.Client.html#method.stats) returns a
+//! [Snapshot](struct.Snapshot.html). Events are also forwarded to the sink set with
+//! [Client::metrics](../struct.Client.html#method.metrics), to feed another metrics system.
+//!
+//! With the `prometheus` feature a snapshot can be rendered in the Prometheus text format.
+//!
+//! # Examples
+//!```no-run
+//! use nsq_rust::metrics::MetricsSink;
+//! use nsq_rust::{Client, Config};
+//! use std::time::Duration;
+//!
+//! struct Statsd;
+//!
+//! impl MetricsSink for Statsd {
+//!     fn publish_latency(&self, addr: &str, latency: Duration) {
+//!         println!("nsq.publish.latency:{}|ms|#addr:{}", latency.as_millis(), addr);
+//!     }
+//! }
+//!
+//! let client = Client::new("localhost:4150", Config::new(), None, None).metrics(Statsd);
+//! println!("{:?}", client.stats());
+//!```
+
+use futures::io::{AsyncRead, AsyncWrite};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+/// Upper bounds (milliseconds) of the publish latency histogram buckets.
+pub const LATENCY_BUCKETS: [u64; 12] = [1, 2, 5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000];
+
+/// Receives the events of every connection of a client, `addr` is the nsqd address.
+///
+/// All methods default to doing nothing.
+pub trait MetricsSink: Send + Sync {
+    /// A message has been delivered by nsqd.
+    fn message_received(&self, _addr: &str, _size: usize) {}
+    /// FIN sent.
+    fn message_finished(&self, _addr: &str) {}
+    /// REQ sent.
+    fn message_requeued(&self, _addr: &str) {}
+    /// FIN, REQ or TOUCH failed, the message timed out on nsqd.
+    fn message_timed_out(&self, _addr: &str) {}
+    /// Bytes read from the socket.
+    fn bytes_in(&self, _addr: &str, _bytes: usize) {}
+    /// Bytes written to the socket.
+    fn bytes_out(&self, _addr: &str, _bytes: usize) {}
+    /// Time between sending a publish and nsqd's response.
+    fn publish_latency(&self, _addr: &str, _latency: Duration) {}
+    /// The connection has been established again.
+    fn reconnected(&self, _addr: &str) {}
+    /// RDY sent.
+    fn rdy(&self, _addr: &str, _count: u32) {}
+    /// Heartbeat received, `gap` is the time since the previous one (or since connecting).
+    fn heartbeat(&self, _addr: &str, _gap: Duration) {}
+}
+
+/// Counters of a client, see [Client::stats](../struct.Client.html#method.stats).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Snapshot {
+    pub messages_received: u64,
+    pub messages_finished: u64,
+    pub messages_requeued: u64,
+    pub messages_timed_out: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub reconnects: u64,
+    pub publish_latency: Histogram,
+    /// Connections currently open.
+    pub connections: Vec<ConnectionSnapshot>,
+}
+
+/// Publish latency histogram, buckets are [LATENCY_BUCKETS](constant.LATENCY_BUCKETS.html).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Histogram {
+    /// Cumulative counts, one per bucket.
+    pub buckets: Vec<u64>,
+    pub count: u64,
+    pub sum: Duration,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConnectionSnapshot {
+    /// Identifies the connection within the client.
+    pub id: u32,
+    pub addr: String,
+    /// Last RDY count sent.
+    pub rdy: u32,
+    /// Percentage of the channel's messages delivered, as negotiated with nsqd (0: all).
+    pub sample_rate: u16,
+    /// Time between the last two heartbeats.
+    pub heartbeat_gap: Option<Duration>,
+    /// Longest time between two heartbeats.
+    pub max_heartbeat_gap: Option<Duration>,
+}
+
+#[derive(Default)]
+struct Connection {
+    addr: String,
+    rdy: u32,
+    sample_rate: u16,
+    last_heartbeat: Option<Instant>,
+    heartbeat_gap: Option<Duration>,
+    max_heartbeat_gap: Option<Duration>,
+}
+
+/// Counters shared by the connections of a client.
+#[derive(Default)]
+pub(crate) struct Metrics {
+    messages_received: AtomicU64,
+    messages_finished: AtomicU64,
+    messages_requeued: AtomicU64,
+    messages_timed_out: AtomicU64,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    reconnects: AtomicU64,
+    latency_buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    latency_count: AtomicU64,
+    latency_sum_us: AtomicU64,
+    next_connection: AtomicU32,
+    connections: Mutex<HashMap<u32, Connection>>,
+    sink: Option<Arc<dyn MetricsSink>>,
+}
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.snapshot())
+    }
+}
+
+impl Metrics {
+    pub(crate) fn with_sink(sink: Arc<dyn MetricsSink>) -> Self {
+        Metrics {
+            sink: Some(sink),
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> Snapshot {
+        let mut connections: Vec<ConnectionSnapshot> = self
+            .connections
+            .lock()
+            .expect("metrics lock poisoned")
+            .iter()
+            .map(|(id, c)| ConnectionSnapshot {
+                id: *id,
+                addr: c.addr.clone(),
+                rdy: c.rdy,
+                sample_rate: c.sample_rate,
+                heartbeat_gap: c.heartbeat_gap,
+                max_heartbeat_gap: c.max_heartbeat_gap,
+            })
+            .collect();
+        connections.sort_by_key(|c| c.id);
+        Snapshot {
+            messages_received: self.messages_received.load(Ordering::Relaxed),
+            messages_finished: self.messages_finished.load(Ordering::Relaxed),
+            messages_requeued: self.messages_requeued.load(Ordering::Relaxed),
+            messages_timed_out: self.messages_timed_out.load(Ordering::Relaxed),
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.bytes_out.load(Ordering::Relaxed),
+            reconnects: self.reconnects.load(Ordering::Relaxed),
+            publish_latency: Histogram {
+                buckets: self.latency_buckets.iter().map(|b| b.load(Ordering::Relaxed)).collect(),
+                count: self.latency_count.load(Ordering::Relaxed),
+                sum: Duration::from_micros(self.latency_sum_us.load(Ordering::Relaxed)),
+            },
+            connections,
+        }
+    }
+}
+
+/// Metrics of a connection, updated by producers and consumers.
+#[derive(Clone)]
+pub(crate) struct Meter(Arc<MeterInner>);
+
+struct MeterInner {
+    id: u32,
+    addr: String,
+    metrics: Arc<Metrics>,
+}
+
+impl Drop for MeterInner {
+    fn drop(&mut self) {
+        self.metrics.connections.lock().expect("metrics lock poisoned").remove(&self.id);
+    }
+}
+
+impl Meter {
+    pub(crate) fn new(addr: &str, metrics: Arc<Metrics>) -> Self {
+        let id = metrics.next_connection.fetch_add(1, Ordering::Relaxed);
+        let connection = Connection {
+            addr: addr.to_owned(),
+            last_heartbeat: Some(Instant::now()),
+            ..Default::default()
+        };
+        metrics.connections.lock().expect("metrics lock poisoned").insert(id, connection);
+        Meter(Arc::new(MeterInner {
+            id,
+            addr: addr.to_owned(),
+            metrics,
+        }))
+    }
+
+    pub(crate) fn snapshot(&self) -> Snapshot {
+        self.0.metrics.snapshot()
+    }
+
+    fn sink(&self) -> Option<&dyn MetricsSink> {
+        self.0.metrics.sink.as_deref()
+    }
+
+    fn connection<F: FnOnce(&mut Connection)>(&self, f: F) {
+        if let Some(c) = self.0.metrics.connections.lock().expect("metrics lock poisoned").get_mut(&self.0.id) {
+            f(c)
+        }
+    }
+
+    pub(crate) fn message_received(&self, size: usize) {
+        self.0.metrics.messages_received.fetch_add(1, Ordering::Relaxed);
+        if let Some(sink) = self.sink() {
+            sink.message_received(&self.0.addr, size);
+        }
+    }
+
+    pub(crate) fn message_finished(&self) {
+        self.0.metrics.messages_finished.fetch_add(1, Ordering::Relaxed);
+        if let Some(sink) = self.sink() {
+            sink.message_finished(&self.0.addr);
+        }
+    }
+
+    pub(crate) fn message_requeued(&self) {
+        self.0.metrics.messages_requeued.fetch_add(1, Ordering::Relaxed);
+        if let Some(sink) = self.sink() {
+            sink.message_requeued(&self.0.addr);
+        }
+    }
+
+    pub(crate) fn message_timed_out(&self) {
+        self.0.metrics.messages_timed_out.fetch_add(1, Ordering::Relaxed);
+        if let Some(sink) = self.sink() {
+            sink.message_timed_out(&self.0.addr);
+        }
+    }
+
+    pub(crate) fn bytes_in(&self, bytes: usize) {
+        self.0.metrics.bytes_in.fetch_add(bytes as u64, Ordering::Relaxed);
+        if let Some(sink) = self.sink() {
+            sink.bytes_in(&self.0.addr, bytes);
+        }
+    }
+
+    pub(crate) fn bytes_out(&self, bytes: usize) {
+        self.0.metrics.bytes_out.fetch_add(bytes as u64, Ordering::Relaxed);
+        if let Some(sink) = self.sink() {
+            sink.bytes_out(&self.0.addr, bytes);
+        }
+    }
+
+    pub(crate) fn publish_latency(&self, latency: Duration) {
+        let metrics = &self.0.metrics;
+        let ms = latency.as_millis() as u64;
+        for (bucket, bound) in metrics.latency_buckets.iter().zip(LATENCY_BUCKETS.iter()) {
+            if ms <= *bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        metrics.latency_count.fetch_add(1, Ordering::Relaxed);
+        metrics.latency_sum_us.fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+        if let Some(sink) = self.sink() {
+            sink.publish_latency(&self.0.addr, latency);
+        }
+    }
+
+    pub(crate) fn reconnected(&self) {
+        self.0.metrics.reconnects.fetch_add(1, Ordering::Relaxed);
+        if let Some(sink) = self.sink() {
+            sink.reconnected(&self.0.addr);
+        }
+    }
+
+    pub(crate) fn rdy(&self, count: u32) {
+        self.connection(|c| c.rdy = count);
+        if let Some(sink) = self.sink() {
+            sink.rdy(&self.0.addr, count);
+        }
+    }
+
+    pub(crate) fn sample_rate(&self, rate: u16) {
+        self.connection(|c| c.sample_rate = rate);
+    }
+
+    pub(crate) fn heartbeat(&self) {
+        let now = Instant::now();
+        let mut gap = Duration::from_secs(0);
+        self.connection(|c| {
+            gap = c.last_heartbeat.map(|last| now - last).unwrap_or_default();
+            c.last_heartbeat = Some(now);
+            c.heartbeat_gap = Some(gap);
+            c.max_heartbeat_gap = c.max_heartbeat_gap.max(Some(gap));
+        });
+        if let Some(sink) = self.sink() {
+            sink.heartbeat(&self.0.addr, gap);
+        }
+    }
+}
+
+/// Socket wrapper counting the bytes read and written.
+pub(crate) struct Metered<S> {
+    stream: S,
+    meter: Meter,
+}
+
+impl<S> Metered<S> {
+    pub(crate) fn new(stream: S, meter: Meter) -> Self {
+        Metered { stream, meter }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Metered<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let res = Pin::new(&mut self.stream).poll_read(cx, buf);
+        if let Poll::Ready(Ok(n)) = res {
+            self.meter.bytes_in(n);
+        }
+        res
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Metered<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let res = Pin::new(&mut self.stream).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = res {
+            self.meter.bytes_out(n);
+        }
+        res
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_close(cx)
+    }
+}
+
+#[cfg(feature = "prometheus")]
+impl Snapshot {
+    /// Render the snapshot in the Prometheus text exposition format, metric names
+    /// are prefixed with `namespace` (e.g. `nsq`).
+    /// ```no-run
+    /// let body = client.stats().to_prometheus("nsq");
+    /// ```
+    pub fn to_prometheus(&self, namespace: &str) -> String {
+        use std::fmt::Write;
+
+        let mut out = String::new();
+        let counters = [
+            ("messages_received_total", "Messages delivered by nsqd.", self.messages_received),
+            ("messages_finished_total", "Messages finished.", self.messages_finished),
+            ("messages_requeued_total", "Messages requeued.", self.messages_requeued),
+            ("messages_timed_out_total", "Messages timed out on nsqd.", self.messages_timed_out),
+            ("bytes_in_total", "Bytes read from nsqd.", self.bytes_in),
+            ("bytes_out_total", "Bytes written to nsqd.", self.bytes_out),
+            ("reconnects_total", "Connections established again.", self.reconnects),
+        ];
+        for (name, help, value) in counters.iter() {
+            let _ = writeln!(out, "# HELP {}_{} {}", namespace, name, help);
+            let _ = writeln!(out, "# TYPE {}_{} counter", namespace, name);
+            let _ = writeln!(out, "{}_{} {}", namespace, name, value);
+        }
+        let name = format!("{}_publish_latency_seconds", namespace);
+        let _ = writeln!(out, "# HELP {} Time between a publish and nsqd's response.", name);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        for (count, bound) in self.publish_latency.buckets.iter().zip(LATENCY_BUCKETS.iter()) {
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, *bound as f64 / 1000.0, count);
+        }
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, self.publish_latency.count);
+        let _ = writeln!(out, "{}_sum {}", name, self.publish_latency.sum.as_secs_f64());
+        let _ = writeln!(out, "{}_count {}", name, self.publish_latency.count);
+        let header = |out: &mut String, name: &str, help: &str| {
+            let _ = writeln!(out, "# HELP {}_{} {}", namespace, name, help);
+            let _ = writeln!(out, "# TYPE {}_{} gauge", namespace, name);
+        };
+        header(&mut out, "rdy", "Last RDY count sent.");
+        for c in &self.connections {
+            let _ = writeln!(out, "{}_rdy{} {}", namespace, labels(c), c.rdy);
+        }
+        header(&mut out, "sample_rate", "Percentage of the channel's messages delivered, 0 for all.");
+        for c in &self.connections {
+            let _ = writeln!(out, "{}_sample_rate{} {}", namespace, labels(c), c.sample_rate);
+        }
+        header(&mut out, "heartbeat_gap_seconds", "Time between the last two heartbeats.");
+        for c in &self.connections {
+            if let Some(gap) = c.heartbeat_gap {
+                let _ = writeln!(out, "{}_heartbeat_gap_seconds{} {}", namespace, labels(c), gap.as_secs_f64());
+            }
+        }
+        out
+    }
+}
+
+#[cfg(feature = "prometheus")]
+fn labels(c: &ConnectionSnapshot) -> String {
+    let addr = c.addr.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("{{addr=\"{}\",conn=\"{}\"}}", addr, c.id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use futures::io::{AsyncReadExt, AsyncWriteExt, Cursor};
+
+    /// Sink recording the events it receives.
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl MetricsSink for Recorder {
+        fn message_received(&self, addr: &str, size: usize) {
+            self.0.lock().unwrap().push(format!("received {} {}", addr, size));
+        }
+
+        fn publish_latency(&self, addr: &str, latency: Duration) {
+            self.0.lock().unwrap().push(format!("latency {} {:?}", addr, latency));
+        }
+    }
+
+    #[test]
+    fn counters() {
+        let recorder = Arc::new(Recorder::default());
+        let metrics = Arc::new(Metrics::with_sink(recorder.clone()));
+        let meter = Meter::new("nsqd:4150", metrics.clone());
+        meter.message_received(10);
+        meter.message_received(5);
+        meter.message_finished();
+        meter.message_requeued();
+        meter.message_timed_out();
+        meter.reconnected();
+        meter.rdy(20);
+        meter.sample_rate(50);
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.messages_received, 2);
+        assert_eq!(snapshot.messages_finished, 1);
+        assert_eq!(snapshot.messages_requeued, 1);
+        assert_eq!(snapshot.messages_timed_out, 1);
+        assert_eq!(snapshot.reconnects, 1);
+        assert_eq!(snapshot.connections.len(), 1);
+        assert_eq!((snapshot.connections[0].rdy, snapshot.connections[0].sample_rate), (20, 50));
+        assert_eq!(*recorder.0.lock().unwrap(), vec!["received nsqd:4150 10", "received nsqd:4150 5"]);
+
+        drop(meter);
+        assert!(metrics.snapshot().connections.is_empty());
+        assert_eq!(metrics.snapshot().messages_received, 2);
+    }
+
+    #[test]
+    fn latency() {
+        let metrics = Arc::new(Metrics::default());
+        let meter = Meter::new("nsqd:4150", metrics.clone());
+        for ms in &[3, 30, 7000] {
+            meter.publish_latency(Duration::from_millis(*ms));
+        }
+
+        let latency = metrics.snapshot().publish_latency;
+        assert_eq!(latency.count, 3);
+        assert_eq!(latency.sum, Duration::from_millis(7033));
+        // cumulative, 7s is above the last bucket
+        assert_eq!(latency.buckets, vec![0, 0, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn bytes() {
+        let metrics = Arc::new(Metrics::default());
+        let meter = Meter::new("nsqd:4150", metrics.clone());
+        let mut stream = Metered::new(Cursor::new(b"0123456789".to_vec()), meter);
+        let mut buf = [0u8; 4];
+        block_on(stream.read_exact(&mut buf)).unwrap();
+        block_on(stream.write_all(b"abc")).unwrap();
+
+        let snapshot = metrics.snapshot();
+        assert_eq!((snapshot.bytes_in, snapshot.bytes_out), (4, 3));
+    }
+
+    #[cfg(feature = "prometheus")]
+    #[test]
+    fn prometheus() {
+        let metrics = Arc::new(Metrics::default());
+        let meter = Meter::new("nsqd:4150", metrics.clone());
+        meter.message_received(10);
+        meter.rdy(5);
+        meter.publish_latency(Duration::from_millis(30));
+
+        let text = metrics.snapshot().to_prometheus("nsq");
+        let lines: Vec<&str> = text.lines().collect();
+        for expected in &[
+            "# HELP nsq_messages_received_total Messages delivered by nsqd.",
+            "# TYPE nsq_messages_received_total counter",
+            "nsq_messages_received_total 1",
+            "nsq_messages_finished_total 0",
+            "# TYPE nsq_publish_latency_seconds histogram",
+            "nsq_publish_latency_seconds_bucket{le=\"0.025\"} 0",
+            "nsq_publish_latency_seconds_bucket{le=\"0.05\"} 1",
+            "nsq_publish_latency_seconds_bucket{le=\"+Inf\"} 1",
+            "nsq_publish_latency_seconds_sum 0.03",
+            "nsq_publish_latency_seconds_count 1",
+            "# TYPE nsq_rdy gauge",
+            "nsq_rdy{addr=\"nsqd:4150\",conn=\"0\"} 5",
+            "nsq_sample_rate{addr=\"nsqd:4150\",conn=\"0\"} 0",
+        ] {
+            assert!(lines.contains(expected), "{} missing from\n{}", expected, text);
+        }
+        // every sample follows the HELP and TYPE of its metric
+        for line in lines.iter().filter(|l| !l.starts_with('#')) {
+            let name = line.split(['{', ' ']).next().unwrap();
+            let base = name.trim_end_matches("_bucket").trim_end_matches("_sum").trim_end_matches("_count");
+            assert!(lines.iter().any(|l| l.starts_with(&format!("# TYPE {} ", base))), "{}", line);
+        }
+    }
+}
@@ -21,7 +21,7 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Msg {
     timestamp: i64,
     attemps: u16,
@@ -29,13 +29,33 @@ pub struct Msg {
     body: Vec<u8>,
 }
 
-impl Into<Msg> for (i64, u16, String, Vec<u8>) {
-    fn into(self) -> Msg {
+impl From<(i64, u16, String, Vec<u8>)> for Msg {
+    fn from(m: (i64, u16, String, Vec<u8>)) -> Msg {
         Msg {
-            timestamp: self.0,
-            attemps: self.1,
-            id: self.2,
-            body: self.3,
+            timestamp: m.0,
+            attemps: m.1,
+            id: m.2,
+            body: m.3,
         }
     }
 }
+
+impl Msg {
+    /// Time the message was published (nanoseconds since epoch).
+    pub fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+
+    /// Number of times nsqd delivered the message.
+    pub fn attempts(&self) -> u16 {
+        self.attemps
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+}
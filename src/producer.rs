@@ -0,0 +1,355 @@
+// MIT License
+//
+// Copyright (c) 2019-2021 Alessandro Cresto Miseroglio <alex179ohm@gmail.com>
+// Copyright (c) 2019-2021 Tangram Technologies S.R.L. <https://tngrm.io>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::client::Client;
+use crate::codec::{Dpub, Encoder, Mpub, Pub};
+use crate::connection::{Connection, Session};
+use crate::error::NsqError;
+use crate::metrics::Snapshot;
+use crate::response::Response;
+use crate::result::NsqResult;
+use crate::retry::Outage;
+use crate::rt;
+use crate::topic::Topic;
+use crate::trace::{self, span, Span};
+use bytes::{Bytes, BytesMut};
+use futures::channel::oneshot;
+use futures::future::poll_fn;
+use futures::{ready, FutureExt, Sink, SinkExt, Stream};
+use log::debug;
+use std::io;
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+const MAX_UNACKNOWLEDGED: usize = 128;
+
+/// Connection to nsqd used to publish, created by [Client::producer](struct.Client.html#method.producer).
+///
+/// # Examples
+///```no-run
+/// use nsq_rust::{Client, Config, Topic};
+///
+/// let mut producer = Client::new("localhost:4150", Config::new(), None, None).producer().await?;
+/// let topic = Topic::new("test")?;
+/// producer.publish(&topic, b"hello".to_vec()).await?;
+/// producer.mpublish(&topic, vec![b"a".to_vec(), b"b".to_vec()]).await?;
+///```
+///
+/// The producer is also a `Sink<(Topic, Bytes)>`: PUB commands are pipelined, at most
+/// [max_unacknowledged](#method.max_unacknowledged) of them waiting for nsqd's response,
+/// and flushing completes once nsqd acknowledged every message sent. MPUBs are pipelined the
+/// same way through [batches](#method.batches).
+///
+///```no-run
+/// use futures::{stream, StreamExt};
+/// use nsq_rust::{Bytes, Client, Config, Topic};
+///
+/// let producer = Client::new("localhost:4150", Config::new(), None, None).producer().await?;
+/// let topic = Topic::new("test")?;
+/// stream::iter(0..1000)
+///     .map(|i| Ok((topic.clone(), Bytes::from(i.to_string()))))
+///     .forward(producer)
+///     .await?;
+///```
+///
+/// When the connection drops the producer reconnects in the background according to the
+/// [retry policy](retry/index.html) of the client. With [Outage::Wait](retry/enum.Outage.html)
+/// publishes wait for the new connection and the commands nsqd didn't acknowledge are sent
+/// again, so a message may be published twice if the connection dropped before its response
+/// arrived. With `Outage::Fail` they fail with `NsqError::Disconnected` until the producer is
+/// connected again.
+///
+/// Heartbeats are answered in the background, an idle producer stays connected.
+pub struct Producer {
+    client: Client,
+    conn: Session,
+    /// Connection being established again, in the background.
+    reconnecting: Option<oneshot::Receiver<NsqResult<Session>>>,
+    /// Send time, name and encoded command of the messages waiting for a response.
+    unacknowledged: VecDeque<(Instant, &'static str, Bytes)>,
+    /// Unacknowledged commands left to send again on a new connection.
+    replay: usize,
+    max_unacknowledged: usize,
+    pub(crate) span: Span,
+}
+
+impl Producer {
+    pub(crate) fn new(client: Client, conn: Session, span: Span) -> Self {
+        Producer {
+            client,
+            conn,
+            reconnecting: None,
+            unacknowledged: VecDeque::new(),
+            replay: 0,
+            max_unacknowledged: MAX_UNACKNOWLEDGED,
+            span,
+        }
+    }
+
+    /// Messages sent through the `Sink` waiting for nsqd's response before
+    /// `poll_ready` applies backpressure (default 128).
+    pub fn max_unacknowledged(mut self, max: usize) -> Self {
+        self.max_unacknowledged = max.max(1);
+        self
+    }
+
+    /// `Sink` of MPUBs sharing the pipeline of the producer, each MPUB counts once against
+    /// `max_unacknowledged`.
+    pub fn batches(&mut self) -> Batches<'_> {
+        Batches(self)
+    }
+
+    pub async fn publish(&mut self, topic: &Topic, msg: Vec<u8>) -> NsqResult<Response> {
+        let span = span!(parent: &self.span, "nsq.publish", topic = %topic, count = 1);
+        trace::instrument(self.send(Pub::new(topic.clone(), msg)), span).await
+    }
+
+    pub async fn mpublish(&mut self, topic: &Topic, msgs: Vec<Vec<u8>>) -> NsqResult<Response> {
+        let span = span!(parent: &self.span, "nsq.publish", topic = %topic, count = msgs.len());
+        let mpub = Mpub::new(topic.clone(), msgs)?;
+        trace::instrument(self.send(mpub), span).await
+    }
+
+    pub async fn dpublish(&mut self, topic: &Topic, delay: Duration, msg: Vec<u8>) -> NsqResult<Response> {
+        let span = span!(parent: &self.span, "nsq.publish", topic = %topic, count = 1, delay_ms = delay.as_millis() as u64);
+        trace::instrument(self.send(Dpub::new(topic.clone(), delay, msg)), span).await
+    }
+
+    /// Send an encoded command and wait for nsqd to acknowledge it.
+    ///
+    /// Messages sent through the `Sink` are flushed first.
+    pub async fn send<T: Encoder>(&mut self, cmd: T) -> NsqResult<Response> {
+        if !self.unacknowledged.is_empty() || self.conn.has_pending() {
+            SinkExt::<(Topic, Bytes)>::flush(self).await?;
+        }
+        let name = cmd.name();
+        let cmd = encode(cmd);
+        loop {
+            poll_fn(|cx| self.poll_connected(cx)).await?;
+            let sent = Instant::now();
+            let res = async {
+                self.conn.push_encoded(name, cmd.clone()).await?;
+                self.conn.flush().await?;
+                self.conn.response().await
+            }
+            .await;
+            match res {
+                Err(e) if e.is_connection_error() => self.disconnected(e)?,
+                res => {
+                    if let (Ok(_), Some(meter)) = (&res, self.conn.meter()) {
+                        meter.publish_latency(sent.elapsed());
+                    }
+                    return res;
+                }
+            }
+        }
+    }
+
+    /// Counters of the client that created this producer.
+    pub fn stats(&self) -> Snapshot {
+        self.conn.meter().map(|m| m.snapshot()).unwrap_or_default()
+    }
+
+    /// Start reconnecting in the background after `error`, returned in `Outage::Fail` mode.
+    ///
+    /// The unacknowledged commands are sent again on the new connection, or dropped
+    /// in `Outage::Fail` mode: they were reported as failed.
+    fn disconnected(&mut self, error: NsqError) -> NsqResult<()> {
+        let (tx, rx) = oneshot::channel();
+        let client = self.client.clone();
+        rt::spawn(async move {
+            let _ = tx.send(client.reconnect(error, &mut 0).await.map(Connection::start));
+        });
+        self.reconnecting = Some(rx);
+        self.replay = 0;
+        match self.client.outage_mode() {
+            Outage::Wait => Ok(()),
+            Outage::Fail => {
+                self.unacknowledged.clear();
+                Err(NsqError::Disconnected)
+            }
+        }
+    }
+
+    /// Wait for the background reconnection, if any, and send the unacknowledged commands again.
+    fn poll_connected(&mut self, cx: &mut Context<'_>) -> Poll<NsqResult<()>> {
+        loop {
+            ready!(self.poll_reconnected(cx))?;
+            match ready!(self.poll_replay(cx)) {
+                Err(e) if e.is_connection_error() => self.disconnected(e)?,
+                res => return Poll::Ready(res),
+            }
+        }
+    }
+
+    fn poll_reconnected(&mut self, cx: &mut Context<'_>) -> Poll<NsqResult<()>> {
+        if let Some(rx) = &mut self.reconnecting {
+            let res = match rx.poll_unpin(cx) {
+                Poll::Ready(res) => res.unwrap_or(Err(NsqError::Disconnected)),
+                Poll::Pending if self.client.outage_mode() == Outage::Fail => {
+                    return Poll::Ready(Err(NsqError::Disconnected))
+                }
+                Poll::Pending => return Poll::Pending,
+            };
+            self.reconnecting = None;
+            match res {
+                Ok(conn) => {
+                    self.conn = conn;
+                    self.replay = self.unacknowledged.len();
+                }
+                Err(e) => {
+                    // the retry policy gave up, the next publish tries again
+                    self.unacknowledged.clear();
+                    return Poll::Ready(Err(e));
+                }
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Send the unacknowledged commands again, one by one in the order they were first sent.
+    fn poll_replay(&mut self, cx: &mut Context<'_>) -> Poll<NsqResult<()>> {
+        if self.replay > 0 {
+            while self.replay > 0 {
+                ready!(self.conn.poll_ready(cx))?;
+                let (_, name, cmd) = &self.unacknowledged[self.unacknowledged.len() - self.replay];
+                self.conn.start_push(name, cmd.clone())?;
+                self.replay -= 1;
+            }
+            ready!(self.conn.poll_flush(cx))?;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Run `f` on the connection, reconnecting when it fails.
+    fn poll_io<F>(&mut self, cx: &mut Context<'_>, mut f: F) -> Poll<NsqResult<()>>
+    where
+        F: FnMut(&mut Self, &mut Context<'_>) -> Poll<NsqResult<()>>,
+    {
+        loop {
+            ready!(self.poll_connected(cx))?;
+            match ready!(f(self, cx)) {
+                Err(e) if e.is_connection_error() => self.disconnected(e)?,
+                res => return Poll::Ready(res),
+            }
+        }
+    }
+
+    /// Encode and send `cmd` without waiting for its response.
+    fn start_command<T: Encoder>(&mut self, cmd: T) -> NsqResult<()> {
+        let name = cmd.name();
+        let cmd = encode(cmd);
+        self.unacknowledged.push_back((Instant::now(), name, cmd.clone()));
+        match self.conn.start_push(name, cmd) {
+            // sent again once connected
+            Err(e) if e.is_connection_error() => self.disconnected(e),
+            Err(e) => {
+                self.unacknowledged.pop_back();
+                Err(e)
+            }
+            Ok(()) => Ok(()),
+        }
+    }
+
+    /// Read the response of the oldest unacknowledged message.
+    fn poll_ack(&mut self, cx: &mut Context<'_>) -> Poll<NsqResult<()>> {
+        match ready!(Pin::new(&mut self.conn).poll_next(cx)) {
+            Some(Err(e)) if e.is_connection_error() => Poll::Ready(Err(e)),
+            Some(res) => {
+                let sent = self.unacknowledged.pop_front();
+                if let (Ok(_), Some(meter), Some((sent, ..))) = (&res, self.conn.meter(), sent) {
+                    meter.publish_latency(sent.elapsed());
+                }
+                debug!("publish acknowledged: {:?}", res);
+                Poll::Ready(res.map(|_| ()))
+            }
+            None => Poll::Ready(Err(io::Error::from(io::ErrorKind::UnexpectedEof).into())),
+        }
+    }
+}
+
+impl Sink<(Topic, Bytes)> for Producer {
+    type Error = NsqError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<NsqResult<()>> {
+        self.get_mut().poll_io(cx, |this, cx| {
+            while this.unacknowledged.len() >= this.max_unacknowledged {
+                ready!(this.conn.poll_flush(cx))?;
+                ready!(this.poll_ack(cx))?;
+            }
+            this.conn.poll_ready(cx)
+        })
+    }
+
+    fn start_send(self: Pin<&mut Self>, (topic, msg): (Topic, Bytes)) -> NsqResult<()> {
+        self.get_mut().start_command(Pub::new(topic, msg.to_vec()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<NsqResult<()>> {
+        self.get_mut().poll_io(cx, |this, cx| {
+            ready!(this.conn.poll_flush(cx))?;
+            while !this.unacknowledged.is_empty() {
+                ready!(this.poll_ack(cx))?;
+            }
+            Poll::Ready(Ok(()))
+        })
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<NsqResult<()>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        self.conn.close();
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// `Sink<Mpub>` pipelining MPUBs like the producer pipelines PUBs, returned by
+/// [Producer::batches](struct.Producer.html#method.batches).
+pub struct Batches<'a>(&'a mut Producer);
+
+impl Sink<Mpub> for Batches<'_> {
+    type Error = NsqError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<NsqResult<()>> {
+        Sink::<(Topic, Bytes)>::poll_ready(Pin::new(&mut *self.get_mut().0), cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, mpub: Mpub) -> NsqResult<()> {
+        self.get_mut().0.start_command(mpub)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<NsqResult<()>> {
+        Sink::<(Topic, Bytes)>::poll_flush(Pin::new(&mut *self.get_mut().0), cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<NsqResult<()>> {
+        Sink::<(Topic, Bytes)>::poll_close(Pin::new(&mut *self.get_mut().0), cx)
+    }
+}
+
+fn encode<T: Encoder>(cmd: T) -> Bytes {
+    let mut buf = BytesMut::new();
+    cmd.encode(&mut buf);
+    buf.freeze()
+}
@@ -1,40 +0,0 @@
-// MIT License
-//
-// Copyright (c) 2019-2021 Alessandro Cresto Miseroglio <alex179ohm@gmail.com>
-// Copyright (c) 2019-2021 Tangram Technologies S.R.L. <https://tngrm.io>
-//
-// Permission is hereby granted, free of charge, to any person obtaining a copy
-// of this software and associated documentation files (the "Software"), to deal
-// in the Software without restriction, including without limitation the rights
-// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
-// copies of the Software, and to permit persons to whom the Software is
-// furnished to do so, subject to the following conditions:
-//
-// The above copyright notice and this permission notice shall be included in all
-// copies or substantial portions of the Software.
-//
-// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
-// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
-// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
-// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
-// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
-// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
-// SOFTWARE.
-
-use futures::io::{AsyncWrite, AsyncWriteExt};
-use futures::Stream;
-use async_std::stream::StreamExt;
-use bytes::BytesMut;
-use crate::result::NsqResult;
-use crate::response::Response;
-use crate::error::NsqError;
-
-pub async fn io_pub<S>(io: &mut S, buf: &mut BytesMut) -> NsqResult<Response>
-    where
-        S: AsyncWrite + Stream<Item = NsqResult<Response>> + Unpin,
-{
-    if let Err(e) = io.write_all(&buf.take()[..]).await {
-        return Err(NsqError::from(e));
-    }
-    io.next().await.unwrap()
-}
@@ -31,26 +31,26 @@ pub enum Response {
     Json(String),
 }
 
-impl Into<Response> for &'_ str {
-    fn into(self) -> Response {
-        match self {
+impl From<&'_ str> for Response {
+    fn from(s: &'_ str) -> Response {
+        match s {
             "OK" => Response::Ok,
             "CLOSE_WAIT" => Response::Ok,
-            "__heartbeat__" => Response::HeartBeat,
+            "_heartbeat_" => Response::HeartBeat,
             s => Response::Json(String::from(s)),
         }
     }
 }
 
-impl Into<Response> for (i64, u16, String, Vec<u8>) {
-    fn into(self) -> Response {
-        Response::Msg(self.into())
+impl From<(i64, u16, String, Vec<u8>)> for Response {
+    fn from(m: (i64, u16, String, Vec<u8>)) -> Response {
+        Response::Msg(m.into())
     }
 }
 
-impl Into<Response> for Msg {
-    fn into(self) -> Response {
-        Response::Msg(self)
+impl From<Msg> for Response {
+    fn from(m: Msg) -> Response {
+        Response::Msg(m)
     }
 }
 
@@ -65,3 +65,14 @@ impl fmt::Debug for Response {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heartbeat() {
+        assert!(matches!(Response::from("_heartbeat_"), Response::HeartBeat));
+        assert!(matches!(Response::from("__heartbeat__"), Response::Json(_)));
+    }
+}
@@ -27,14 +27,14 @@ use crate::response::Response;
 
 pub type NsqResult<T> = Result<T, NsqError>;
 
-impl Into<NsqResult<Response>> for Response {
-    fn into(self) -> NsqResult<Response> {
-        NsqResult::Ok(self)
+impl From<Response> for NsqResult<Response> {
+    fn from(r: Response) -> NsqResult<Response> {
+        NsqResult::Ok(r)
     }
 }
 
-impl Into<NsqResult<Response>> for NsqError {
-    fn into(self) -> NsqResult<Response> {
-        NsqResult::Err(self)
+impl From<NsqError> for NsqResult<Response> {
+    fn from(e: NsqError) -> NsqResult<Response> {
+        NsqResult::Err(e)
     }
 }
@@ -0,0 +1,133 @@
+// MIT License
+//
+// Copyright (c) 2019-2021 Alessandro Cresto Miseroglio <alex179ohm@gmail.com>
+// Copyright (c) 2019-2021 Tangram Technologies S.R.L. <https://tngrm.io>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Reconnection of producers and consumers when the connection to nsqd drops.
+//!
+//! The [RetryPolicy](trait.RetryPolicy.html) of the [Client](../struct.Client.html) decides how
+//! long to wait before each attempt and when to give up, the whole handshake runs again
+//! (MAGIC, IDENTIFY, TLS, AUTH) and consumers subscribe again and restore RDY.
+//! Only network failures trigger a reconnection, errors returned by nsqd are final.
+//!
+//! # Examples
+//!```no-run
+//! use nsq_rust::events::ConnectionEvent;
+//! use nsq_rust::retry::{ExponentialBackoff, Outage};
+//! use nsq_rust::{Client, Config};
+//! use std::time::Duration;
+//!
+//! let client = Client::new("localhost:4150", Config::new(), None, None)
+//!     .retry_policy(ExponentialBackoff::new(Duration::from_millis(100), Duration::from_secs(10)).max_attempts(20))
+//!     .outage(Outage::Fail)
+//!     .on_event(|event: &ConnectionEvent| println!("{:?}", event));
+//!```
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, SystemTime};
+
+/// Decide the delay before each reconnection attempt.
+pub trait RetryPolicy: Send + Sync {
+    /// Delay before the reconnection attempt number `attempt` (starting from 1),
+    /// `None` to give up.
+    fn next_delay(&self, attempt: u32) -> Option<Duration>;
+}
+
+/// Exponential backoff: `initial * 2^(attempt - 1)` capped at `max`, randomized by `jitter`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExponentialBackoff {
+    initial: Duration,
+    max: Duration,
+    jitter: f64,
+    max_attempts: Option<u32>,
+}
+
+impl ExponentialBackoff {
+    /// Retry forever with 20% jitter.
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        ExponentialBackoff {
+            initial,
+            max,
+            jitter: 0.2,
+            max_attempts: None,
+        }
+    }
+
+    /// Give up after `max_attempts` failed attempts.
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = Some(max_attempts);
+        self
+    }
+
+    /// Never give up (default).
+    pub fn forever(mut self) -> Self {
+        self.max_attempts = None;
+        self
+    }
+
+    /// Fraction (0.0 to 1.0) of the delay randomly added or removed.
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+}
+
+impl RetryPolicy for ExponentialBackoff {
+    fn next_delay(&self, attempt: u32) -> Option<Duration> {
+        if self.max_attempts.is_some_and(|max| attempt > max) {
+            return None;
+        }
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1).min(31));
+        let delay = self.initial.checked_mul(factor).unwrap_or(self.max).min(self.max);
+        // uniform in [1 - jitter, 1 + jitter]
+        let unit = (random() >> 11) as f64 / (1u64 << 53) as f64;
+        let scale = 1.0 + self.jitter * (2.0 * unit - 1.0);
+        Some(delay.mul_f64(scale))
+    }
+}
+
+/// Don't reconnect, the error is returned as before.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NoRetry;
+
+impl RetryPolicy for NoRetry {
+    fn next_delay(&self, _attempt: u32) -> Option<Duration> {
+        None
+    }
+}
+
+/// What publishes do while a producer is reconnecting.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Outage {
+    /// Wait for the connection to be established again (default).
+    Wait,
+    /// Fail immediately with [NsqError::Disconnected](../enum.NsqError.html#variant.Disconnected).
+    Fail,
+}
+
+/// Random number from the std hasher seeds, good enough for jitter and ephemeral
+/// channel names but not for cryptography.
+pub fn random() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_nanos());
+    hasher.finish()
+}
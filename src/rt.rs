@@ -0,0 +1,273 @@
+// MIT License
+//
+// Copyright (c) 2019-2021 Alessandro Cresto Miseroglio <alex179ohm@gmail.com>
+// Copyright (c) 2019-2021 Tangram Technologies S.R.L. <https://tngrm.io>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Runtime glue: TCP connects and listeners, timers, spawning and blocking.
+//!
+//! The protocol engine only relies on the `futures` I/O traits, the runtime is selected
+//! with the `runtime-async-std` (default) or `runtime-tokio` cargo features. When both are
+//! enabled tokio is used, so enabling `runtime-tokio` on top of the default features works.
+
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+#[cfg(not(any(feature = "runtime-async-std", feature = "runtime-tokio")))]
+compile_error!("one of the `runtime-async-std` or `runtime-tokio` features must be enabled");
+
+#[cfg(feature = "runtime-tokio")]
+mod imp {
+    use std::future::Future;
+    use std::io;
+    use std::net::SocketAddr;
+    use std::path::Path;
+    use std::time::Duration;
+    use tokio_util::compat::{Compat, TokioAsyncReadCompatExt};
+
+    pub type TcpStream = Compat<tokio::net::TcpStream>;
+
+    pub async fn connect(addr: &str) -> io::Result<TcpStream> {
+        let stream = tokio::net::TcpStream::connect(addr).await?;
+        stream.set_nodelay(true)?;
+        Ok(stream.compat())
+    }
+
+    pub struct TcpListener(tokio::net::TcpListener);
+
+    impl TcpListener {
+        pub async fn bind(addr: &str) -> io::Result<Self> {
+            tokio::net::TcpListener::bind(addr).await.map(TcpListener)
+        }
+
+        pub async fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+            let (stream, addr) = self.0.accept().await?;
+            stream.set_nodelay(true)?;
+            Ok((stream.compat(), addr))
+        }
+
+        pub fn local_addr(&self) -> io::Result<SocketAddr> {
+            self.0.local_addr()
+        }
+    }
+
+    pub async fn sleep(duration: Duration) {
+        tokio::time::sleep(duration).await
+    }
+
+    pub async fn timeout<F: Future>(duration: Duration, future: F) -> Option<F::Output> {
+        tokio::time::timeout(duration, future).await.ok()
+    }
+
+    pub fn spawn<F>(future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        tokio::spawn(future);
+    }
+
+    pub struct Runtime(tokio::runtime::Runtime);
+
+    impl Runtime {
+        pub fn new() -> io::Result<Self> {
+            tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()
+                .map(Runtime)
+        }
+
+        pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+            self.0.block_on(future)
+        }
+
+        pub fn spawn<F>(&self, future: F)
+        where
+            F: Future<Output = ()> + Send + 'static,
+        {
+            self.0.spawn(future);
+        }
+    }
+
+    pub async fn read_file(path: &Path) -> io::Result<Vec<u8>> {
+        tokio::fs::read(path).await
+    }
+}
+
+#[cfg(all(feature = "runtime-async-std", not(feature = "runtime-tokio")))]
+mod imp {
+    use std::future::Future;
+    use std::io;
+    use std::net::SocketAddr;
+    use std::path::Path;
+    use std::time::Duration;
+
+    pub type TcpStream = async_std::net::TcpStream;
+
+    pub async fn connect(addr: &str) -> io::Result<TcpStream> {
+        let stream = TcpStream::connect(addr).await?;
+        stream.set_nodelay(true)?;
+        Ok(stream)
+    }
+
+    pub struct TcpListener(async_std::net::TcpListener);
+
+    impl TcpListener {
+        pub async fn bind(addr: &str) -> io::Result<Self> {
+            async_std::net::TcpListener::bind(addr).await.map(TcpListener)
+        }
+
+        pub async fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+            let (stream, addr) = self.0.accept().await?;
+            stream.set_nodelay(true)?;
+            Ok((stream, addr))
+        }
+
+        pub fn local_addr(&self) -> io::Result<SocketAddr> {
+            self.0.local_addr()
+        }
+    }
+
+    pub async fn sleep(duration: Duration) {
+        async_std::task::sleep(duration).await
+    }
+
+    pub async fn timeout<F: Future>(duration: Duration, future: F) -> Option<F::Output> {
+        async_std::future::timeout(duration, future).await.ok()
+    }
+
+    pub fn spawn<F>(future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        async_std::task::spawn(future);
+    }
+
+    /// async-std runs a global executor, there is nothing to own.
+    pub struct Runtime;
+
+    impl Runtime {
+        pub fn new() -> io::Result<Self> {
+            Ok(Runtime)
+        }
+
+        pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+            async_std::task::block_on(future)
+        }
+
+        pub fn spawn<F>(&self, future: F)
+        where
+            F: Future<Output = ()> + Send + 'static,
+        {
+            async_std::task::spawn(future);
+        }
+    }
+
+    pub async fn read_file(path: &Path) -> io::Result<Vec<u8>> {
+        async_std::fs::read(path).await
+    }
+}
+
+/// TCP stream of the selected runtime, implementing the `futures` I/O traits.
+pub type TcpStream = imp::TcpStream;
+
+/// Open a TCP connection to `addr` (`host:port`).
+pub async fn connect(addr: &str) -> io::Result<TcpStream> {
+    imp::connect(addr).await
+}
+
+/// TCP listener of the selected runtime.
+pub struct TcpListener(imp::TcpListener);
+
+impl TcpListener {
+    pub async fn bind(addr: &str) -> io::Result<Self> {
+        imp::TcpListener::bind(addr).await.map(TcpListener)
+    }
+
+    pub async fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        self.0.accept().await
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.0.local_addr()
+    }
+}
+
+pub async fn sleep(duration: Duration) {
+    imp::sleep(duration).await
+}
+
+/// Run `future` for at most `duration`, `None` if it did not complete in time.
+pub async fn timeout<F: Future>(duration: Duration, future: F) -> Option<F::Output> {
+    imp::timeout(duration, future).await
+}
+
+/// Spawn `future` on the runtime, it must be called from within the runtime.
+pub fn spawn<F>(future: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    imp::spawn(future)
+}
+
+/// Runtime owned by the caller, used to drive futures from synchronous code.
+///
+/// With tokio this is a multi threaded runtime, stopped when dropped. With async-std it is
+/// a handle to the global executor.
+pub struct Runtime(imp::Runtime);
+
+impl Runtime {
+    pub fn new() -> io::Result<Self> {
+        imp::Runtime::new().map(Runtime)
+    }
+
+    /// Run `future` to completion blocking the current thread.
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.0.block_on(future)
+    }
+
+    pub fn spawn<F>(&self, future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.0.spawn(future)
+    }
+}
+
+/// Run `future` to completion blocking the current thread.
+///
+/// With tokio the [Runtime](struct.Runtime.html) is started on the first call and kept for
+/// the following ones, the tasks spawned by `future` keep running after it completes.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    shared().block_on(future)
+}
+
+/// Runtime of [block_on](fn.block_on.html) and the [blocking](../blocking/index.html) clients.
+pub(crate) fn shared() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| Runtime::new().expect("failed to start runtime"))
+}
+
+pub(crate) async fn read_file(path: &Path) -> io::Result<Vec<u8>> {
+    imp::read_file(path).await
+}
@@ -0,0 +1,229 @@
+// MIT License
+//
+// Copyright (c) 2019-2021 Alessandro Cresto Miseroglio <alex179ohm@gmail.com>
+// Copyright (c) 2019-2021 Tangram Technologies S.R.L. <https://tngrm.io>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Typed nsqd `/stats?format=json` response.
+
+use crate::http::null_as_default;
+use serde::Deserialize;
+
+/// Response of [HttpClient::stats](../struct.HttpClient.html#method.stats).
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Stats {
+    pub version: String,
+    pub health: String,
+    /// Unix timestamp (seconds) nsqd was started.
+    pub start_time: i64,
+    #[serde(deserialize_with = "null_as_default")]
+    pub topics: Vec<TopicStats>,
+    pub memory: Option<MemoryStats>,
+    /// Clients connected to publish only (nsqd >= 1.2).
+    #[serde(deserialize_with = "null_as_default")]
+    pub producers: Vec<ClientStats>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct TopicStats {
+    pub topic_name: String,
+    #[serde(deserialize_with = "null_as_default")]
+    pub channels: Vec<ChannelStats>,
+    /// Messages in memory and on disk.
+    pub depth: i64,
+    pub backend_depth: i64,
+    pub message_count: u64,
+    pub message_bytes: u64,
+    pub paused: bool,
+    pub e2e_processing_latency: E2eLatency,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct ChannelStats {
+    pub channel_name: String,
+    /// Messages in memory and on disk.
+    pub depth: i64,
+    pub backend_depth: i64,
+    pub in_flight_count: i64,
+    pub deferred_count: i64,
+    pub message_count: u64,
+    pub requeue_count: u64,
+    pub timeout_count: u64,
+    pub client_count: u64,
+    #[serde(deserialize_with = "null_as_default")]
+    pub clients: Vec<ClientStats>,
+    pub paused: bool,
+    pub e2e_processing_latency: E2eLatency,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct ClientStats {
+    pub client_id: String,
+    pub hostname: String,
+    pub version: String,
+    pub remote_address: String,
+    pub state: i32,
+    pub ready_count: i64,
+    pub in_flight_count: i64,
+    pub message_count: u64,
+    pub finish_count: u64,
+    pub requeue_count: u64,
+    /// Unix timestamp (seconds) of the connection.
+    pub connect_ts: i64,
+    pub sample_rate: i32,
+    pub deflate: bool,
+    pub snappy: bool,
+    pub user_agent: String,
+    pub authed: bool,
+    pub auth_identity: String,
+    pub auth_identity_url: String,
+    #[serde(deserialize_with = "null_as_default")]
+    pub pub_counts: Vec<PubCount>,
+    pub tls: bool,
+    pub tls_cipher_suite: String,
+    pub tls_version: String,
+    pub tls_negotiated_protocol: String,
+    pub tls_negotiated_protocol_is_mutual: bool,
+}
+
+/// Messages published by a client to a topic.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct PubCount {
+    pub topic: String,
+    pub count: u64,
+}
+
+/// End to end processing latency, only populated when nsqd runs with
+/// `--e2e-processing-latency-percentile`.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct E2eLatency {
+    pub count: u64,
+    #[serde(deserialize_with = "null_as_default")]
+    pub percentiles: Vec<Percentile>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Percentile {
+    pub quantile: f64,
+    /// Latency in nanoseconds.
+    pub value: f64,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct MemoryStats {
+    pub heap_objects: u64,
+    pub heap_idle_bytes: u64,
+    pub heap_in_use_bytes: u64,
+    pub heap_released_bytes: u64,
+    pub gc_pause_usec_100: u64,
+    pub gc_pause_usec_99: u64,
+    pub gc_pause_usec_95: u64,
+    pub next_gc_bytes: u64,
+    pub gc_total_runs: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::NsqError;
+    use crate::http::decode_json;
+
+    /// `/stats?format=json` of nsqd 0.3.8, one consumer connected.
+    const V0_3: &str = r#"{"status_code":200,"status_txt":"OK","data":{"version":"0.3.8","health":"OK",
+        "start_time":1467309823,"topics":[{"topic_name":"test","channels":[{"channel_name":"ch","depth":1,
+        "backend_depth":0,"in_flight_count":1,"deferred_count":0,"message_count":3,"requeue_count":1,
+        "timeout_count":0,"clients":[{"name":"worker","client_id":"worker","hostname":"worker.local",
+        "version":"V2","remote_address":"127.0.0.1:52416","state":3,"ready_count":1,"in_flight_count":1,
+        "message_count":2,"finish_count":1,"requeue_count":1,"connect_ts":1467309850,"sample_rate":0,
+        "deflate":false,"snappy":false,"user_agent":"nsq_tail/0.3.8 go-nsq/1.0.6","tls":false,
+        "tls_cipher_suite":"","tls_version":"","tls_negotiated_protocol":"",
+        "tls_negotiated_protocol_is_mutual":false}],"paused":false,
+        "e2e_processing_latency":{"count":0,"percentiles":null}}],"depth":0,"backend_depth":0,
+        "message_count":3,"paused":false,"e2e_processing_latency":{"count":0,"percentiles":null}}]}}"#;
+
+    /// `/stats?format=json` of nsqd 1.2.1 started with `--e2e-processing-latency-percentile=0.99`.
+    const V1_2: &str = r#"{"version":"1.2.1","health":"OK","start_time":1600000000,"topics":[{"topic_name":"test",
+        "channels":[{"channel_name":"ch","depth":0,"backend_depth":0,"in_flight_count":0,"deferred_count":0,
+        "message_count":2,"requeue_count":0,"timeout_count":0,"client_count":1,"clients":[{"client_id":"worker",
+        "hostname":"worker.local","version":"V2","remote_address":"127.0.0.1:52416","state":3,"ready_count":1,
+        "in_flight_count":0,"message_count":2,"finish_count":2,"requeue_count":0,"connect_ts":1600000010,
+        "sample_rate":0,"deflate":false,"snappy":true,"user_agent":"go-nsq/1.1.0","authed":false,"tls":false,
+        "tls_cipher_suite":"","tls_version":"","tls_negotiated_protocol":"",
+        "tls_negotiated_protocol_is_mutual":false}],"paused":false,"e2e_processing_latency":{"count":2,
+        "percentiles":[{"quantile":0.99,"value":1234567.5}]}}],"depth":0,"backend_depth":0,"message_count":2,
+        "message_bytes":10,"paused":false,"e2e_processing_latency":{"count":0,"percentiles":null}}],
+        "memory":{"heap_objects":7500,"heap_idle_bytes":1900544,"heap_in_use_bytes":3457024,
+        "heap_released_bytes":0,"gc_pause_usec_100":245,"gc_pause_usec_99":245,"gc_pause_usec_95":196,
+        "next_gc_bytes":4473924,"gc_total_runs":5},"producers":[{"client_id":"publisher",
+        "hostname":"publisher.local","version":"V2","remote_address":"127.0.0.1:52418","state":3,
+        "ready_count":0,"in_flight_count":0,"message_count":0,"finish_count":0,"requeue_count":0,
+        "connect_ts":1600000020,"sample_rate":0,"deflate":false,"snappy":false,"user_agent":"go-nsq/1.1.0",
+        "authed":false,"pub_counts":[{"topic":"test","count":2}],"tls":false,"tls_cipher_suite":"",
+        "tls_version":"","tls_negotiated_protocol":"","tls_negotiated_protocol_is_mutual":false}]}"#;
+
+    #[test]
+    fn nsqd_0_3() {
+        let stats: Stats = decode_json(V0_3.as_bytes()).unwrap();
+        assert_eq!(stats.version, "0.3.8");
+        assert_eq!(stats.memory, None);
+        assert!(stats.producers.is_empty());
+        let channel = &stats.topics[0].channels[0];
+        assert_eq!((channel.channel_name.as_str(), channel.depth, channel.in_flight_count), ("ch", 1, 1));
+        assert_eq!(channel.client_count, 0);
+        assert!(channel.e2e_processing_latency.percentiles.is_empty());
+        let client = &channel.clients[0];
+        assert_eq!(client.client_id, "worker");
+        assert_eq!(client.user_agent, "nsq_tail/0.3.8 go-nsq/1.0.6");
+        assert_eq!((client.finish_count, client.requeue_count), (1, 1));
+    }
+
+    #[test]
+    fn nsqd_1_2() {
+        let stats: Stats = decode_json(V1_2.as_bytes()).unwrap();
+        assert_eq!(stats.version, "1.2.1");
+        assert_eq!(stats.topics[0].message_bytes, 10);
+        let channel = &stats.topics[0].channels[0];
+        assert_eq!(channel.client_count, 1);
+        assert!(channel.clients[0].snappy);
+        assert_eq!(channel.e2e_processing_latency.count, 2);
+        assert_eq!(
+            channel.e2e_processing_latency.percentiles,
+            vec![Percentile { quantile: 0.99, value: 1234567.5 }]
+        );
+        assert_eq!(stats.memory.unwrap().gc_total_runs, 5);
+        assert_eq!(stats.producers[0].pub_counts, vec![PubCount { topic: "test".to_owned(), count: 2 }]);
+    }
+
+    #[test]
+    fn mistyped_fields_are_errors() {
+        let res = decode_json::<Stats>(br#"{"version":"1.2.1","topics":[{"topic_name":"test","depth":"many"}]}"#);
+        assert!(matches!(res, Err(NsqError::Json(_))), "{:?}", res);
+        let res = decode_json::<Stats>(br#"{"status_code":200,"status_txt":"OK","data":{"start_time":"now"}}"#);
+        assert!(matches!(res, Err(NsqError::Json(_))), "{:?}", res);
+    }
+}
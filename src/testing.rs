@@ -0,0 +1,874 @@
+// MIT License
+//
+// Copyright (c) 2019-2021 Alessandro Cresto Miseroglio <alex179ohm@gmail.com>
+// Copyright (c) 2019-2021 Tangram Technologies S.R.L. <https://tngrm.io>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! In-process fake nsqd for tests without network services (`testing` feature).
+//!
+//! [MockNsqd](struct.MockNsqd.html) speaks the V2 TCP protocol on a random local port:
+//! MAGIC, IDENTIFY (with configurable negotiation), AUTH, PUB/MPUB/DPUB, SUB, RDY,
+//! FIN/REQ/TOUCH, NOP, CLS and heartbeats. Published messages are delivered to the
+//! subscribed channels (DPUB ones after their delay), every command received is recorded
+//! and errors can be injected.
+//! Sampling is deterministic: a connection with `sample_rate` N receives N messages
+//! out of every 100.
+//!
+//! TLS, snappy and deflate are not supported, don't negotiate them.
+//!
+//! [MockHttp](struct.MockHttp.html) is an HTTP/1.1 endpoint recording the requests it
+//! receives, to test the tools forwarding messages over HTTP.
+//!
+//! # Examples
+//!```no-run
+//! use nsq_rust::testing::MockNsqd;
+//! use nsq_rust::{Client, Config, Pub, Topic};
+//!
+//! let nsqd = MockNsqd::start().await?;
+//! let client = Client::new(nsqd.addr(), Config::new(), None, None);
+//! client.publish(async { Pub::new(Topic::new("test").unwrap(), b"hello".to_vec()) }).await?;
+//! assert_eq!(nsqd.published("test"), vec![b"hello".to_vec()]);
+//! assert_eq!(nsqd.commands().last().unwrap().name, "PUB");
+//!```
+
+use crate::rt::{self, TcpListener, TcpStream};
+use byteorder::{BigEndian, ByteOrder};
+use futures::channel::mpsc::{unbounded, UnboundedSender};
+use futures::channel::oneshot;
+use futures::future::{self, AbortHandle, Abortable, Either};
+use futures::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use futures::StreamExt;
+use log::debug;
+use serde_json::{json, Map, Value};
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const MAX_BODY_SIZE: usize = 5 * 1024 * 1024;
+
+/// Command received by the [MockNsqd](struct.MockNsqd.html).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Command {
+    /// Command name, e.g. `PUB`.
+    pub name: String,
+    /// Parameters following the name on the command line.
+    pub params: Vec<String>,
+    /// Body of IDENTIFY, AUTH, PUB, MPUB and DPUB.
+    pub body: Option<Vec<u8>>,
+}
+
+/// Configure a [MockNsqd](struct.MockNsqd.html) before starting it.
+pub struct MockNsqdBuilder {
+    identify: Map<String, Value>,
+    auth: Value,
+    heartbeat_interval: Option<Duration>,
+}
+
+impl Default for MockNsqdBuilder {
+    fn default() -> Self {
+        MockNsqdBuilder {
+            identify: Map::new(),
+            auth: json!({"identity": "mock", "identity_url": "", "permission_count": 1}),
+            heartbeat_interval: None,
+        }
+    }
+}
+
+impl MockNsqdBuilder {
+    /// Override a field of the IDENTIFY response.
+    ///
+    /// By default the response echoes `sample_rate`, `msg_timeout` and the output buffer
+    /// settings requested by the client, overridden fields are returned as set.
+    pub fn negotiate<V: Into<Value>>(mut self, field: &str, value: V) -> Self {
+        self.identify.insert(field.to_owned(), value.into());
+        self
+    }
+
+    /// Require AUTH, answered with `identity`, before publishing or subscribing.
+    pub fn auth_required(mut self, identity: &str) -> Self {
+        self.identify.insert("auth_required".to_owned(), Value::Bool(true));
+        self.auth = json!({"identity": identity, "identity_url": "", "permission_count": 1});
+        self
+    }
+
+    /// Send heartbeats at `interval` instead of the interval requested in IDENTIFY.
+    pub fn heartbeat_interval(mut self, interval: Duration) -> Self {
+        self.heartbeat_interval = Some(interval);
+        self
+    }
+
+    /// Listen on a random port of 127.0.0.1.
+    pub async fn start(self) -> io::Result<MockNsqd> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let shared = Arc::new(Mutex::new(State::new(self)));
+        let (abort, registration) = AbortHandle::new_pair();
+        let state = shared.clone();
+        rt::spawn(async move {
+            let _ = Abortable::new(accept(listener, state), registration).await;
+        });
+        debug!("mock nsqd listening on {}", addr);
+        Ok(MockNsqd { addr, shared, abort })
+    }
+}
+
+/// Fake nsqd running in the current runtime, stopped when dropped.
+pub struct MockNsqd {
+    addr: SocketAddr,
+    shared: Arc<Mutex<State>>,
+    abort: AbortHandle,
+}
+
+impl MockNsqd {
+    pub fn builder() -> MockNsqdBuilder {
+        MockNsqdBuilder::default()
+    }
+
+    /// Start with the default configuration.
+    pub async fn start() -> io::Result<MockNsqd> {
+        Self::builder().start().await
+    }
+
+    /// TCP address (`127.0.0.1:port`) to connect to.
+    pub fn addr(&self) -> String {
+        self.addr.to_string()
+    }
+
+    /// Commands received so far, from every connection.
+    pub fn commands(&self) -> Vec<Command> {
+        self.state().commands.clone()
+    }
+
+    /// Message bodies published to `topic` by clients (PUB, MPUB and DPUB).
+    pub fn published(&self, topic: &str) -> Vec<Vec<u8>> {
+        self.state().published.get(topic).cloned().unwrap_or_default()
+    }
+
+    /// Queue a message on `topic`, delivered to its channels as if a client published it.
+    pub fn put(&self, topic: &str, body: Vec<u8>) {
+        let mut state = self.state();
+        state.enqueue(topic, body);
+        state.pump();
+    }
+
+    /// Answer the next `command` (e.g. `PUB`) with the error frame `error` (e.g. `E_PUB_FAILED`).
+    ///
+    /// As nsqd, the connection is closed after the error unless it is one of
+    /// `E_FIN_FAILED`, `E_REQ_FAILED` or `E_TOUCH_FAILED`.
+    pub fn inject_error(&self, command: &str, error: &str) {
+        self.state().errors.push_back((command.to_owned(), error.to_owned()));
+    }
+
+    /// Close every client connection.
+    pub fn disconnect_all(&self) {
+        for conn in self.state().conns.values() {
+            let _ = conn.tx.unbounded_send(Outgoing::Close);
+        }
+    }
+
+    /// Number of clients connected.
+    pub fn connections(&self) -> usize {
+        self.state().conns.len()
+    }
+
+    /// Messages delivered and not yet finished or requeued.
+    pub fn in_flight(&self) -> usize {
+        self.state().conns.values().map(|c| c.in_flight.len()).sum()
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.shared.lock().expect("mock nsqd state poisoned")
+    }
+}
+
+impl Drop for MockNsqd {
+    fn drop(&mut self) {
+        self.abort.abort();
+        self.disconnect_all();
+    }
+}
+
+enum Outgoing {
+    Frame(Vec<u8>),
+    Close,
+}
+
+#[derive(Clone)]
+struct Message {
+    id: String,
+    timestamp: i64,
+    attempts: u16,
+    body: Vec<u8>,
+}
+
+#[derive(Default)]
+struct TopicState {
+    /// Messages published before any channel existed.
+    backlog: VecDeque<Message>,
+    channels: HashMap<String, VecDeque<Message>>,
+}
+
+struct Conn {
+    tx: UnboundedSender<Outgoing>,
+    sub: Option<(String, String)>,
+    rdy: usize,
+    in_flight: HashMap<String, Message>,
+    /// Negotiated in IDENTIFY, 0 delivers every message.
+    sample_rate: u64,
+    /// Messages of the channel seen while sampling.
+    sampled: u64,
+}
+
+struct State {
+    config: MockNsqdBuilder,
+    commands: Vec<Command>,
+    published: HashMap<String, Vec<Vec<u8>>>,
+    topics: HashMap<String, TopicState>,
+    conns: HashMap<u64, Conn>,
+    errors: VecDeque<(String, String)>,
+    next_conn: u64,
+    next_msg: u64,
+}
+
+impl State {
+    fn new(config: MockNsqdBuilder) -> Self {
+        State {
+            config,
+            commands: Vec::new(),
+            published: HashMap::new(),
+            topics: HashMap::new(),
+            conns: HashMap::new(),
+            errors: VecDeque::new(),
+            next_conn: 0,
+            next_msg: 0,
+        }
+    }
+
+    fn enqueue(&mut self, topic: &str, body: Vec<u8>) {
+        self.next_msg += 1;
+        let msg = Message {
+            id: format!("{:016x}", self.next_msg),
+            timestamp: now_nanos(),
+            attempts: 0,
+            body,
+        };
+        let topic = self.topics.entry(topic.to_owned()).or_default();
+        if topic.channels.is_empty() {
+            topic.backlog.push_back(msg);
+        } else {
+            for queue in topic.channels.values_mut() {
+                queue.push_back(msg.clone());
+            }
+        }
+    }
+
+    fn subscribe(&mut self, conn: u64, topic: &str, channel: &str) {
+        let state = self.topics.entry(topic.to_owned()).or_default();
+        if !state.channels.contains_key(channel) {
+            let backlog = if state.channels.is_empty() {
+                state.backlog.drain(..).collect()
+            } else {
+                VecDeque::new()
+            };
+            state.channels.insert(channel.to_owned(), backlog);
+        }
+        if let Some(conn) = self.conns.get_mut(&conn) {
+            conn.sub = Some((topic.to_owned(), channel.to_owned()));
+        }
+    }
+
+    fn requeue(&mut self, topic: &str, channel: &str, msg: Message) {
+        if let Some(queue) = self.topics.get_mut(topic).and_then(|t| t.channels.get_mut(channel)) {
+            queue.push_front(msg);
+        }
+    }
+
+    /// Deliver queued messages to the subscribers ready to receive them.
+    fn pump(&mut self) {
+        let topics = &mut self.topics;
+        for conn in self.conns.values_mut() {
+            let (topic, channel) = match &conn.sub {
+                Some(sub) => sub,
+                None => continue,
+            };
+            let queue = match topics.get_mut(topic).and_then(|t| t.channels.get_mut(channel)) {
+                Some(queue) => queue,
+                None => continue,
+            };
+            while conn.in_flight.len() < conn.rdy {
+                let mut msg = match queue.pop_front() {
+                    Some(msg) => msg,
+                    None => break,
+                };
+                if conn.sample_rate > 0 {
+                    // like nsqd, messages sampled out are dropped for this connection
+                    conn.sampled += 1;
+                    if conn.sampled % 100 >= conn.sample_rate {
+                        continue;
+                    }
+                }
+                msg.attempts += 1;
+                let _ = conn.tx.unbounded_send(Outgoing::Frame(message_frame(&msg)));
+                conn.in_flight.insert(msg.id.clone(), msg);
+            }
+        }
+    }
+
+    fn take_error(&mut self, command: &str) -> Option<String> {
+        let pos = self.errors.iter().position(|(c, _)| c == command)?;
+        self.errors.remove(pos).map(|(_, e)| e)
+    }
+}
+
+async fn accept(listener: TcpListener, shared: Arc<Mutex<State>>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                debug!("mock nsqd: client connected from {}", addr);
+                rt::spawn(serve(stream, shared.clone()));
+            }
+            Err(e) => debug!("mock nsqd: accept failed: {}", e),
+        }
+    }
+}
+
+async fn serve(stream: TcpStream, shared: Arc<Mutex<State>>) {
+    let (reader, mut writer) = stream.split();
+    let (tx, mut rx) = unbounded();
+    let id = {
+        let mut state = shared.lock().expect("mock nsqd state poisoned");
+        state.next_conn += 1;
+        let id = state.next_conn;
+        state.conns.insert(
+            id,
+            Conn {
+                tx: tx.clone(),
+                sub: None,
+                rdy: 0,
+                in_flight: HashMap::new(),
+                sample_rate: 0,
+                sampled: 0,
+            },
+        );
+        id
+    };
+    // dropped once the writer is done, ending the session: the socket is closed
+    // when both halves are dropped
+    let (closed_tx, closed) = oneshot::channel::<()>();
+    rt::spawn(async move {
+        let _closed_tx = closed_tx;
+        while let Some(out) = rx.next().await {
+            match out {
+                Outgoing::Frame(frame) => {
+                    if writer.write_all(&frame).await.is_err() {
+                        break;
+                    }
+                }
+                Outgoing::Close => break,
+            }
+        }
+        let _ = writer.close().await;
+    });
+    let mut session = Session {
+        id,
+        shared: shared.clone(),
+        tx: tx.clone(),
+        authed: false,
+        heartbeat: false,
+    };
+    let run = Box::pin(session.run(BufReader::new(reader)));
+    match future::select(run, closed).await {
+        Either::Left((Err(e), _)) => debug!("mock nsqd: connection {} closed: {}", id, e),
+        Either::Left((Ok(()), _)) => {}
+        Either::Right(_) => debug!("mock nsqd: connection {} closed by the server", id),
+    }
+    let _ = tx.unbounded_send(Outgoing::Close);
+    let mut state = shared.lock().expect("mock nsqd state poisoned");
+    if let Some(conn) = state.conns.remove(&id) {
+        if let Some((topic, channel)) = conn.sub {
+            for (_, msg) in conn.in_flight {
+                state.requeue(&topic, &channel, msg);
+            }
+        }
+    }
+    state.pump();
+}
+
+struct Session {
+    id: u64,
+    shared: Arc<Mutex<State>>,
+    tx: UnboundedSender<Outgoing>,
+    authed: bool,
+    heartbeat: bool,
+}
+
+impl Session {
+    async fn run<R: AsyncBufReadExt + Unpin>(&mut self, mut reader: R) -> io::Result<()> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic).await?;
+        if &magic != b"  V2" {
+            self.error("E_BAD_PROTOCOL unsupported protocol version");
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "bad magic"));
+        }
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if reader.read_until(b'\n', &mut line).await? == 0 {
+                return Ok(());
+            }
+            let text = String::from_utf8_lossy(&line).trim_end().to_owned();
+            let mut parts = text.split(' ').map(str::to_owned);
+            let name = parts.next().unwrap_or_default();
+            let params: Vec<String> = parts.collect();
+            let body = match name.as_str() {
+                "IDENTIFY" | "AUTH" | "PUB" | "MPUB" | "DPUB" => {
+                    let mut size = [0u8; 4];
+                    reader.read_exact(&mut size).await?;
+                    let size = BigEndian::read_u32(&size) as usize;
+                    if size > MAX_BODY_SIZE {
+                        self.error("E_BAD_BODY body too big");
+                        return Ok(());
+                    }
+                    let mut body = vec![0u8; size];
+                    reader.read_exact(&mut body).await?;
+                    Some(body)
+                }
+                _ => None,
+            };
+            let command = Command { name, params, body };
+            debug!("mock nsqd: connection {} sent {:?}", self.id, command.name);
+            if !self.handle(command) {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Handle a command, false if the connection must be closed.
+    fn handle(&mut self, command: Command) -> bool {
+        let mut state = self.shared.lock().expect("mock nsqd state poisoned");
+        state.commands.push(command.clone());
+        if let Some(error) = state.take_error(&command.name) {
+            drop(state);
+            return self.error(&error);
+        }
+        let auth_required = state.config.identify.get("auth_required") == Some(&Value::Bool(true));
+        let name = command.name.as_str();
+        let needs_auth = matches!(name, "PUB" | "MPUB" | "DPUB" | "SUB");
+        if needs_auth && auth_required && !self.authed {
+            drop(state);
+            return self.error(&format!("E_AUTH_FIRST AUTH required before {}", name));
+        }
+        let body = command.body.unwrap_or_default();
+        let params = command.params;
+        match name {
+            "IDENTIFY" => {
+                let request: Map<String, Value> = match serde_json::from_slice(&body) {
+                    Ok(request) => request,
+                    Err(_) => {
+                        drop(state);
+                        return self.error("E_BAD_BODY IDENTIFY failed to decode JSON body");
+                    }
+                };
+                let heartbeat = state.config.heartbeat_interval.or_else(|| {
+                    match request.get("heartbeat_interval").and_then(Value::as_i64) {
+                        Some(ms) if ms > 0 => Some(Duration::from_millis(ms as u64)),
+                        Some(_) => None,
+                        None => Some(Duration::from_secs(30)),
+                    }
+                });
+                let negotiation = request.get("feature_negotiation") == Some(&Value::Bool(true));
+                let response = if negotiation {
+                    identify_response(&state.config.identify, &request)
+                } else {
+                    "OK".to_owned()
+                };
+                let sample_rate = state.config.identify.get("sample_rate").or_else(|| request.get("sample_rate"));
+                let sample_rate = sample_rate.and_then(Value::as_u64).unwrap_or(0);
+                if let Some(conn) = state.conns.get_mut(&self.id) {
+                    conn.sample_rate = sample_rate;
+                }
+                drop(state);
+                self.respond(response.as_bytes());
+                if let (Some(interval), false) = (heartbeat, self.heartbeat) {
+                    self.heartbeat = true;
+                    rt::spawn(heartbeats(self.tx.clone(), interval));
+                }
+                true
+            }
+            "AUTH" => {
+                if !auth_required {
+                    drop(state);
+                    return self.error("E_INVALID AUTH not enabled");
+                }
+                let response = state.config.auth.to_string();
+                drop(state);
+                self.authed = true;
+                self.respond(response.as_bytes());
+                true
+            }
+            "PUB" => {
+                let topic = params.first().cloned().unwrap_or_default();
+                state.published.entry(topic.clone()).or_default().push(body.clone());
+                state.enqueue(&topic, body);
+                state.pump();
+                drop(state);
+                self.respond(b"OK");
+                true
+            }
+            "DPUB" => {
+                let delay = match params.get(1).and_then(|d| d.parse::<u64>().ok()) {
+                    Some(delay) => delay,
+                    None => {
+                        drop(state);
+                        return self.error("E_INVALID DPUB could not parse timeout");
+                    }
+                };
+                let topic = params.first().cloned().unwrap_or_default();
+                state.published.entry(topic.clone()).or_default().push(body.clone());
+                drop(state);
+                let shared = self.shared.clone();
+                rt::spawn(async move {
+                    rt::sleep(Duration::from_millis(delay)).await;
+                    let mut state = shared.lock().expect("mock nsqd state poisoned");
+                    state.enqueue(&topic, body);
+                    state.pump();
+                });
+                self.respond(b"OK");
+                true
+            }
+            "MPUB" => {
+                let msgs = match split_mpub(&body) {
+                    Some(msgs) => msgs,
+                    None => {
+                        drop(state);
+                        return self.error("E_BAD_BODY MPUB invalid body");
+                    }
+                };
+                if msgs.is_empty() {
+                    drop(state);
+                    return self.error("E_BAD_BODY MPUB invalid message count 0");
+                }
+                let topic = params.first().cloned().unwrap_or_default();
+                for msg in msgs {
+                    state.published.entry(topic.clone()).or_default().push(msg.clone());
+                    state.enqueue(&topic, msg);
+                }
+                state.pump();
+                drop(state);
+                self.respond(b"OK");
+                true
+            }
+            "SUB" => {
+                if params.len() != 2 {
+                    drop(state);
+                    return self.error("E_INVALID SUB insufficient number of parameters");
+                }
+                state.subscribe(self.id, &params[0], &params[1]);
+                drop(state);
+                self.respond(b"OK");
+                true
+            }
+            "RDY" => {
+                let count = params.first().and_then(|c| c.parse::<usize>().ok());
+                match (count, state.conns.get_mut(&self.id)) {
+                    (Some(count), Some(conn)) => conn.rdy = count,
+                    _ => {
+                        drop(state);
+                        return self.error("E_INVALID RDY invalid count");
+                    }
+                }
+                state.pump();
+                true
+            }
+            "FIN" | "REQ" | "TOUCH" => {
+                let id = params.first().cloned().unwrap_or_default();
+                let conn = match state.conns.get_mut(&self.id) {
+                    Some(conn) => conn,
+                    None => return false,
+                };
+                let msg = match name {
+                    "TOUCH" => conn.in_flight.get(&id).cloned(),
+                    _ => conn.in_flight.remove(&id),
+                };
+                let (msg, sub) = match (msg, conn.sub.clone()) {
+                    (Some(msg), Some(sub)) => (msg, sub),
+                    _ => {
+                        drop(state);
+                        return self.error(&format!("E_{}_FAILED {} {} failed", name, name, id));
+                    }
+                };
+                if name == "REQ" {
+                    let delay = params.get(1).and_then(|d| d.parse::<u64>().ok()).unwrap_or(0);
+                    if delay == 0 {
+                        state.requeue(&sub.0, &sub.1, msg);
+                    } else {
+                        let shared = self.shared.clone();
+                        rt::spawn(async move {
+                            rt::sleep(Duration::from_millis(delay)).await;
+                            let mut state = shared.lock().expect("mock nsqd state poisoned");
+                            state.requeue(&sub.0, &sub.1, msg);
+                            state.pump();
+                        });
+                    }
+                }
+                state.pump();
+                true
+            }
+            "NOP" => true,
+            "CLS" => {
+                drop(state);
+                self.respond(b"CLOSE_WAIT");
+                true
+            }
+            _ => {
+                drop(state);
+                self.error(&format!("E_INVALID invalid command {}", name))
+            }
+        }
+    }
+
+    fn respond(&self, data: &[u8]) {
+        let _ = self.tx.unbounded_send(Outgoing::Frame(frame(0, data)));
+    }
+
+    /// Send an error frame, false if the error is fatal and the connection must be closed.
+    fn error(&self, error: &str) -> bool {
+        let _ = self.tx.unbounded_send(Outgoing::Frame(frame(1, error.as_bytes())));
+        let code = error.split(' ').next().unwrap_or(error);
+        matches!(code, "E_FIN_FAILED" | "E_REQ_FAILED" | "E_TOUCH_FAILED")
+    }
+}
+
+async fn heartbeats(tx: UnboundedSender<Outgoing>, interval: Duration) {
+    loop {
+        rt::sleep(interval).await;
+        if tx.unbounded_send(Outgoing::Frame(frame(0, b"_heartbeat_"))).is_err() {
+            return;
+        }
+    }
+}
+
+fn identify_response(overrides: &Map<String, Value>, request: &Map<String, Value>) -> String {
+    let mut response = json!({
+        "max_rdy_count": 2500,
+        "version": "1.2.1",
+        "max_msg_timeout": 900000,
+        "msg_timeout": 60000,
+        "tls_v1": false,
+        "deflate": false,
+        "deflate_level": 6,
+        "max_deflate_level": 6,
+        "snappy": false,
+        "sample_rate": 0,
+        "auth_required": false,
+        "output_buffer_size": 16384,
+        "output_buffer_timeout": 250,
+    });
+    let fields = response.as_object_mut().expect("IDENTIFY response is an object");
+    for field in &["sample_rate", "output_buffer_size", "output_buffer_timeout", "msg_timeout"] {
+        match request.get(*field) {
+            Some(value) if value.as_i64().unwrap_or(0) > 0 => {
+                fields.insert((*field).to_owned(), value.clone());
+            }
+            _ => {}
+        }
+    }
+    fields.extend(overrides.clone());
+    response.to_string()
+}
+
+fn split_mpub(body: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let num = BigEndian::read_u32(body.get(..4)?) as usize;
+    let mut msgs = Vec::with_capacity(num);
+    let mut rest = &body[4..];
+    for _ in 0..num {
+        let size = BigEndian::read_u32(rest.get(..4)?) as usize;
+        msgs.push(rest.get(4..4 + size)?.to_vec());
+        rest = &rest[4 + size..];
+    }
+    Some(msgs)
+}
+
+fn frame(frame_type: u32, data: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0u8; 8];
+    BigEndian::write_u32(&mut frame[..4], data.len() as u32 + 4);
+    BigEndian::write_u32(&mut frame[4..], frame_type);
+    frame.extend_from_slice(data);
+    frame
+}
+
+fn message_frame(msg: &Message) -> Vec<u8> {
+    let mut data = vec![0u8; 10];
+    BigEndian::write_i64(&mut data[..8], msg.timestamp);
+    BigEndian::write_u16(&mut data[8..], msg.attempts);
+    data.extend_from_slice(msg.id.as_bytes());
+    data.extend_from_slice(&msg.body);
+    frame(2, &data)
+}
+
+fn now_nanos() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as i64)
+        .unwrap_or(0)
+}
+
+/// Request received by the [MockHttp](struct.MockHttp.html).
+#[derive(Clone, Debug, PartialEq)]
+pub struct HttpRequest {
+    pub method: String,
+    /// Path with the query string.
+    pub path: String,
+    /// Header names are lowercase.
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Default)]
+struct HttpState {
+    requests: Vec<HttpRequest>,
+    statuses: VecDeque<u16>,
+    delay: Option<Duration>,
+}
+
+/// Fake HTTP endpoint running in the current runtime, stopped when dropped.
+///
+/// Every request is answered `200 OK` unless other statuses are queued with
+/// [respond](#method.respond), one connection per request.
+pub struct MockHttp {
+    addr: SocketAddr,
+    shared: Arc<Mutex<HttpState>>,
+    abort: AbortHandle,
+}
+
+impl MockHttp {
+    /// Listen on a random port of 127.0.0.1.
+    pub async fn start() -> io::Result<MockHttp> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let shared = Arc::new(Mutex::new(HttpState::default()));
+        let (abort, registration) = AbortHandle::new_pair();
+        let state = shared.clone();
+        rt::spawn(async move {
+            let _ = Abortable::new(accept_http(listener, state), registration).await;
+        });
+        debug!("mock http listening on {}", addr);
+        Ok(MockHttp { addr, shared, abort })
+    }
+
+    /// Address (`127.0.0.1:port`) to send requests to.
+    pub fn addr(&self) -> String {
+        self.addr.to_string()
+    }
+
+    /// Requests received so far.
+    pub fn requests(&self) -> Vec<HttpRequest> {
+        self.state().requests.clone()
+    }
+
+    /// Answer the next request with `status` instead of 200, statuses queue up.
+    pub fn respond(&self, status: u16) {
+        self.state().statuses.push_back(status);
+    }
+
+    /// Wait `delay` before answering each request, to test timeouts.
+    pub fn delay(&self, delay: Duration) {
+        self.state().delay = Some(delay);
+    }
+
+    fn state(&self) -> MutexGuard<'_, HttpState> {
+        self.shared.lock().expect("mock http state poisoned")
+    }
+}
+
+impl Drop for MockHttp {
+    fn drop(&mut self) {
+        self.abort.abort();
+    }
+}
+
+async fn accept_http(listener: TcpListener, shared: Arc<Mutex<HttpState>>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let shared = shared.clone();
+                rt::spawn(async move {
+                    if let Err(e) = serve_http(stream, shared).await {
+                        debug!("mock http: {}", e);
+                    }
+                });
+            }
+            Err(e) => {
+                debug!("mock http: accept failed: {}", e);
+                return;
+            }
+        }
+    }
+}
+
+async fn serve_http(stream: TcpStream, shared: Arc<Mutex<HttpState>>) -> io::Result<()> {
+    let (reader, mut writer) = stream.split();
+    let mut reader = BufReader::new(reader);
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_owned();
+    let path = parts.next().unwrap_or_default().to_owned();
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_ascii_lowercase(), value.trim().to_owned()));
+        }
+    }
+    let length = headers
+        .iter()
+        .find(|(name, _)| name == "content-length")
+        .and_then(|(_, value)| value.parse::<usize>().ok())
+        .unwrap_or(0)
+        .min(MAX_BODY_SIZE);
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).await?;
+    let (status, delay) = {
+        let mut state = shared.lock().expect("mock http state poisoned");
+        state.requests.push(HttpRequest {
+            method,
+            path,
+            headers,
+            body,
+        });
+        (state.statuses.pop_front().unwrap_or(200), state.delay)
+    };
+    if let Some(delay) = delay {
+        rt::sleep(delay).await;
+    }
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        status,
+        if (200..300).contains(&status) { "OK" } else { "Error" }
+    );
+    writer.write_all(response.as_bytes()).await?;
+    writer.flush().await
+}
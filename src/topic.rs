@@ -0,0 +1,182 @@
+// MIT License
+//
+// Copyright (c) 2019-2021 Alessandro Cresto Miseroglio <alex179ohm@gmail.com>
+// Copyright (c) 2019-2021 Tangram Technologies S.R.L. <https://tngrm.io>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::error::NsqError;
+use crate::result::NsqResult;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+const MAX_NAME_LEN: usize = 64;
+const EPHEMERAL_SUFFIX: &str = "#ephemeral";
+
+/// Validated nsqd topic name.
+///
+/// Valid names are 1-64 characters of `[.a-zA-Z0-9_-]`, optionally followed by
+/// the `#ephemeral` suffix (the suffix counts toward the 64 characters).
+///
+/// # Examples
+///```no-run
+/// use nsq_rust::Topic;
+///
+/// let topic = Topic::new("events").unwrap();
+/// assert!(!topic.is_ephemeral());
+///
+/// let topic = Topic::ephemeral("events").unwrap();
+/// assert_eq!(topic.as_str(), "events#ephemeral");
+///
+/// assert!(Topic::new("bad topic").is_err());
+///```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Topic(String);
+
+/// Validated nsqd channel name, same rules as [Topic](struct.Topic.html).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Channel(String);
+
+macro_rules! impl_name {
+    ($name:ident, $err:ident) => {
+        impl $name {
+            /// Validate `name`, returning an error if nsqd would reject it.
+            pub fn new<N: Into<String>>(name: N) -> NsqResult<Self> {
+                let name = name.into();
+                if is_valid_name(&name) {
+                    Ok($name(name))
+                } else {
+                    Err(NsqError::$err(name))
+                }
+            }
+
+            /// Validate `name` and append the `#ephemeral` suffix.
+            ///
+            /// An ephemeral name is discarded by nsqd once its last client disconnects.
+            pub fn ephemeral<N: Into<String>>(name: N) -> NsqResult<Self> {
+                let mut name = name.into();
+                if !name.ends_with(EPHEMERAL_SUFFIX) {
+                    name.push_str(EPHEMERAL_SUFFIX);
+                }
+                Self::new(name)
+            }
+
+            /// True if the name carries the `#ephemeral` suffix.
+            pub fn is_ephemeral(&self) -> bool {
+                self.0.ends_with(EPHEMERAL_SUFFIX)
+            }
+
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+
+        impl AsRef<str> for $name {
+            fn as_ref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = NsqError;
+
+            fn from_str(s: &str) -> NsqResult<Self> {
+                Self::new(s)
+            }
+        }
+
+        impl TryFrom<&'_ str> for $name {
+            type Error = NsqError;
+
+            fn try_from(s: &str) -> NsqResult<Self> {
+                Self::new(s)
+            }
+        }
+
+        impl TryFrom<String> for $name {
+            type Error = NsqError;
+
+            fn try_from(s: String) -> NsqResult<Self> {
+                Self::new(s)
+            }
+        }
+    };
+}
+
+impl_name!(Topic, InvalidTopic);
+impl_name!(Channel, InvalidChannel);
+
+fn is_valid_name(name: &str) -> bool {
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        return false;
+    }
+    let base = name.strip_suffix(EPHEMERAL_SUFFIX).unwrap_or(name);
+    !base.is_empty()
+        && base
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'.' || b == b'_' || b == b'-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn length_includes_the_ephemeral_suffix() {
+        let base = "a".repeat(MAX_NAME_LEN - EPHEMERAL_SUFFIX.len());
+        assert!(Topic::new("a".repeat(MAX_NAME_LEN)).is_ok());
+        assert!(Topic::new("a".repeat(MAX_NAME_LEN + 1)).is_err());
+        assert_eq!(Topic::ephemeral(base.as_str()).unwrap().as_str().len(), MAX_NAME_LEN);
+        assert!(Topic::ephemeral(format!("{}a", base)).is_err());
+        assert!(Channel::ephemeral(format!("{}a", base)).is_err());
+    }
+
+    #[test]
+    fn invalid_characters() {
+        for name in &["bad topic", "topic!", "tópico", "a#b", "#ephemeral#ephemeral", "a/b"] {
+            assert!(matches!(Topic::new(*name), Err(NsqError::InvalidTopic(n)) if n == *name), "{}", name);
+            assert!(matches!(Channel::new(*name), Err(NsqError::InvalidChannel(n)) if n == *name), "{}", name);
+        }
+        assert!(Topic::new("valid.name_with-0").is_ok());
+    }
+
+    #[test]
+    fn empty_base_name() {
+        assert!(Topic::new("").is_err());
+        assert!(Topic::new(EPHEMERAL_SUFFIX).is_err());
+        assert!(Topic::ephemeral("").is_err());
+        assert!(Channel::ephemeral("").is_err());
+    }
+
+    #[test]
+    fn ephemeral_twice() {
+        let channel = Channel::ephemeral("ch").unwrap();
+        let again = Channel::ephemeral(channel.as_str()).unwrap();
+        assert_eq!(again.as_str(), "ch#ephemeral");
+        assert!(again.is_ephemeral());
+        assert!(!Channel::new("ch").unwrap().is_ephemeral());
+    }
+}
@@ -0,0 +1,69 @@
+// MIT License
+//
+// Copyright (c) 2019-2021 Alessandro Cresto Miseroglio <alex179ohm@gmail.com>
+// Copyright (c) 2019-2021 Tangram Technologies S.R.L. <https://tngrm.io>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Spans emitted with the `tracing` feature, no-ops without it.
+
+use std::future::Future;
+
+#[cfg(feature = "tracing")]
+pub(crate) use tracing::Span;
+
+/// Stand-in for `tracing::Span` when the feature is disabled.
+#[cfg(not(feature = "tracing"))]
+#[derive(Clone, Debug)]
+pub(crate) struct Span;
+
+/// Create an info span, arguments use the `tracing::info_span!` syntax
+/// (`parent: &span, "name", addr = %addr`).
+macro_rules! span {
+    (parent: $parent:expr, $($args:tt)*) => {{
+        #[cfg(feature = "tracing")]
+        let span = tracing::info_span!(parent: $parent, $($args)*);
+        #[cfg(not(feature = "tracing"))]
+        let span = {
+            let _ = $parent;
+            $crate::trace::Span
+        };
+        span
+    }};
+    ($($args:tt)*) => {{
+        #[cfg(feature = "tracing")]
+        let span = tracing::info_span!($($args)*);
+        #[cfg(not(feature = "tracing"))]
+        let span = $crate::trace::Span;
+        span
+    }};
+}
+
+pub(crate) use span;
+
+/// Run `fut` inside `span`.
+#[cfg(feature = "tracing")]
+pub(crate) fn instrument<F: Future>(fut: F, span: Span) -> tracing::instrument::Instrumented<F> {
+    tracing::Instrument::instrument(fut, span)
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn instrument<F: Future>(fut: F, _span: Span) -> F {
+    fut
+}
@@ -0,0 +1,259 @@
+// MIT License
+//
+// Copyright (c) 2019-2021 Alessandro Cresto Miseroglio <alex179ohm@gmail.com>
+// Copyright (c) 2019-2021 Tangram Technologies S.R.L. <https://tngrm.io>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Serde typed publishing and consuming.
+//!
+//! The serialization format is selected by a [Codec](trait.Codec.html): JSON is always
+//! available, MessagePack and bincode are enabled by the `msgpack` and `bincode` features.
+
+use crate::client::Client;
+use crate::consumer::{Action, Handler};
+#[cfg(any(feature = "msgpack", feature = "bincode"))]
+use crate::error::NsqError;
+use crate::msg::Msg;
+use crate::producer::Producer;
+use crate::response::Response;
+use crate::result::NsqResult;
+use crate::topic::Topic;
+use log::{error, warn};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::time::Duration;
+
+/// Serialization format of message bodies.
+pub trait Codec {
+    fn encode<T: Serialize>(value: &T) -> NsqResult<Vec<u8>>;
+    fn decode<T: DeserializeOwned>(body: &[u8]) -> NsqResult<T>;
+}
+
+/// JSON bodies through serde_json.
+pub struct Json;
+
+impl Codec for Json {
+    fn encode<T: Serialize>(value: &T) -> NsqResult<Vec<u8>> {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    fn decode<T: DeserializeOwned>(body: &[u8]) -> NsqResult<T> {
+        Ok(serde_json::from_slice(body)?)
+    }
+}
+
+/// MessagePack bodies through rmp-serde, structs are encoded as maps.
+#[cfg(feature = "msgpack")]
+pub struct MsgPack;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MsgPack {
+    fn encode<T: Serialize>(value: &T) -> NsqResult<Vec<u8>> {
+        rmp_serde::to_vec_named(value).map_err(|e| NsqError::Codec(Box::new(e)))
+    }
+
+    fn decode<T: DeserializeOwned>(body: &[u8]) -> NsqResult<T> {
+        rmp_serde::from_slice(body).map_err(|e| NsqError::Codec(Box::new(e)))
+    }
+}
+
+/// Bincode bodies.
+#[cfg(feature = "bincode")]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl Codec for Bincode {
+    fn encode<T: Serialize>(value: &T) -> NsqResult<Vec<u8>> {
+        bincode::serialize(value).map_err(|e| NsqError::Codec(e))
+    }
+
+    fn decode<T: DeserializeOwned>(body: &[u8]) -> NsqResult<T> {
+        bincode::deserialize(body).map_err(|e| NsqError::Codec(e))
+    }
+}
+
+/// Publish values of type `T` to a topic, serialized with `C`.
+///
+/// # Examples
+///```no-run
+/// use nsq_rust::{Client, Config, Topic, TypedProducer};
+///
+/// #[derive(Serialize)]
+/// struct Event { id: u64 }
+///
+/// let client = Client::new("localhost:4150", Config::new(), None, None);
+/// let mut producer: TypedProducer<Event> = TypedProducer::new(client, Topic::new("events")?);
+/// producer.publish(&Event { id: 1 }).await?;
+///```
+///
+/// The connection is opened by the first publish and kept for the next ones.
+pub struct TypedProducer<T, C = Json> {
+    producer: LazyProducer,
+    topic: Topic,
+    _marker: PhantomData<fn(&T, C)>,
+}
+
+impl<T: Serialize, C: Codec> TypedProducer<T, C> {
+    pub fn new(client: Client, topic: Topic) -> Self {
+        TypedProducer {
+            producer: LazyProducer::new(client),
+            topic,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn topic(&self) -> &Topic {
+        &self.topic
+    }
+
+    pub async fn publish(&mut self, value: &T) -> NsqResult<Response> {
+        let body = C::encode(value)?;
+        self.producer.get().await?.publish(&self.topic, body).await
+    }
+
+    pub async fn mpublish(&mut self, values: &[T]) -> NsqResult<Response> {
+        let bodies = values.iter().map(C::encode).collect::<NsqResult<Vec<_>>>()?;
+        self.producer.get().await?.mpublish(&self.topic, bodies).await
+    }
+
+    pub async fn dpublish(&mut self, value: &T, delay: Duration) -> NsqResult<Response> {
+        let body = C::encode(value)?;
+        self.producer.get().await?.dpublish(&self.topic, delay, body).await
+    }
+}
+
+/// Producer connected on first use, then reused.
+struct LazyProducer {
+    client: Client,
+    producer: Option<Producer>,
+}
+
+impl LazyProducer {
+    fn new(client: Client) -> Self {
+        LazyProducer { client, producer: None }
+    }
+
+    async fn get(&mut self) -> NsqResult<&mut Producer> {
+        if self.producer.is_none() {
+            self.producer = Some(self.client.clone().producer().await?);
+        }
+        Ok(self.producer.as_mut().expect("producer connected"))
+    }
+}
+
+/// Handler receiving deserialized message bodies, see [Typed](struct.Typed.html).
+pub trait TypedHandler<T>: Send {
+    fn handle(&mut self, value: T, msg: &Msg) -> impl Future<Output = Action> + Send;
+}
+
+/// What to do with a message whose body cannot be deserialized.
+#[derive(Clone)]
+pub enum DecodePolicy {
+    /// Log the error and FIN the message.
+    Finish,
+    /// REQ the message with the given delay.
+    Requeue(Duration),
+    /// Publish the raw body to another topic and FIN the message once nsqd acknowledged it.
+    ///
+    /// The connection to the dead letter nsqd is opened on the first failure and kept.
+    DeadLetter(Client, Topic),
+}
+
+/// [Handler](trait.Handler.html) deserializing message bodies with `C` before calling a
+/// [TypedHandler](trait.TypedHandler.html).
+///
+/// Bodies failing deserialization never reach the wrapped handler, they are handled
+/// following the configured [DecodePolicy](enum.DecodePolicy.html) (default: `Finish`).
+pub struct Typed<T, H, C = Json> {
+    handler: H,
+    policy: DecodePolicy,
+    /// Connection of the `DeadLetter` policy.
+    dead_letter: Option<LazyProducer>,
+    _marker: PhantomData<fn() -> (T, C)>,
+}
+
+impl<T, H, C> Typed<T, H, C>
+where
+    T: DeserializeOwned + Send,
+    H: TypedHandler<T>,
+    C: Codec,
+{
+    pub fn new(handler: H) -> Self {
+        Typed {
+            handler,
+            policy: DecodePolicy::Finish,
+            dead_letter: None,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn policy(mut self, policy: DecodePolicy) -> Self {
+        self.dead_letter = match &policy {
+            DecodePolicy::DeadLetter(client, _) => Some(LazyProducer::new(client.clone())),
+            _ => None,
+        };
+        self.policy = policy;
+        self
+    }
+}
+
+impl<T, H, C> Handler for Typed<T, H, C>
+where
+    T: DeserializeOwned + Send,
+    H: TypedHandler<T>,
+    C: Codec,
+{
+    fn handle(&mut self, msg: &Msg) -> impl Future<Output = Action> + Send {
+        let decoded = C::decode::<T>(msg.body());
+        async move {
+            let e = match decoded {
+                Ok(value) => return self.handler.handle(value, msg).await,
+                Err(e) => e,
+            };
+            match &self.policy {
+                DecodePolicy::Finish => {
+                    warn!("dropping message {}: {}", msg.id(), e);
+                    Action::Finish
+                }
+                DecodePolicy::Requeue(delay) => {
+                    warn!("requeueing message {}: {}", msg.id(), e);
+                    Action::Requeue(*delay)
+                }
+                DecodePolicy::DeadLetter(_, topic) => {
+                    warn!("dead lettering message {} to {}: {}", msg.id(), topic, e);
+                    let producer = self.dead_letter.as_mut().expect("dead letter producer");
+                    let res = match producer.get().await {
+                        Ok(producer) => producer.publish(topic, msg.body().to_vec()).await,
+                        Err(e) => Err(e),
+                    };
+                    match res {
+                        Ok(_) => Action::Finish,
+                        Err(e) => {
+                            error!("dead letter of message {} to {} failed: {}", msg.id(), topic, e);
+                            Action::Requeue(Duration::from_secs(0))
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,63 @@
+// MIT License
+//
+// Copyright (c) 2019-2021 Alessandro Cresto Miseroglio <alex179ohm@gmail.com>
+// Copyright (c) 2019-2021 Tangram Technologies S.R.L. <https://tngrm.io>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Blocking clients against MockNsqd.
+
+#[allow(dead_code)]
+mod common;
+
+use common::{channel, eventually, names, topic};
+use nsq_rust::testing::MockNsqd;
+use nsq_rust::{blocking, rt, Client, Config, NsqError};
+
+#[test]
+fn publish_and_consume() {
+    let nsqd = rt::block_on(MockNsqd::start()).unwrap();
+    let client = Client::new(nsqd.addr(), Config::new(), None, None);
+    let mut producer = blocking::Producer::connect(client.clone()).unwrap();
+    producer.publish(&topic(), b"hello".to_vec()).unwrap();
+
+    let mut consumer = blocking::Consumer::connect(client, topic(), channel()).unwrap();
+    let msg = consumer.next().unwrap().unwrap();
+    assert_eq!(msg.body(), b"hello");
+    msg.touch();
+    let id = msg.id().to_owned();
+    msg.finish();
+    rt::block_on(eventually(|| names(&nsqd).contains(&"FIN".to_owned())));
+
+    let commands = nsqd.commands();
+    let touch = commands.iter().find(|c| c.name == "TOUCH").unwrap();
+    assert_eq!(touch.params, vec![id]);
+    let replies = names(&nsqd).into_iter().filter(|name| name == "TOUCH" || name == "FIN").collect::<Vec<_>>();
+    assert_eq!(replies, vec!["TOUCH", "FIN"]);
+}
+
+#[test]
+fn rejected_subscription() {
+    let nsqd = rt::block_on(MockNsqd::start()).unwrap();
+    nsqd.inject_error("SUB", "E_BAD_TOPIC SUB topic name is not valid");
+    let client = Client::new(nsqd.addr(), Config::new(), None, None);
+
+    let res = blocking::Consumer::connect(client, topic(), channel());
+    assert!(matches!(res, Err(NsqError::Topic)), "{:?}", res.err());
+}
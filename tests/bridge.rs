@@ -0,0 +1,117 @@
+// MIT License
+//
+// Copyright (c) 2019-2021 Alessandro Cresto Miseroglio <alex179ohm@gmail.com>
+// Copyright (c) 2019-2021 Tangram Technologies S.R.L. <https://tngrm.io>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Bridge between two MockNsqd.
+
+#[allow(dead_code)]
+mod common;
+
+use common::{channel, count, eventually, topic};
+use futures::future::{self, Either};
+use futures::pin_mut;
+use nsq_rust::retry::ExponentialBackoff;
+use nsq_rust::testing::MockNsqd;
+use nsq_rust::{rt, Bridge, Client, Config, MessageStream};
+use std::future::Future;
+use std::time::Duration;
+
+fn policy() -> ExponentialBackoff {
+    ExponentialBackoff::new(Duration::from_millis(10), Duration::from_millis(50))
+}
+
+async fn setup() -> (MockNsqd, MockNsqd, MessageStream, Bridge) {
+    let source = MockNsqd::start().await.unwrap();
+    let destination = MockNsqd::start().await.unwrap();
+    let messages = Client::new(source.addr(), Config::new().max_in_flight(3), None, None)
+        .subscribe(topic(), channel())
+        .await
+        .unwrap();
+    let producer = Client::new(destination.addr(), Config::new(), None, None)
+        .retry_policy(policy())
+        .producer()
+        .await
+        .unwrap();
+    let bridge = Bridge::new(topic(), vec![producer]).requeue_policy(policy());
+    (source, destination, messages, bridge)
+}
+
+/// Run `bridge` until `done` completes.
+async fn run_until<F: Future>(mut bridge: Bridge, mut messages: MessageStream, done: F) {
+    let run = bridge.run(&mut messages);
+    pin_mut!(run, done);
+    if let Either::Left((res, _)) = future::select(run, done).await {
+        panic!("bridge stopped: {:?}", res);
+    }
+}
+
+#[test]
+fn finish_after_the_destination_acknowledged() {
+    rt::block_on(async {
+        let (source, destination, messages, bridge) = setup().await;
+        destination.inject_error("PUB", "E_PUB_FAILED PUB failed");
+        source.put("test", b"a".to_vec());
+
+        run_until(bridge, messages, eventually(|| count(&source, "FIN") == 1)).await;
+        // requeued after the failure, then delivered again
+        assert_eq!(count(&source, "REQ"), 1);
+        assert_eq!(destination.published("test"), vec![b"a".to_vec()]);
+    });
+}
+
+#[test]
+fn filtered_messages_are_finished() {
+    rt::block_on(async {
+        let (source, destination, messages, bridge) = setup().await;
+        let bridge = bridge.filter(|body| match body {
+            b"skip" => None,
+            b"empty" => Some(Vec::new()),
+            _ => Some(body.to_ascii_uppercase()),
+        });
+        for body in [&b"skip"[..], b"empty", b"a"] {
+            source.put("test", body.to_vec());
+        }
+
+        run_until(bridge, messages, eventually(|| count(&source, "FIN") == 3)).await;
+        assert_eq!(count(&source, "REQ"), 0);
+        assert_eq!(destination.published("test"), vec![b"A".to_vec()]);
+    });
+}
+
+#[test]
+fn rejected_batches_are_published_one_by_one() {
+    rt::block_on(async {
+        let (source, destination, messages, bridge) = setup().await;
+        let bridge = bridge.batch(3, Duration::from_millis(100));
+        destination.inject_error("MPUB", "E_BAD_MESSAGE MPUB message too big 5 > 4");
+        destination.inject_error("PUB", "E_BAD_MESSAGE PUB message too big 5 > 4");
+        for body in [&b"a"[..], b"b", b"c"] {
+            source.put("test", body.to_vec());
+        }
+
+        run_until(bridge, messages, eventually(|| count(&source, "FIN") == 3)).await;
+        // only the message rejected on its own is requeued, then published alone
+        assert_eq!(count(&source, "REQ"), 1);
+        assert_eq!(count(&destination, "MPUB"), 1);
+        assert_eq!(destination.published("test"), vec![b"b".to_vec(), b"c".to_vec(), b"a".to_vec()]);
+    });
+}
@@ -0,0 +1,64 @@
+// MIT License
+//
+// Copyright (c) 2019-2021 Alessandro Cresto Miseroglio <alex179ohm@gmail.com>
+// Copyright (c) 2019-2021 Tangram Technologies S.R.L. <https://tngrm.io>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Helpers shared by the tests running the client against MockNsqd.
+
+use nsq_rust::events::ConnectionEvent;
+use nsq_rust::testing::MockNsqd;
+use nsq_rust::{rt, Channel, Topic};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub fn topic() -> Topic {
+    Topic::new("test").unwrap()
+}
+
+pub fn channel() -> Channel {
+    Channel::new("ch").unwrap()
+}
+
+/// Names of the commands received by `nsqd`, in order.
+pub fn names(nsqd: &MockNsqd) -> Vec<String> {
+    nsqd.commands().into_iter().map(|c| c.name).collect()
+}
+
+/// Number of `name` commands received by `nsqd`.
+pub fn count(nsqd: &MockNsqd, name: &str) -> usize {
+    nsqd.commands().iter().filter(|c| c.name == name).count()
+}
+
+/// Wait up to 2 seconds for `condition`.
+pub async fn eventually<F: FnMut() -> bool>(mut condition: F) {
+    let start = Instant::now();
+    while !condition() {
+        assert!(start.elapsed() < Duration::from_secs(2), "condition not met in time");
+        rt::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+/// Events recorded by the callback returned along, for `Client::on_event`.
+pub fn recorder() -> (Arc<Mutex<Vec<ConnectionEvent>>>, impl Fn(&ConnectionEvent) + Send + Sync + 'static) {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let recorded = events.clone();
+    (events, move |event: &ConnectionEvent| recorded.lock().unwrap().push(event.clone()))
+}
@@ -0,0 +1,96 @@
+// MIT License
+//
+// Copyright (c) 2019-2021 Alessandro Cresto Miseroglio <alex179ohm@gmail.com>
+// Copyright (c) 2019-2021 Tangram Technologies S.R.L. <https://tngrm.io>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Connection events emitted while talking to MockNsqd.
+
+#[allow(dead_code)]
+mod common;
+
+use common::{channel, eventually, recorder, topic};
+use nsq_rust::events::ConnectionEvent;
+use nsq_rust::retry::ExponentialBackoff;
+use nsq_rust::testing::MockNsqd;
+use nsq_rust::{rt, Client, Config, NsqError};
+use std::time::Duration;
+
+#[test]
+fn error_frames_are_reported() {
+    rt::block_on(async {
+        let nsqd = MockNsqd::start().await.unwrap();
+        nsqd.inject_error("PUB", "E_PUB_FAILED topic is full");
+        let (events, callback) = recorder();
+        let client = Client::new(nsqd.addr(), Config::new(), None, None).on_event(callback);
+        let mut producer = client.producer().await.unwrap();
+
+        let res = producer.publish(&topic(), b"lost".to_vec()).await;
+        assert!(matches!(res, Err(NsqError::Pub)), "{:?}", res);
+        assert!(events.lock().unwrap().iter().any(|event| matches!(
+            event,
+            ConnectionEvent::ErrorFrame { error, .. } if error.starts_with("E_PUB_FAILED")
+        )));
+        assert_eq!(nsqd.published("test"), Vec::<Vec<u8>>::new());
+    });
+}
+
+#[test]
+fn heartbeats_are_reported() {
+    rt::block_on(async {
+        let nsqd = MockNsqd::builder().heartbeat_interval(Duration::from_millis(50)).start().await.unwrap();
+        let (events, callback) = recorder();
+        let client = Client::new(nsqd.addr(), Config::new(), None, None).on_event(callback);
+        let _stream = client.subscribe(topic(), channel()).await.unwrap();
+
+        eventually(|| {
+            let events = events.lock().unwrap();
+            events.iter().filter(|event| matches!(event, ConnectionEvent::Heartbeat { .. })).count() >= 2
+        })
+        .await;
+    });
+}
+
+#[test]
+fn reconnections_are_reported() {
+    rt::block_on(async {
+        let nsqd = MockNsqd::start().await.unwrap();
+        let (events, callback) = recorder();
+        let policy = ExponentialBackoff::new(Duration::from_millis(10), Duration::from_millis(50));
+        let client = Client::new(nsqd.addr(), Config::new(), None, None)
+            .retry_policy(policy)
+            .on_event(callback);
+        let _stream = client.subscribe(topic(), channel()).await.unwrap();
+
+        nsqd.disconnect_all();
+        eventually(|| {
+            let events = events.lock().unwrap();
+            events.iter().any(|event| matches!(event, ConnectionEvent::Reconnected { .. }))
+        })
+        .await;
+
+        let events = events.lock().unwrap();
+        let position = |f: fn(&ConnectionEvent) -> bool| events.iter().position(f).unwrap();
+        let disconnected = position(|e| matches!(e, ConnectionEvent::Disconnected { .. }));
+        let reconnecting = position(|e| matches!(e, ConnectionEvent::Reconnecting { attempt: 1, .. }));
+        let reconnected = position(|e| matches!(e, ConnectionEvent::Reconnected { .. }));
+        assert!(disconnected < reconnecting && reconnecting < reconnected, "{:?}", *events);
+    });
+}
@@ -0,0 +1,137 @@
+// MIT License
+//
+// Copyright (c) 2019-2021 Alessandro Cresto Miseroglio <alex179ohm@gmail.com>
+// Copyright (c) 2019-2021 Tangram Technologies S.R.L. <https://tngrm.io>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! IDENTIFY and AUTH against MockNsqd.
+
+#[allow(dead_code)]
+mod common;
+
+use common::{channel, names, recorder, topic};
+use nsq_rust::events::ConnectionEvent;
+use nsq_rust::testing::MockNsqd;
+use nsq_rust::{rt, Client, Config, NsqConfig, NsqError, Response};
+
+#[test]
+fn identify_then_publish() {
+    rt::block_on(async {
+        let nsqd = MockNsqd::start().await.unwrap();
+        let (events, on_event) = recorder();
+        let client = Client::new(nsqd.addr(), Config::new(), None, None).on_event(on_event);
+        let mut producer = client.producer().await.unwrap();
+        assert!(matches!(producer.publish(&topic(), b"hello".to_vec()).await, Ok(Response::Ok)));
+
+        assert_eq!(names(&nsqd), vec!["IDENTIFY", "PUB"]);
+        let identify: serde_json::Value = serde_json::from_slice(nsqd.commands()[0].body.as_ref().unwrap()).unwrap();
+        assert_eq!(identify["feature_negotiation"], true);
+        let events = events.lock().unwrap();
+        assert!(matches!(events[0], ConnectionEvent::Connected { .. }));
+        assert!(matches!(events[1], ConnectionEvent::Identified { .. }));
+    });
+}
+
+#[test]
+fn auth_sends_the_secret() {
+    rt::block_on(async {
+        let nsqd = MockNsqd::builder().auth_required("bob").start().await.unwrap();
+        let (events, on_event) = recorder();
+        let client = Client::new(nsqd.addr(), Config::new(), Some("secret".to_owned()), None).on_event(on_event);
+        let mut producer = client.producer().await.unwrap();
+        producer.publish(&topic(), b"hello".to_vec()).await.unwrap();
+
+        let commands = nsqd.commands();
+        assert_eq!(names(&nsqd), vec!["IDENTIFY", "AUTH", "PUB"]);
+        assert_eq!(commands[1].body.as_deref(), Some(&b"secret"[..]));
+        let identity = events.lock().unwrap().iter().find_map(|event| match event {
+            ConnectionEvent::Authenticated { auth, .. } => Some(auth.identity().to_owned()),
+            _ => None,
+        });
+        assert_eq!(identity.as_deref(), Some("bob"));
+    });
+}
+
+#[test]
+fn auth_required_without_secret() {
+    rt::block_on(async {
+        let nsqd = MockNsqd::builder().auth_required("bob").start().await.unwrap();
+        let client = Client::new(nsqd.addr(), Config::new(), None, None);
+        let res = async { client.producer().await?.publish(&topic(), b"hello".to_vec()).await }.await;
+        assert!(res.is_err());
+        assert_eq!(nsqd.published("test"), Vec::<Vec<u8>>::new());
+    });
+}
+
+#[test]
+fn identify_answered_with_ok() {
+    rt::block_on(async {
+        let nsqd = MockNsqd::builder().negotiate("max_rdy_count", 1).start().await.unwrap();
+        let (events, on_event) = recorder();
+        // without feature negotiation nsqd answers OK and there is nothing to check
+        let config = Config::new().feature_negotiation(false).max_in_flight(10);
+        let client = Client::new(nsqd.addr(), config, None, None).on_event(on_event);
+        let mut producer = client.producer().await.unwrap();
+        producer.publish(&topic(), b"hello".to_vec()).await.unwrap();
+
+        assert_eq!(names(&nsqd), vec!["IDENTIFY", "PUB"]);
+        let events = events.lock().unwrap();
+        assert!(matches!(&events[1], ConnectionEvent::Identified { config, .. } if *config == NsqConfig::default()));
+    });
+}
+
+#[test]
+fn negotiated_limits_are_checked() {
+    rt::block_on(async {
+        let nsqd = MockNsqd::builder().negotiate("max_rdy_count", 1).start().await.unwrap();
+        let client = Client::new(nsqd.addr(), Config::new().max_in_flight(10), None, None);
+        let res = client.producer().await;
+        assert!(matches!(&res, Err(NsqError::Config(field, _)) if field == "max_in_flight"), "{:?}", res.err());
+        assert_eq!(names(&nsqd), vec!["IDENTIFY"]);
+    });
+}
+
+#[test]
+fn sample_rate_is_negotiated() {
+    rt::block_on(async {
+        // confirmed by nsqd
+        let nsqd = MockNsqd::start().await.unwrap();
+        let client = Client::new(nsqd.addr(), Config::new(), None, None).sample_rate(10);
+        let _messages = client.clone().subscribe(topic(), channel()).await.unwrap();
+
+        let identify: serde_json::Value = serde_json::from_slice(nsqd.commands()[0].body.as_ref().unwrap()).unwrap();
+        assert_eq!(identify["sample_rate"], 10);
+        let connections = client.stats().connections;
+        assert_eq!(connections.len(), 1);
+        assert_eq!(connections[0].sample_rate, 10);
+    });
+}
+
+#[test]
+fn sample_rate_not_confirmed() {
+    rt::block_on(async {
+        // ignored by nsqd
+        let nsqd = MockNsqd::builder().negotiate("sample_rate", 0).start().await.unwrap();
+        let client = Client::new(nsqd.addr(), Config::new(), None, None).sample_rate(10);
+        let res = client.subscribe(topic(), channel()).await;
+        assert!(matches!(&res, Err(NsqError::Config(field, _)) if field == "sample_rate"), "{:?}", res.err());
+        assert_eq!(names(&nsqd), vec!["IDENTIFY"]);
+    });
+}
@@ -0,0 +1,150 @@
+// MIT License
+//
+// Copyright (c) 2019-2021 Alessandro Cresto Miseroglio <alex179ohm@gmail.com>
+// Copyright (c) 2019-2021 Tangram Technologies S.R.L. <https://tngrm.io>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Publishing against MockNsqd.
+
+#[allow(dead_code)]
+mod common;
+
+use common::{channel, count, eventually, names, topic};
+use futures::future::poll_fn;
+use futures::{AsyncReadExt, AsyncWriteExt, Sink, SinkExt, StreamExt};
+use nsq_rust::testing::MockNsqd;
+use nsq_rust::{rt, Bytes, Client, Config, Mpub};
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+#[test]
+fn pub_mpub_dpub() {
+    rt::block_on(async {
+        let nsqd = MockNsqd::start().await.unwrap();
+        let client = Client::new(nsqd.addr(), Config::new(), None, None);
+        let mut producer = client.producer().await.unwrap();
+        producer.publish(&topic(), b"a".to_vec()).await.unwrap();
+        producer.mpublish(&topic(), vec![b"b".to_vec(), b"c".to_vec()]).await.unwrap();
+        producer.dpublish(&topic(), Duration::from_millis(1500), b"d".to_vec()).await.unwrap();
+
+        let commands = nsqd.commands();
+        assert_eq!(names(&nsqd), vec!["IDENTIFY", "PUB", "MPUB", "DPUB"]);
+        assert_eq!(commands[1].params, vec!["test"]);
+        assert_eq!(commands[3].params, vec!["test", "1500"]);
+        assert_eq!(nsqd.published("test"), vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec(), b"d".to_vec()]);
+    });
+}
+
+#[test]
+fn dpub_is_delivered_after_the_delay() {
+    rt::block_on(async {
+        let nsqd = MockNsqd::start().await.unwrap();
+        let client = Client::new(nsqd.addr(), Config::new(), None, None);
+        let mut stream = client.clone().subscribe(topic(), channel()).await.unwrap();
+        let mut producer = client.producer().await.unwrap();
+        let start = Instant::now();
+        producer.dpublish(&topic(), Duration::from_millis(300), b"later".to_vec()).await.unwrap();
+
+        assert!(rt::timeout(Duration::from_millis(100), stream.next()).await.is_none());
+        let msg = stream.next().await.unwrap().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(300));
+        assert_eq!(msg.body(), b"later");
+        msg.finish();
+    });
+}
+
+#[test]
+fn mpub_without_messages_is_rejected() {
+    rt::block_on(async {
+        let nsqd = MockNsqd::start().await.unwrap();
+        let mut conn = rt::connect(&nsqd.addr()).await.unwrap();
+        // written by hand, bypassing the client encoder
+        conn.write_all(b"  V2MPUB test\n\0\0\0\x04\0\0\0\0").await.unwrap();
+        let mut frame = Vec::new();
+        conn.read_to_end(&mut frame).await.unwrap();
+
+        assert_eq!(&frame[4..8], &[0, 0, 0, 1]);
+        assert_eq!(&frame[8..], b"E_BAD_BODY MPUB invalid message count 0");
+        assert!(nsqd.published("test").is_empty());
+    });
+}
+
+#[test]
+fn sink_backpressure_and_flush() {
+    rt::block_on(async {
+        let nsqd = MockNsqd::start().await.unwrap();
+        let client = Client::new(nsqd.addr(), Config::new(), None, None);
+        let mut sink = client.clone().producer().await.unwrap().max_unacknowledged(2);
+        let acknowledged = || client.stats().publish_latency.count;
+
+        for body in &["a", "b"] {
+            poll_fn(|cx| Pin::new(&mut sink).poll_ready(cx)).await.unwrap();
+            Pin::new(&mut sink).start_send((topic(), Bytes::from(body.as_bytes()))).unwrap();
+        }
+        assert_eq!(acknowledged(), 0);
+        // two messages waiting for nsqd: the next one is accepted once the first is acknowledged
+        poll_fn(|cx| Pin::new(&mut sink).poll_ready(cx)).await.unwrap();
+        assert_eq!(acknowledged(), 1);
+        Pin::new(&mut sink).start_send((topic(), Bytes::from(&b"c"[..]))).unwrap();
+
+        sink.flush().await.unwrap();
+        assert_eq!(acknowledged(), 3);
+        assert_eq!(nsqd.published("test"), vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]);
+    });
+}
+
+#[test]
+fn batches_are_pipelined() {
+    rt::block_on(async {
+        let nsqd = MockNsqd::start().await.unwrap();
+        let client = Client::new(nsqd.addr(), Config::new(), None, None);
+        let mut producer = client.clone().producer().await.unwrap().max_unacknowledged(2);
+        let mut batches = producer.batches();
+        let mpub = |bodies: &[&[u8]]| Mpub::new(topic(), bodies.iter().map(|b| b.to_vec()).collect()).unwrap();
+
+        batches.feed(mpub(&[b"a", b"b"])).await.unwrap();
+        batches.feed(mpub(&[b"c"])).await.unwrap();
+        assert_eq!(client.stats().publish_latency.count, 0);
+        batches.feed(mpub(&[b"d", b"e"])).await.unwrap();
+        assert_eq!(client.stats().publish_latency.count, 1);
+
+        batches.flush().await.unwrap();
+        assert_eq!(client.stats().publish_latency.count, 3);
+        assert_eq!(names(&nsqd)[1..], ["MPUB", "MPUB", "MPUB"]);
+        let published: Vec<_> = [&b"a"[..], b"b", b"c", b"d", b"e"].iter().map(|b| b.to_vec()).collect();
+        assert_eq!(nsqd.published("test"), published);
+    });
+}
+
+#[test]
+fn heartbeats_do_not_flush() {
+    rt::block_on(async {
+        let nsqd = MockNsqd::builder().heartbeat_interval(Duration::from_millis(20)).start().await.unwrap();
+        let mut sink = Client::new(nsqd.addr(), Config::new(), None, None).producer().await.unwrap();
+        poll_fn(|cx| Pin::new(&mut sink).poll_ready(cx)).await.unwrap();
+        Pin::new(&mut sink).start_send((topic(), Bytes::from(&b"a"[..]))).unwrap();
+
+        eventually(|| count(&nsqd, "NOP") >= 2).await;
+        // the PUB waits for the flush
+        assert_eq!(count(&nsqd, "PUB"), 0);
+        sink.flush().await.unwrap();
+        assert_eq!(nsqd.published("test"), vec![b"a".to_vec()]);
+    });
+}
@@ -0,0 +1,113 @@
+// MIT License
+//
+// Copyright (c) 2019-2021 Alessandro Cresto Miseroglio <alex179ohm@gmail.com>
+// Copyright (c) 2019-2021 Tangram Technologies S.R.L. <https://tngrm.io>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Reconnections against MockNsqd.
+
+#[allow(dead_code)]
+mod common;
+
+use common::{channel, count, eventually, recorder, topic};
+use futures::{SinkExt, StreamExt};
+use nsq_rust::events::ConnectionEvent;
+use nsq_rust::retry::ExponentialBackoff;
+use nsq_rust::testing::MockNsqd;
+use nsq_rust::{rt, Bytes, Client, Config};
+use std::time::Duration;
+
+fn policy() -> ExponentialBackoff {
+    ExponentialBackoff::new(Duration::from_millis(10), Duration::from_millis(50))
+}
+
+#[test]
+fn unacknowledged_publishes_are_replayed() {
+    rt::block_on(async {
+        let nsqd = MockNsqd::start().await.unwrap();
+        let client = Client::new(nsqd.addr(), Config::new(), None, None).retry_policy(policy());
+        let mut producer = client.clone().producer().await.unwrap();
+        producer.publish(&topic(), b"a".to_vec()).await.unwrap();
+
+        producer.feed((topic(), Bytes::from(&b"b"[..]))).await.unwrap();
+        producer.feed((topic(), Bytes::from(&b"c"[..]))).await.unwrap();
+        nsqd.disconnect_all();
+        producer.flush().await.unwrap();
+
+        // nsqd may have received them before closing the connection, their responses were lost
+        // and they are published again: delivery is at least once
+        let published = nsqd.published("test");
+        let sent = [b"a".to_vec(), b"b".to_vec(), b"c".to_vec()];
+        assert!(published.starts_with(&sent[..2]) && published.ends_with(&sent[2..]), "{:?}", published);
+        assert!(published[1..].iter().all(|body| sent[1..].contains(body)), "{:?}", published);
+        assert!(published.len() <= 5, "{:?}", published);
+        assert_eq!(count(&nsqd, "IDENTIFY"), 2);
+        assert_eq!(client.stats().reconnects, 1);
+        producer.publish(&topic(), b"d".to_vec()).await.unwrap();
+        assert_eq!(nsqd.published("test").last(), Some(&b"d".to_vec()));
+    });
+}
+
+#[test]
+fn subscription_resumes() {
+    rt::block_on(async {
+        let nsqd = MockNsqd::start().await.unwrap();
+        let client = Client::new(nsqd.addr(), Config::new().max_in_flight(3), None, None).retry_policy(policy());
+        let mut stream = client.subscribe(topic(), channel()).await.unwrap();
+        nsqd.put("test", b"before".to_vec());
+        stream.next().await.unwrap().unwrap().finish();
+        eventually(|| count(&nsqd, "FIN") == 1).await;
+
+        nsqd.disconnect_all();
+        eventually(|| count(&nsqd, "SUB") == 2).await;
+        nsqd.put("test", b"after".to_vec());
+        let msg = stream.next().await.unwrap().unwrap();
+        assert_eq!(msg.body(), b"after");
+        msg.finish();
+        eventually(|| count(&nsqd, "FIN") == 2).await;
+
+        let commands = nsqd.commands();
+        let resubscribed = commands.iter().filter(|c| c.name == "SUB").nth(1).map(|c| c.params.clone());
+        assert_eq!(resubscribed, Some(vec!["test".to_owned(), "ch".to_owned()]));
+        let rdy = commands.iter().skip_while(|c| c.name != "SUB").skip(1).skip_while(|c| c.name != "SUB");
+        assert!(rdy.filter(|c| c.name == "RDY").any(|c| c.params == vec!["3"]), "{:?}", commands);
+    });
+}
+
+#[test]
+fn max_attempts_gives_up() {
+    rt::block_on(async {
+        let nsqd = MockNsqd::start().await.unwrap();
+        let (events, on_event) = recorder();
+        let client = Client::new(nsqd.addr(), Config::new(), None, None)
+            .retry_policy(policy().max_attempts(2))
+            .on_event(on_event);
+        let mut stream = client.subscribe(topic(), channel()).await.unwrap();
+        // stops accepting connections and closes the current one
+        drop(nsqd);
+
+        assert!(stream.next().await.unwrap().is_err());
+        assert!(stream.next().await.is_none());
+        let events = events.lock().unwrap();
+        let attempts = events.iter().filter(|e| matches!(e, ConnectionEvent::Reconnecting { .. })).count();
+        assert_eq!(attempts, 2);
+        assert!(matches!(events.last(), Some(ConnectionEvent::GaveUp { attempts: 2, .. })), "{:?}", *events);
+    });
+}
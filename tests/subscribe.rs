@@ -0,0 +1,110 @@
+// MIT License
+//
+// Copyright (c) 2019-2021 Alessandro Cresto Miseroglio <alex179ohm@gmail.com>
+// Copyright (c) 2019-2021 Tangram Technologies S.R.L. <https://tngrm.io>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Subscriptions against MockNsqd.
+
+#[allow(dead_code)]
+mod common;
+
+use common::{channel, count, eventually, names, topic};
+use futures::StreamExt;
+use nsq_rust::testing::MockNsqd;
+use nsq_rust::{rt, Client, Config};
+use std::time::Duration;
+
+#[test]
+fn sub_rdy_fin() {
+    rt::block_on(async {
+        let nsqd = MockNsqd::start().await.unwrap();
+        for body in &["1", "2", "3"] {
+            nsqd.put("test", body.as_bytes().to_vec());
+        }
+        let client = Client::new(nsqd.addr(), Config::new().max_in_flight(2), None, None);
+        let mut stream = client.subscribe(topic(), channel()).await.unwrap();
+        let mut ids = Vec::new();
+        for expected in &["1", "2", "3"] {
+            let msg = stream.next().await.unwrap().unwrap();
+            assert_eq!(msg.body(), expected.as_bytes());
+            assert_eq!(msg.attempts(), 1);
+            ids.push(msg.id().to_owned());
+            msg.finish();
+        }
+        eventually(|| count(&nsqd, "FIN") == 3).await;
+
+        let commands = nsqd.commands();
+        let sub = commands.iter().find(|c| c.name == "SUB").unwrap();
+        assert_eq!(sub.params, vec!["test", "ch"]);
+        let rdy = commands.iter().find(|c| c.name == "RDY").unwrap();
+        assert_eq!(rdy.params, vec!["2"]);
+        let fins: Vec<&String> = commands.iter().filter(|c| c.name == "FIN").map(|c| &c.params[0]).collect();
+        assert_eq!(fins, ids.iter().collect::<Vec<_>>());
+        assert_eq!(nsqd.in_flight(), 0);
+    });
+}
+
+#[test]
+fn touch_and_req() {
+    rt::block_on(async {
+        let nsqd = MockNsqd::start().await.unwrap();
+        nsqd.put("test", b"again".to_vec());
+        let client = Client::new(nsqd.addr(), Config::new(), None, None);
+        let mut stream = client.subscribe(topic(), channel()).await.unwrap();
+
+        let msg = stream.next().await.unwrap().unwrap();
+        let id = msg.id().to_owned();
+        msg.touch();
+        msg.requeue(Duration::from_millis(0));
+        let msg = stream.next().await.unwrap().unwrap();
+        assert_eq!(msg.id(), id);
+        assert_eq!(msg.attempts(), 2);
+        msg.finish();
+        eventually(|| names(&nsqd).contains(&"FIN".to_owned())).await;
+
+        let commands = nsqd.commands();
+        let touch = commands.iter().find(|c| c.name == "TOUCH").unwrap();
+        assert_eq!(touch.params, vec![id.clone()]);
+        let req = commands.iter().find(|c| c.name == "REQ").unwrap();
+        assert_eq!(req.params, vec![id, "0".to_owned()]);
+    });
+}
+
+#[test]
+fn injected_fin_error_keeps_the_subscription() {
+    rt::block_on(async {
+        let nsqd = MockNsqd::start().await.unwrap();
+        nsqd.put("test", b"1".to_vec());
+        nsqd.put("test", b"2".to_vec());
+        nsqd.inject_error("FIN", "E_FIN_FAILED FIN 0 failed");
+        // the failed FIN leaves the first message in flight
+        let client = Client::new(nsqd.addr(), Config::new().max_in_flight(2), None, None);
+        let mut stream = client.subscribe(topic(), channel()).await.unwrap();
+
+        stream.next().await.unwrap().unwrap().finish();
+        let msg = stream.next().await.unwrap().unwrap();
+        assert_eq!(msg.body(), b"2");
+        msg.finish();
+        eventually(|| count(&nsqd, "FIN") == 2).await;
+        assert_eq!(nsqd.connections(), 1);
+        assert_eq!(stream.stats().messages_timed_out, 1);
+    });
+}
@@ -0,0 +1,99 @@
+// MIT License
+//
+// Copyright (c) 2019-2021 Alessandro Cresto Miseroglio <alex179ohm@gmail.com>
+// Copyright (c) 2019-2021 Tangram Technologies S.R.L. <https://tngrm.io>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Typed publishing and consuming against MockNsqd.
+
+#[allow(dead_code)]
+mod common;
+
+use common::{channel, eventually, names, topic};
+use nsq_rust::testing::MockNsqd;
+use nsq_rust::{rt, Action, Client, Config, DecodePolicy, Msg, Topic, Typed, TypedHandler, TypedProducer};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Event {
+    id: u64,
+}
+
+/// Requeues the first delivery of every message, finishes the next ones.
+struct RequeueOnce(Arc<Mutex<Vec<(u64, u16)>>>);
+
+impl TypedHandler<Event> for RequeueOnce {
+    fn handle(&mut self, value: Event, msg: &Msg) -> impl Future<Output = Action> + Send {
+        self.0.lock().unwrap().push((value.id, msg.attempts()));
+        let action = if msg.attempts() == 1 {
+            Action::Requeue(Duration::from_millis(0))
+        } else {
+            Action::Finish
+        };
+        async move { action }
+    }
+}
+
+#[test]
+fn typed_publish_then_requeue_and_finish() {
+    rt::block_on(async {
+        let nsqd = MockNsqd::start().await.unwrap();
+        let client = Client::new(nsqd.addr(), Config::new(), None, None);
+        let mut producer: TypedProducer<Event> = TypedProducer::new(client.clone(), topic());
+        producer.publish(&Event { id: 7 }).await.unwrap();
+        assert_eq!(nsqd.published("test"), vec![br#"{"id":7}"#.to_vec()]);
+
+        let handled = Arc::new(Mutex::new(Vec::new()));
+        let handler = Typed::<Event, _>::new(RequeueOnce(handled.clone()));
+        rt::spawn(async move {
+            let _ = client.consumer(topic(), channel(), handler).await;
+        });
+        eventually(|| names(&nsqd).contains(&"FIN".to_owned())).await;
+
+        assert_eq!(*handled.lock().unwrap(), vec![(7, 1), (7, 2)]);
+        let consumed = names(&nsqd).into_iter().skip_while(|name| name != "SUB").collect::<Vec<_>>();
+        assert_eq!(consumed, vec!["SUB", "RDY", "REQ", "FIN"]);
+        assert_eq!(nsqd.in_flight(), 0);
+    });
+}
+
+#[test]
+fn undecodable_bodies_are_dead_lettered() {
+    rt::block_on(async {
+        let nsqd = MockNsqd::start().await.unwrap();
+        nsqd.put("test", b"not json".to_vec());
+        let client = Client::new(nsqd.addr(), Config::new(), None, None);
+        let handled = Arc::new(Mutex::new(Vec::new()));
+        let dead = Topic::new("dead").unwrap();
+        let handler = Typed::<Event, _>::new(RequeueOnce(handled.clone()))
+            .policy(DecodePolicy::DeadLetter(client.clone(), dead));
+        rt::spawn(async move {
+            let _ = client.consumer(topic(), channel(), handler).await;
+        });
+        eventually(|| names(&nsqd).contains(&"FIN".to_owned())).await;
+
+        assert_eq!(nsqd.published("dead"), vec![b"not json".to_vec()]);
+        assert!(handled.lock().unwrap().is_empty());
+        assert_eq!(nsqd.in_flight(), 0);
+    });
+}